@@ -1,5 +1,6 @@
 #![allow(clippy::useless_vec)]
 use std::sync::{Arc, Mutex, Weak};
+use std::time::{Instant, SystemTime};
 
 use self::ban_list::{Ban, BanTimeout};
 use self::{defs::Preset, error::Bf4Result, player_cache::PlayerEaidCache};
@@ -18,6 +19,7 @@ use tokio_stream::{wrappers::BroadcastStream, StreamExt};
 
 pub mod defs;
 pub mod ea_guid;
+pub mod envelope;
 pub mod error;
 pub mod map_list;
 pub(crate) mod player_cache;
@@ -29,6 +31,7 @@ pub mod ban_list;
 
 pub use defs::{Event, GameMode, Map, Player, Squad, Team, Visibility, CommmoRose, Weapon};
 pub use ea_guid::Eaid;
+pub use envelope::{Envelope, ServerId};
 
 // cmd_err!(pub PlayerKickError, PlayerNotFound, A);
 cmd_err!(pub PlayerKillError, InvalidPlayerName, SoldierNotAlive);
@@ -40,6 +43,7 @@ cmd_err!(pub MapListError, MapListFull, InvalidGameMode, InvalidMapIndex, Invali
 cmd_err!(pub ReservedSlotsError, PlayerAlreadyInList, ReservedSlotsFull, PlayerNotInList);
 cmd_err!(pub GameAdminError, Full, AlreadyInList);
 cmd_err!(pub PlayerKickError, PlayerNotFound);
+cmd_err!(pub PlayerMoveError, InvalidPlayerName, PlayerNotDead, SetTeamFailed, SetSquadFailed);
 cmd_err!(pub BanListError, BanListFull, NotFound);
 
 pub(crate) trait RconDecoding: Sized {
@@ -57,7 +61,10 @@ pub struct Bf4Client {
     rcon: RconClient,
     /// You can `.subscribe()` to this and you'll received Events.
     /// The mutex is here only as a thread-safe `Cell<T>`.
-    events: Mutex<Option<broadcast::Sender<Bf4Result<Event>>>>,
+    events: Mutex<Option<broadcast::Sender<Bf4Result<Envelope>>>>,
+
+    /// Stamped onto every event envelope.
+    server_id: ServerId,
 
     /// Needs to be in Bf4Client and behind a cache, since we'll be accessing this from two places:
     /// - When parsing packets, e.g. from events.
@@ -96,10 +103,12 @@ impl Bf4Client {
     pub async fn new_from(mut rcon: RconClient, harmless: bool) -> RconResult<Arc<Self>> {
         let (tx, rx) = oneshot::channel::<Weak<Bf4Client>>();
 
-        let events = Bf4Client::packet_to_event_stream(rx, rcon.take_nonresponse_rx().expect("Bf4Client requires Rcon's `take_nonresponse_tx()` to succeed. If you are calling this yourself, then please don't."));
+        let server_id = ServerId::from(rcon.peer_addr());
+        let events = Bf4Client::packet_to_event_stream(rx, rcon.take_nonresponse_rx().expect("Bf4Client requires Rcon's `take_nonresponse_tx()` to succeed. If you are calling this yourself, then please don't."), server_id.clone());
         let myself = Arc::new(Self {
             rcon,
             events: Mutex::new(Some(events)),
+            server_id,
             player_cache: Mutex::new(PlayerEaidCache::new()),
             // interval_timers: Vec::new(),
             harmless,
@@ -167,6 +176,11 @@ impl Bf4Client {
         }
     }
 
+    /// The server this client is connected to, as stamped onto each event `Envelope`.
+    pub fn server_id(&self) -> &ServerId {
+        &self.server_id
+    }
+
     // Set a player's GUID. For example on join this is used.
     pub fn player_has_guid(&self, name: &AsciiString, eaid: &Eaid) {
         let mut lock = self
@@ -212,7 +226,7 @@ impl Bf4Client {
                     victim: bf4.resolve_player(&packet.words[2]).await?,
                     weapon: Weapon::rcon_decode(&packet.words[3])?,
                     // weapon: Weapon::Other(packet.words[3].clone()),
-                    headshot: false,
+                    headshot: packet.words[4] == "true",
                 })
             }
             "player.onSpawn" => {
@@ -399,19 +413,26 @@ impl Bf4Client {
         }
     }
 
-    /// Takes packets, transforms into events, broadcasts.
+    /// Takes packets, transforms into events, stamps them into an `Envelope`, broadcasts.
     fn packet_to_event_stream(
         bf4client_rx: oneshot::Receiver<Weak<Bf4Client>>,
         mut packets: mpsc::UnboundedReceiver<RconResult<Packet>>,
-    ) -> broadcast::Sender<Bf4Result<Event>> {
-        let (tx, _) = broadcast::channel::<Bf4Result<Event>>(1024);
+        server_id: ServerId,
+    ) -> broadcast::Sender<Bf4Result<Envelope>> {
+        let (tx, _) = broadcast::channel::<Bf4Result<Envelope>>(1024);
         let tx2 = tx.clone();
         tokio::spawn(async move {
             // Just for initialization: bf4client constructor sends us an instance of itself.
             // Has to be like this since we can't pass an instance via parameter here.
             let bf4client: Weak<Bf4Client> = bf4client_rx.await.unwrap();
+            let mut seq = 0_u64;
             while let Some(packet) = packets.recv().await {
                 // println!("[Bf4Clinet::packet_to_event_stream] Received {:?}", packet);
+                // Stamp the time *before* parsing, since parsing may need to query rcon.
+                let received = Instant::now();
+                let received_at = SystemTime::now();
+                let this_seq = seq;
+                seq += 1;
                 match packet {
                     Ok(packet) => {
                         if packet.words.is_empty() {
@@ -421,8 +442,15 @@ impl Bf4Client {
                             .into())); // All events must have at least one word.
                             continue; // should probably be a break, but yeah whatever.
                         }
-                        let event = Bf4Client::parse_packet(&bf4client, packet).await;
-                        let _ = tx2.send(event); // actually broadcast packet to all event streams.
+                        let envelope = Bf4Client::parse_packet(&bf4client, packet).await
+                            .map(|event| Envelope {
+                                seq: this_seq,
+                                received,
+                                received_at,
+                                server: server_id.clone(),
+                                event,
+                            });
+                        let _ = tx2.send(envelope); // actually broadcast packet to all event streams.
                     }
                     Err(e) => {
                         // the packet receiver loop (tcp,...) encountered an error. This will most of the time
@@ -458,7 +486,7 @@ impl Bf4Client {
 
     /// Errors:
     /// When the underlying events stream has already ended, returns `Err(())`
-    async fn event_stream_raw(&self) -> RconResult<BroadcastStream<Bf4Result<Event>>> {
+    async fn event_stream_raw(&self) -> RconResult<BroadcastStream<Bf4Result<Envelope>>> {
         self.rcon.events_enabled(true).await?;
 
         let lock = self
@@ -479,7 +507,16 @@ impl Bf4Client {
     /// which only occur when the backlog of unhandled events gets too big (1024 currently).
     /// In that case, those events are simply ignored and only a warning is emitted.
     /// If you want to handle the overflow error yourself, use `event_stream_raw`.
+    ///
+    /// If you also need to know when or in which order the events arrived, use
+    /// `envelope_stream` instead.
     pub async fn event_stream(&self) -> RconResult<impl Stream<Item = Bf4Result<Event>>> {
+        Ok(self.envelope_stream().await?.map(|ev| ev.map(Envelope::into_event)))
+    }
+
+    /// Same as `event_stream`, but each event comes wrapped in an `Envelope`, with a
+    /// sequence number, receive timestamps and the originating server.
+    pub async fn envelope_stream(&self) -> RconResult<impl Stream<Item = Bf4Result<Envelope>>> {
         Ok(self.event_stream_raw().await?.filter_map(|ev| {
             match ev {
                 Ok(x) => Some(x),
@@ -566,6 +603,39 @@ impl Bf4Client {
             .await
     }
 
+    /// Moves a player to another team and squad.
+    ///
+    /// Unless `force_kill` is set, this only works when the player is dead, otherwise you get
+    /// `PlayerNotDead`.
+    pub async fn move_player(
+        &self,
+        player: impl IntoAsciiString + Into<String>,
+        team: Team,
+        squad: Squad,
+        force_kill: bool,
+    ) -> Result<(), PlayerMoveError> {
+        if self.harmless {
+            info!("harmless MOVE {} to {:?} {:?} (force kill: {})", player.into(), team, squad, force_kill);
+            return Ok(());
+        }
+
+        let player = player.into_ascii_string()?;
+        let force_kill = if force_kill { "true" } else { "false" };
+        self.rcon
+            .query(
+                &veca!["admin.movePlayer", player, team.rcon_encode(), squad.rcon_encode(), force_kill],
+                ok_eof,
+                |err| match err {
+                    "InvalidPlayerName" => Some(PlayerMoveError::InvalidPlayerName),
+                    "PlayerNotDead" => Some(PlayerMoveError::PlayerNotDead),
+                    "SetTeamFailed" => Some(PlayerMoveError::SetTeamFailed),
+                    "SetSquadFailed" => Some(PlayerMoveError::SetSquadFailed),
+                    _ => None,
+                },
+            )
+            .await
+    }
+
     /// Adds a ban to the ban list.
     ///
     /// # RCON Errors
@@ -1001,6 +1071,39 @@ impl Bf4Client {
             .await
     }
 
+    /// Round time limit in percent of the default, `vars.roundTimeLimit`.
+    pub async fn set_round_time_limit(&self, percent: usize) -> RconResult<()> {
+        if self.harmless {
+            info!("harmless SET_ROUND_TIME_LIMIT {percent}");
+            return Ok(());
+        }
+
+        self.rcon
+            .query(
+                &veca!["vars.roundTimeLimit", format!("{}", percent)],
+                ok_eof,
+                |_| None,
+            )
+            .await
+    }
+
+    /// Seconds after which idle players are kicked by the server itself, `vars.idleTimeout`.
+    /// `0` disables it.
+    pub async fn set_idle_timeout(&self, seconds: usize) -> RconResult<()> {
+        if self.harmless {
+            info!("harmless SET_IDLE_TIMEOUT {seconds}");
+            return Ok(());
+        }
+
+        self.rcon
+            .query(
+                &veca!["vars.idleTimeout", format!("{}", seconds)],
+                ok_eof,
+                |_| None,
+            )
+            .await
+    }
+
     /// add player name to reserved slots list.
     pub async fn reserved_add(&self, player: &Player) -> Result<(), ReservedSlotsError> {
         if self.harmless {
@@ -1021,6 +1124,25 @@ impl Bf4Client {
             .await
     }
 
+    /// remove player name from reserved slots list.
+    pub async fn reserved_remove(&self, player: impl AsRef<str>) -> Result<(), ReservedSlotsError> {
+        if self.harmless {
+            info!("harmless RESERVED_SLOT_REMOVE {}", player.as_ref());
+            return Ok(());
+        }
+
+        self.rcon
+            .query(
+                &veca!["reservedSlotsList.remove", player.as_ref()],
+                ok_eof,
+                |err| match err {
+                    "PlayerNotInList" => Some(ReservedSlotsError::PlayerNotInList),
+                    _ => None,
+                },
+            )
+            .await
+    }
+
     /// Saves the reserved slots to file. (can fail, remote rcon io error, no error code yet.)
     pub async fn reserved_save(&self) -> Result<(), ReservedSlotsError> {
         if self.harmless {
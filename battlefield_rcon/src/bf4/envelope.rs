@@ -0,0 +1,57 @@
+//! Metadata which travels alongside each `Event`: When it was received, in which order, and
+//! from which server.
+
+use std::{
+    fmt::Display,
+    net::SocketAddr,
+    time::{Instant, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::Event;
+
+/// Identifies the server an event originated from.
+///
+/// Currently this is just the `ip:port` of the RCON connection.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct ServerId(pub String);
+
+impl From<SocketAddr> for ServerId {
+    fn from(addr: SocketAddr) -> Self {
+        Self(addr.to_string())
+    }
+}
+
+impl Display for ServerId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// An `Event`, together with when and where it was received.
+///
+/// The timestamps are taken as soon as the packet arrives, *before* the packet is parsed (which
+/// may involve further RCON queries) and before it is handed out to any event stream. So they
+/// are unaffected by whatever reordering happens later on, e.g. due to `tokio::spawn`.
+#[derive(Debug, Clone)]
+pub struct Envelope {
+    /// Monotonically increasing, starting at 0 for the first packet received on this connection.
+    ///
+    /// Every received packet gets a number, including ones that failed to parse, so a gap in
+    /// the sequence means that some packets didn't make it as events.
+    pub seq: u64,
+    /// Monotonic receive time, use this for measuring durations between events.
+    pub received: Instant,
+    /// Wall-clock receive time, use this for logging and reports.
+    pub received_at: SystemTime,
+    pub server: ServerId,
+    pub event: Event,
+}
+
+impl Envelope {
+    /// Throws away the metadata.
+    pub fn into_event(self) -> Event {
+        self.event
+    }
+}
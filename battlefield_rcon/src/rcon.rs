@@ -1,5 +1,5 @@
 use core::panic;
-use std::{collections::HashMap, convert::TryInto, io::ErrorKind, num::ParseIntError, sync::Arc};
+use std::{collections::HashMap, convert::TryInto, io::ErrorKind, net::SocketAddr, num::ParseIntError, sync::Arc};
 
 // use crate::error::{Error, Result};
 use ascii::{AsciiString, FromAsciiError, IntoAsciiString};
@@ -147,6 +147,9 @@ pub struct RconClient {
 
     nonresponse_rx: Option<mpsc::UnboundedReceiver<RconResult<Packet>>>,
 
+    /// The address of the server we're connected to.
+    peer_addr: SocketAddr,
+
     // / ip, port, password.
     // _connection_info: RconConnectionInfo,
 }
@@ -178,6 +181,7 @@ impl RconClient {
     #[allow(clippy::useless_vec)]
     pub async fn connect(addr: impl ToSocketAddrs) -> RconResult<Self> {
         let tcp = TcpStream::connect(addr).await?;
+        let peer_addr = tcp.peer_addr()?;
 
         let (query_tx, query_rx) = mpsc::unbounded_channel::<SendQuery>();
         let (shutdown_tx, shutdown_rx) = mpsc::unbounded_channel::<()>();
@@ -200,6 +204,7 @@ impl RconClient {
             queries: query_tx,
             nonresponse_rx: Some(nonresponses_rx),
             mainloop_shutdown: shutdown_tx,
+            peer_addr,
         };
 
         // at this point we should have a fully functional async way to query.
@@ -256,6 +261,11 @@ impl RconClient {
         self.nonresponse_rx.take()
     }
 
+    /// The address of the server this client is connected to.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
     /// tx stuff replies:
     /// - `Ok(Some(packet))` when normal, think of it as a stream.
     /// - `Ok(None)` stream ended gracefully (e.g. when shutdown signal sent).
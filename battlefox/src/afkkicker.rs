@@ -0,0 +1,270 @@
+//! Frees up slots on a full server by kicking players who aren't playing.
+//!
+//! Spawning, killing someone, chatting, switching team or squad, and gaining score all count as
+//! activity. Whoever shows none for a while is warned, and then kicked, but only while the server
+//! is (almost) full. There is no point in kicking anyone when nobody is waiting for a slot.
+//!
+//! BF4's RCON can't move someone into a spectator slot, so kicking is all we can do.
+
+use std::{collections::{HashMap, HashSet}, sync::{atomic::{AtomicBool, Ordering}, Arc}, time::{Duration, Instant}};
+
+use async_trait::async_trait;
+use battlefield_rcon::{bf4::{Bf4Client, Event, Player, PlayerKickError, Visibility}, rcon::RconResult};
+use parking_lot::{Mutex, RwLock};
+use serde::Deserialize;
+
+use crate::roles::{Role, Roles};
+use crate::{ConfigError, Plugin};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    enabled: bool,
+    /// How often to look for idle players.
+    #[serde(with = "humantime_serde")]
+    interval: Duration,
+    /// Kick after this long without any activity.
+    #[serde(with = "humantime_serde")]
+    idle: Duration,
+    /// Warn this long before kicking.
+    #[serde(with = "humantime_serde")]
+    warn_before: Duration,
+    /// Only kick when there are at most this many free slots.
+    max_free_slots: usize,
+    /// Players with this role or higher are never kicked.
+    exempt: Role,
+}
+
+#[derive(Debug)]
+struct Activity {
+    last: Instant,
+    score: Option<i32>,
+    warned: bool,
+}
+
+impl Activity {
+    fn new(now: Instant) -> Self {
+        Self { last: now, score: None, warned: false }
+    }
+
+    fn active(&mut self, now: Instant) {
+        self.last = now;
+        self.warned = false;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Verdict {
+    Fine,
+    Warn,
+    Kick,
+}
+
+/// Nobody is kicked without a warning first, even if they were idle long enough already, e.g. when
+/// the server only just filled up.
+fn verdict(activity: &Activity, config: &Config, now: Instant) -> Verdict {
+    let idle = now.saturating_duration_since(activity.last);
+    if idle + config.warn_before < config.idle {
+        Verdict::Fine
+    } else if !activity.warned {
+        Verdict::Warn
+    } else if idle >= config.idle {
+        Verdict::Kick
+    } else {
+        Verdict::Fine
+    }
+}
+
+pub struct AfkKicker {
+    config: RwLock<Arc<Config>>,
+    roles: Arc<Roles>,
+    activity: Mutex<HashMap<Player, Activity>>,
+    between_rounds: AtomicBool,
+    paused: AtomicBool,
+}
+
+impl AfkKicker {
+    pub fn new(roles: Arc<Roles>, config: Config) -> Self {
+        Self {
+            config: RwLock::new(Arc::new(config)),
+            roles,
+            activity: Mutex::new(HashMap::new()),
+            between_rounds: AtomicBool::new(false),
+            paused: AtomicBool::new(false),
+        }
+    }
+
+    fn config(&self) -> Arc<Config> {
+        self.config.read().clone()
+    }
+
+    fn active(&self, player: Player) {
+        let now = Instant::now();
+        self.activity.lock().entry(player).or_insert_with(|| Activity::new(now)).active(now);
+    }
+
+    async fn check_loop(&self, bf4: Arc<Bf4Client>) {
+        loop {
+            tokio::time::sleep(self.config().interval).await;
+            if self.paused.load(Ordering::Relaxed) || self.between_rounds.load(Ordering::Relaxed) {
+                continue;
+            }
+            self.check(&bf4).await;
+        }
+    }
+
+    async fn check(&self, bf4: &Arc<Bf4Client>) {
+        let config = self.config();
+        let (list, info) = match (bf4.list_players(Visibility::All).await, bf4.server_info().await) {
+            (Ok(list), Ok(info)) => (list, info),
+            (Err(err), _) => {
+                warn!("[{}] Couldn't list players: {:?}", Self::NAME, err);
+                return;
+            },
+            (_, Err(err)) => {
+                warn!("[{}] Couldn't get the server info: {:?}", Self::NAME, err);
+                return;
+            },
+        };
+
+        let now = Instant::now();
+        let verdicts = {
+            let mut activity = self.activity.lock();
+            let online = list.iter()
+                .map(|pi| Player { name: pi.player_name.clone(), eaid: pi.eaid })
+                .collect::<HashSet<_>>();
+            activity.retain(|player, _| online.contains(player));
+
+            list.iter()
+                .filter_map(|pi| {
+                    let player = Player { name: pi.player_name.clone(), eaid: pi.eaid };
+                    let entry = activity.entry(player.clone()).or_insert_with(|| Activity::new(now));
+                    // score only ever goes up while playing, and is reset each round.
+                    if entry.score.is_some_and(|score| pi.score > score) {
+                        entry.active(now);
+                    }
+                    entry.score = Some(pi.score);
+                    match verdict(entry, &config, now) {
+                        Verdict::Fine => None,
+                        verdict => Some((player, verdict)),
+                    }
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let free_slots = (info.max_playercount - list.len() as i32).max(0) as usize;
+        if free_slots > config.max_free_slots {
+            return;
+        }
+
+        for (player, verdict) in verdicts {
+            if self.roles.judge(&player, bf4).await.has(config.exempt) {
+                continue;
+            }
+            match verdict {
+                Verdict::Warn => {
+                    if let Some(activity) = self.activity.lock().get_mut(&player) {
+                        activity.warned = true;
+                    }
+                    let _ = bf4.yell(format!("{}: You seem to be away. Move, or you will be kicked to free your slot.", player.name), player).await;
+                },
+                Verdict::Kick => {
+                    info!("[{}] Kicking {} for being idle for {}.", Self::NAME, player, humantime::format_duration(config.idle));
+                    match bf4.kick(player.name.clone(), "Kicked for being idle on a full server").await {
+                        Ok(()) | Err(PlayerKickError::PlayerNotFound) => (),
+                        Err(err) => warn!("[{}] Failed to kick {}: {:?}", Self::NAME, player, err),
+                    }
+                    self.activity.lock().remove(&player);
+                },
+                Verdict::Fine => (),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Plugin for AfkKicker {
+    const NAME: &'static str = "afkkicker";
+    type Config = Config;
+
+    fn enabled(&self) -> bool {
+        self.config().enabled
+    }
+
+    async fn reconfigure(self: &Arc<Self>, _bf4: &Arc<Bf4Client>, config: Config) -> Result<(), ConfigError> {
+        *self.config.write() = Arc::new(config);
+        Ok(())
+    }
+
+    async fn start(self: &Arc<Self>, bf4: &Arc<Bf4Client>) {
+        let myself = self.clone();
+        let bf4 = bf4.clone();
+        tokio::spawn(async move { myself.check_loop(bf4).await });
+    }
+
+    async fn pause(self: &Arc<Self>, _bf4: &Arc<Bf4Client>) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    async fn resume(self: &Arc<Self>, _bf4: &Arc<Bf4Client>) {
+        self.paused.store(false, Ordering::Relaxed);
+    }
+
+    async fn event(self: Arc<Self>, _bf4: Arc<Bf4Client>, event: Event) -> RconResult<()> {
+        match event {
+            Event::Authenticated { player }
+            | Event::Spawn { player, .. }
+            | Event::Chat { player, .. }
+            | Event::TeamChange { player, .. }
+            | Event::SquadChange { player, .. }
+            | Event::Kill { killer: Some(player), .. } => self.active(player),
+            Event::Leave { player, .. } => {
+                self.activity.lock().remove(&player);
+            },
+            Event::RoundOver { .. } => self.between_rounds.store(true, Ordering::Relaxed),
+            Event::LevelLoaded { .. } => {
+                // nobody could do anything during the end screen and while loading.
+                let now = Instant::now();
+                for activity in self.activity.lock().values_mut() {
+                    activity.active(now);
+                    activity.score = None;
+                }
+                self.between_rounds.store(false, Ordering::Relaxed);
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for AfkKicker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AfkKicker")
+            .field("config", &self.config())
+            .field("activity", &*self.activity.lock())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn verdicts() {
+        let config: Config = serde_yaml::from_str(include_str!("../configs/afkkicker.yaml")).unwrap();
+        let then = Instant::now();
+        let mut activity = Activity::new(then);
+
+        assert_eq!(verdict(&activity, &config, then), Verdict::Fine);
+        let almost = then + config.idle - config.warn_before;
+        assert_eq!(verdict(&activity, &config, almost), Verdict::Warn);
+        assert_eq!(verdict(&activity, &config, then + config.idle), Verdict::Warn);
+        activity.warned = true;
+        assert_eq!(verdict(&activity, &config, almost), Verdict::Fine);
+        assert_eq!(verdict(&activity, &config, then + config.idle), Verdict::Kick);
+
+        activity.active(then + config.idle);
+        assert!(!activity.warned);
+        assert_eq!(verdict(&activity, &config, then + config.idle), Verdict::Fine);
+    }
+}
@@ -0,0 +1,319 @@
+//! Cycles through announcements like the rules, the Discord link, or how to get VIP.
+//!
+//! Each announcement is due either every so often, or on a cron schedule, and may only be shown
+//! under some conditions (enough players, certain maps, between rounds, ...). When it has several
+//! messages, the next one is shown each time. Messages can contain placeholders, see `render`.
+
+use std::{sync::{atomic::{AtomicBool, Ordering}, Arc}, time::Duration};
+
+use async_trait::async_trait;
+use battlefield_rcon::{bf4::{server_info::ServerInfo, Bf4Client, Event, Map, Visibility}, rcon::RconResult};
+use chrono::{DateTime, Utc};
+use parking_lot::{Mutex, RwLock};
+use serde::{de::Error, Deserialize, Deserializer};
+
+use crate::mapvote::Mapvote;
+use crate::{ConfigError, Plugin, UPTIME};
+
+/// How often we look whether an announcement is due.
+const TICK: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    enabled: bool,
+    #[serde(default)]
+    announcements: Vec<Announcement>,
+}
+
+impl Config {
+    fn validate(&self) -> Result<(), ConfigError> {
+        for (i, announcement) in self.announcements.iter().enumerate() {
+            if announcement.messages.is_empty() {
+                return Err(ConfigError::Invalid(format!("announcement #{} has no messages", i + 1)));
+            }
+            if let Schedule::Every(every) = announcement.schedule {
+                if every < Duration::from_secs(10) {
+                    return Err(ConfigError::Invalid(format!("announcement #{} would spam more than every 10 seconds", i + 1)));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Announcement {
+    /// Shown one after another, one each time the announcement is due.
+    messages: Vec<String>,
+    #[serde(default)]
+    how: How,
+    #[serde(flatten)]
+    schedule: Schedule,
+    #[serde(default)]
+    only: Conditions,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum How {
+    #[default]
+    Say,
+    Yell,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Schedule {
+    /// e.g. `every: 10m`.
+    Every(#[serde(with = "humantime_serde")] Duration),
+    /// e.g. `cron: "0 0,30 * * * *"` (with seconds), in UTC.
+    Cron(#[serde(deserialize_with = "deserialize_cron")] Box<cron::Schedule>),
+}
+
+fn deserialize_cron<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Box<cron::Schedule>, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    s.parse().map(Box::new).map_err(|err| D::Error::custom(format!("invalid cron schedule \"{}\": {}", s, err)))
+}
+
+impl Schedule {
+    fn next_after(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Schedule::Every(every) => Some(now + chrono::Duration::from_std(*every).ok()?),
+            Schedule::Cron(cron) => cron.after(&now).next(),
+        }
+    }
+}
+
+/// When an announcement may be shown. Everything left out doesn't matter.
+#[derive(Debug, Clone, Default, Deserialize)]
+struct Conditions {
+    min_players: Option<usize>,
+    max_players: Option<usize>,
+    #[serde(default)]
+    maps: Vec<Map>,
+    /// As RCON calls them, e.g. `ConquestLarge0`, except for `Rush`.
+    #[serde(default)]
+    modes: Vec<String>,
+    /// `true`: only in the end screen. `false`: only while a round is going.
+    between_rounds: Option<bool>,
+}
+
+impl Conditions {
+    fn hold(&self, info: &ServerInfo, between_rounds: bool) -> bool {
+        let players = info.playercount.max(0) as usize;
+        self.min_players.is_none_or(|min| players >= min)
+            && self.max_players.is_none_or(|max| players <= max)
+            && (self.maps.is_empty() || self.maps.contains(&info.map))
+            && (self.modes.is_empty() || self.modes.iter().any(|mode| *mode == info.game_mode.to_string()))
+            && self.between_rounds.is_none_or(|wanted| wanted == between_rounds)
+    }
+}
+
+/// Where each announcement is at.
+#[derive(Debug)]
+struct Slot {
+    next: Option<DateTime<Utc>>,
+    /// Index of the message which is shown next.
+    message: usize,
+}
+
+/// Values for the placeholders in messages.
+struct Placeholders<'a> {
+    info: &'a ServerInfo,
+    nextmap: Option<Map>,
+}
+
+/// Fills in `{players}`, `{maxplayers}`, `{map}`, `{mode}`, `{nextmap}`, `{servername}` and
+/// `{uptime}` (of BattleFox).
+fn render(template: &str, values: &Placeholders) -> String {
+    let mut msg = template.to_string();
+    let mut replace = |placeholder: &str, value: &dyn Fn() -> String| {
+        if msg.contains(placeholder) {
+            msg = msg.replace(placeholder, &value());
+        }
+    };
+    replace("{players}", &|| values.info.playercount.to_string());
+    replace("{maxplayers}", &|| values.info.max_playercount.to_string());
+    replace("{map}", &|| values.info.map.Pretty().to_string());
+    replace("{mode}", &|| values.info.game_mode.to_string());
+    replace("{nextmap}", &|| values.nextmap.map_or("undecided".to_string(), |map| map.Pretty().to_string()));
+    replace("{servername}", &|| values.info.server_name.to_string());
+    replace("{uptime}", &|| {
+        // nobody cares about the seconds.
+        let minutes = UPTIME.elapsed().as_secs() / 60 * 60;
+        humantime::format_duration(Duration::from_secs(minutes)).to_string()
+    });
+    msg
+}
+
+pub struct Announcer {
+    config: RwLock<Arc<Config>>,
+    mapvote: Arc<Mapvote>,
+    slots: Mutex<Vec<Slot>>,
+    between_rounds: AtomicBool,
+    paused: AtomicBool,
+}
+
+impl Announcer {
+    pub fn new(mapvote: Arc<Mapvote>, config: Config) -> Self {
+        let slots = Self::slots_for(&config);
+        Self {
+            config: RwLock::new(Arc::new(config)),
+            mapvote,
+            slots: Mutex::new(slots),
+            between_rounds: AtomicBool::new(false),
+            paused: AtomicBool::new(false),
+        }
+    }
+
+    fn config(&self) -> Arc<Config> {
+        self.config.read().clone()
+    }
+
+    fn slots_for(config: &Config) -> Vec<Slot> {
+        let now = Utc::now();
+        config.announcements.iter()
+            .map(|announcement| Slot {
+                next: announcement.schedule.next_after(now),
+                message: 0,
+            })
+            .collect()
+    }
+
+    /// Which messages are due now. Moves their slots on to the next time and message.
+    fn take_due(&self, config: &Config) -> Vec<(usize, String)> {
+        let now = Utc::now();
+        let mut slots = self.slots.lock();
+        let mut due = Vec::new();
+        for (i, (slot, announcement)) in slots.iter_mut().zip(config.announcements.iter()).enumerate() {
+            if matches!(slot.next, Some(next) if next <= now) {
+                due.push((i, announcement.messages[slot.message % announcement.messages.len()].clone()));
+                slot.message = (slot.message + 1) % announcement.messages.len();
+                slot.next = announcement.schedule.next_after(now);
+            }
+        }
+        due
+    }
+
+    async fn announce_loop(&self, bf4: Arc<Bf4Client>) {
+        loop {
+            tokio::time::sleep(TICK).await;
+            if self.paused.load(Ordering::Relaxed) {
+                continue;
+            }
+
+            let config = self.config();
+            let due = self.take_due(&config);
+            if due.is_empty() {
+                continue;
+            }
+
+            let info = match bf4.server_info().await {
+                Ok(info) => info,
+                Err(err) => {
+                    warn!("[{}] Couldn't get the server info, skipping announcements: {:?}", Self::NAME, err);
+                    continue;
+                }
+            };
+            let nextmap = self.mapvote.status().await.and_then(|status| status.alternatives.iter()
+                .max_by(|a, b| a.first_preferences.total_cmp(&b.first_preferences))
+                .filter(|alt| alt.first_preferences > 0.0)
+                .map(|alt| alt.alternative.map));
+            let values = Placeholders { info: &info, nextmap };
+            let between_rounds = self.between_rounds.load(Ordering::Relaxed);
+
+            for (i, template) in due {
+                let announcement = &config.announcements[i];
+                if !announcement.only.hold(&info, between_rounds) {
+                    continue;
+                }
+                let msg = render(&template, &values);
+                let _ = match announcement.how {
+                    How::Say => bf4.say(msg, Visibility::All).await.map_err(|err| format!("{:?}", err)),
+                    How::Yell => bf4.yell(msg, Visibility::All).await.map_err(|err| format!("{:?}", err)),
+                }.map_err(|err| warn!("[{}] Failed to announce: {}", Self::NAME, err));
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Plugin for Announcer {
+    const NAME: &'static str = "announcer";
+    type Config = Config;
+
+    fn enabled(&self) -> bool {
+        self.config().enabled
+    }
+
+    fn validate(config: &Config) -> Result<(), ConfigError> {
+        config.validate()
+    }
+
+    async fn reconfigure(self: &Arc<Self>, _bf4: &Arc<Bf4Client>, config: Config) -> Result<(), ConfigError> {
+        *self.slots.lock() = Self::slots_for(&config);
+        *self.config.write() = Arc::new(config);
+        Ok(())
+    }
+
+    async fn start(self: &Arc<Self>, bf4: &Arc<Bf4Client>) {
+        let myself = self.clone();
+        let bf4 = bf4.clone();
+        tokio::spawn(async move { myself.announce_loop(bf4).await });
+    }
+
+    async fn pause(self: &Arc<Self>, _bf4: &Arc<Bf4Client>) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    async fn resume(self: &Arc<Self>, _bf4: &Arc<Bf4Client>) {
+        self.paused.store(false, Ordering::Relaxed);
+    }
+
+    async fn event(self: Arc<Self>, _bf4: Arc<Bf4Client>, event: Event) -> RconResult<()> {
+        match event {
+            Event::RoundOver { .. } => self.between_rounds.store(true, Ordering::Relaxed),
+            Event::LevelLoaded { .. } => self.between_rounds.store(false, Ordering::Relaxed),
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for Announcer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Announcer")
+            .field("slots", &*self.slots.lock())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn config() {
+        let config: Config = serde_yaml::from_str(include_str!("../configs/announcer.yaml")).unwrap();
+        config.validate().unwrap();
+
+        let config: Config = serde_yaml::from_str(r#"
+enabled: true
+announcements:
+  - messages: [ "a", "b" ]
+    every: 5m
+    only:
+      min_players: 4
+      maps: [ Locker ]
+  - messages: [ "c" ]
+    how: yell
+    cron: "0 0 * * * *"
+"#).unwrap();
+        assert!(matches!(config.announcements[0].schedule, Schedule::Every(every) if every == Duration::from_secs(300)));
+        assert_eq!(config.announcements[0].only.maps, vec![Map::Locker]);
+        assert!(matches!(config.announcements[1].how, How::Yell));
+        assert!(matches!(config.announcements[1].schedule, Schedule::Cron(_)));
+
+        assert!(serde_yaml::from_str::<Config>("enabled: true\nannouncements:\n  - messages: [a]\n    cron: nope\n").is_err());
+    }
+}
@@ -0,0 +1,374 @@
+//! Keeps the teams even, since BF4's own autobalance doesn't do much.
+//!
+//! Every so often, we look at `admin.listPlayers`, and if one team has too many players more than
+//! the other, we move some over. Only dead players are moved, and nobody who plays in a squad or
+//! with teammates of the same clan tag. Players who just joined are moved first, since they
+//! haven't settled in yet. Whoever has at least the `immune` role is never moved.
+
+use std::{collections::HashMap, sync::{atomic::{AtomicBool, Ordering}, Arc}, time::{Duration, Instant}};
+
+use async_trait::async_trait;
+use battlefield_rcon::{bf4::{player_info_block::PlayerInfo, Bf4Client, Event, Player, PlayerMoveError, Squad, Team, Visibility}, rcon::RconResult};
+use battlefox_database::BfoxContext;
+use parking_lot::{Mutex, RwLock};
+use serde::Deserialize;
+
+use crate::mapmanager::MapManager;
+use crate::roles::{Role, Roles};
+use crate::{ConfigError, Plugin};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    enabled: bool,
+    /// How often to check the teams.
+    #[serde(with = "humantime_serde")]
+    interval: Duration,
+    /// Players with this role or higher are never moved.
+    immune: Role,
+    /// Players who joined less than this long ago are moved first.
+    #[serde(with = "humantime_serde")]
+    recent_join: Duration,
+    /// Someone who was moved won't be moved again for this long.
+    #[serde(with = "humantime_serde")]
+    cooldown: Duration,
+    /// Used when the current popstate has no entry in `popstates`.
+    default: Rules,
+    /// Popstate name -> rules.
+    #[serde(default)]
+    popstates: HashMap<String, Rules>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Rules {
+    #[serde(default = "yes")]
+    enabled: bool,
+    /// How many players one team may have more than the other.
+    max_difference: usize,
+    /// At most this many players are moved per check.
+    #[serde(default = "one")]
+    moves_per_check: usize,
+}
+
+fn yes() -> bool { true }
+fn one() -> usize { 1 }
+
+impl Config {
+    fn rules(&self, popstate: &str) -> &Rules {
+        self.popstates.get(popstate).unwrap_or(&self.default)
+    }
+}
+
+pub struct Balancer {
+    config: RwLock<Arc<Config>>,
+    roles: Arc<Roles>,
+    mapman: Arc<MapManager>,
+    db: BfoxContext,
+    inner: Mutex<Inner>,
+    between_rounds: AtomicBool,
+    paused: AtomicBool,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// When they joined, as far as we've seen it.
+    joined: HashMap<Player, Instant>,
+    /// Who is dead right now. Everyone we haven't seen die counts as alive.
+    dead: HashMap<Player, bool>,
+    /// When we last moved them.
+    moved: HashMap<Player, Instant>,
+    /// Clan tags from the database. `None` means they have none.
+    clan_tags: HashMap<Player, Option<String>>,
+}
+
+impl Balancer {
+    pub fn new(roles: Arc<Roles>, mapman: Arc<MapManager>, db: BfoxContext, config: Config) -> Self {
+        Self {
+            config: RwLock::new(Arc::new(config)),
+            roles,
+            mapman,
+            db,
+            inner: Mutex::new(Inner::default()),
+            between_rounds: AtomicBool::new(false),
+            paused: AtomicBool::new(false),
+        }
+    }
+
+    fn config(&self) -> Arc<Config> {
+        self.config.read().clone()
+    }
+
+    async fn balance_loop(&self, bf4: Arc<Bf4Client>) {
+        loop {
+            tokio::time::sleep(self.config().interval).await;
+            if self.paused.load(Ordering::Relaxed) || self.between_rounds.load(Ordering::Relaxed) {
+                continue;
+            }
+            self.balance(&bf4).await;
+        }
+    }
+
+    async fn balance(&self, bf4: &Arc<Bf4Client>) {
+        let config = self.config();
+        let popstate = self.mapman.popstate().await.name;
+        let rules = config.rules(&popstate);
+        if !rules.enabled {
+            return;
+        }
+
+        let list = match bf4.list_players(Visibility::All).await {
+            Ok(list) => list,
+            Err(err) => {
+                warn!("[{}] Couldn't list players: {:?}", Self::NAME, err);
+                return;
+            }
+        };
+        let count = |team| list.iter().filter(|pi| pi.team == team).count();
+        let (big, small) = if count(Team::One) >= count(Team::Two) { (Team::One, Team::Two) } else { (Team::Two, Team::One) };
+        let difference = count(big) - count(small);
+        if difference <= rules.max_difference {
+            return;
+        }
+        let wanted = (difference - rules.max_difference).div_ceil(2).min(rules.moves_per_check);
+
+        self.fetch_clan_tags(&list).await;
+        let candidates = {
+            let inner = self.inner.lock();
+            let candidates = movable(&list, big, &inner.clan_tags);
+            let mut candidates = candidates.into_iter()
+                .filter(|pi| {
+                    let player = player_of(pi);
+                    inner.dead.get(&player).copied().unwrap_or(false)
+                        && inner.moved.get(&player).is_none_or(|when| when.elapsed() >= config.cooldown)
+                })
+                .map(|pi| {
+                    let player = player_of(pi);
+                    let joined = inner.joined.get(&player).copied();
+                    (pi, joined)
+                })
+                .collect::<Vec<_>>();
+            let big_is_stronger = strength(&list, big) >= strength(&list, small);
+            order(&mut candidates, Instant::now(), config.recent_join, big_is_stronger);
+            candidates
+        };
+
+        debug!("[{}] {} has {} players more than {}, moving {} of {} candidates.",
+            Self::NAME, team_name(big), difference, team_name(small), wanted, candidates.len());
+        let mut moved = 0;
+        for (pi, _) in candidates {
+            if moved >= wanted {
+                break;
+            }
+            let player = player_of(pi);
+            if self.roles.judge(&player, bf4).await.has(config.immune) {
+                continue;
+            }
+            match bf4.move_player(player.name.clone(), small, Squad::NoSquad, false).await {
+                Ok(()) => {
+                    info!("[{}] Moved {} to {} to balance the teams.", Self::NAME, player, team_name(small));
+                    self.inner.lock().moved.insert(player.clone(), Instant::now());
+                    let _ = bf4.say(format!("{}: You were moved to the other team to even out the teams. Thanks!", player.name), player).await;
+                    moved += 1;
+                },
+                // they respawned in the meantime, or left.
+                Err(PlayerMoveError::PlayerNotDead | PlayerMoveError::InvalidPlayerName) => (),
+                Err(err) => warn!("[{}] Failed to move {}: {:?}", Self::NAME, player, err),
+            }
+        }
+    }
+
+    /// Looks up the clan tags of everyone we don't know yet.
+    async fn fetch_clan_tags(&self, list: &[PlayerInfo]) {
+        let unknown = {
+            let inner = self.inner.lock();
+            list.iter()
+                .map(player_of)
+                .filter(|player| !inner.clan_tags.contains_key(player))
+                .collect::<Vec<_>>()
+        };
+        for player in unknown {
+            match self.db.get_clan_tag(player.eaid.to_string()).await {
+                Ok(tag) => {
+                    self.inner.lock().clan_tags.insert(player, tag);
+                },
+                Err(err) => {
+                    warn!("[{}] Couldn't get the clan tag of {}: {}", Self::NAME, player, err);
+                    return; // try again next time.
+                },
+            }
+        }
+    }
+}
+
+fn player_of(pi: &PlayerInfo) -> Player {
+    Player {
+        name: pi.player_name.clone(),
+        eaid: pi.eaid,
+    }
+}
+
+fn team_name(team: Team) -> &'static str {
+    match team {
+        Team::One => "US",
+        Team::Two => "RU",
+        Team::Neutral => "Neutral",
+    }
+}
+
+/// How well a team is doing, from score, and kills to break ties.
+fn strength(list: &[PlayerInfo], team: Team) -> (i64, i64) {
+    list.iter()
+        .filter(|pi| pi.team == team)
+        .fold((0, 0), |(score, kills), pi| (score + pi.score as i64, kills + pi.kills as i64))
+}
+
+/// Everyone on `team` who could be moved without tearing apart a squad or a clan.
+fn movable<'a>(list: &'a [PlayerInfo], team: Team, clan_tags: &HashMap<Player, Option<String>>) -> Vec<&'a PlayerInfo> {
+    let mates = list.iter().filter(|pi| pi.team == team).collect::<Vec<_>>();
+    let tag = |pi: &PlayerInfo| clan_tags.get(&player_of(pi)).cloned().flatten();
+    mates.iter()
+        .filter(|pi| pi.squad == Squad::NoSquad || !mates.iter().any(|other| other.eaid != pi.eaid && other.squad == pi.squad))
+        .filter(|pi| match tag(pi) {
+            Some(mine) => !mates.iter().any(|other| other.eaid != pi.eaid && tag(other).as_ref() == Some(&mine)),
+            None => true,
+        })
+        .copied()
+        .collect()
+}
+
+/// Recent joiners first, the most recent first. Then whoever evens out the teams' strength most:
+/// The best players if the bigger team is also the stronger one, otherwise the weakest.
+fn order(candidates: &mut [(&PlayerInfo, Option<Instant>)], now: Instant, recent_join: Duration, big_is_stronger: bool) {
+    candidates.sort_by_key(|(pi, joined)| {
+        let ago = joined.map(|joined| now.saturating_duration_since(joined));
+        let by_recency = ago.filter(|&ago| ago < recent_join).unwrap_or(Duration::MAX);
+        let by_score = if big_is_stronger { -pi.score } else { pi.score };
+        (by_recency, by_score)
+    });
+}
+
+#[async_trait]
+impl Plugin for Balancer {
+    const NAME: &'static str = "balancer";
+    type Config = Config;
+
+    fn enabled(&self) -> bool {
+        self.config().enabled
+    }
+
+    async fn reconfigure(self: &Arc<Self>, _bf4: &Arc<Bf4Client>, config: Config) -> Result<(), ConfigError> {
+        *self.config.write() = Arc::new(config);
+        Ok(())
+    }
+
+    async fn start(self: &Arc<Self>, bf4: &Arc<Bf4Client>) {
+        let myself = self.clone();
+        let bf4 = bf4.clone();
+        tokio::spawn(async move { myself.balance_loop(bf4).await });
+    }
+
+    async fn pause(self: &Arc<Self>, _bf4: &Arc<Bf4Client>) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    async fn resume(self: &Arc<Self>, _bf4: &Arc<Bf4Client>) {
+        self.paused.store(false, Ordering::Relaxed);
+    }
+
+    async fn event(self: Arc<Self>, _bf4: Arc<Bf4Client>, event: Event) -> RconResult<()> {
+        match event {
+            Event::Authenticated { player } => {
+                self.inner.lock().joined.insert(player, Instant::now());
+            },
+            Event::Kill { victim, .. } => {
+                self.inner.lock().dead.insert(victim, true);
+            },
+            Event::Spawn { player, .. } => {
+                self.inner.lock().dead.insert(player, false);
+            },
+            Event::Leave { player, .. } => {
+                let mut inner = self.inner.lock();
+                inner.joined.remove(&player);
+                inner.dead.remove(&player);
+                inner.moved.remove(&player);
+                inner.clan_tags.remove(&player);
+            },
+            Event::RoundOver { .. } => {
+                self.between_rounds.store(true, Ordering::Relaxed);
+                self.inner.lock().dead.clear();
+            },
+            Event::LevelLoaded { .. } => self.between_rounds.store(false, Ordering::Relaxed),
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for Balancer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Balancer")
+            .field("config", &self.config())
+            .field("inner", &*self.inner.lock())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testutil::player_info;
+
+    fn pi(name: &str, eaid: u8, team: Team, squad: Squad, score: i32) -> PlayerInfo {
+        PlayerInfo { squad, score, ..player_info(name, eaid, team) }
+    }
+
+    #[test]
+    fn config() {
+        let config: Config = serde_yaml::from_str(include_str!("../configs/balancer.yaml")).unwrap();
+        assert!(!config.rules("SeedingPopulation").enabled);
+        assert_eq!(config.rules("HighPopulation").max_difference, 1);
+        assert_eq!(config.rules("Whatever").max_difference, config.default.max_difference);
+    }
+
+    #[test]
+    fn keeps_squads_and_clans_together() {
+        let list = vec![
+            pi("Lonely", 1, Team::One, Squad::NoSquad, 100),
+            pi("SquadA", 2, Team::One, Squad::Alpha, 100),
+            pi("SquadB", 3, Team::One, Squad::Alpha, 100),
+            pi("AloneInSquad", 4, Team::One, Squad::Bravo, 100),
+            pi("ClanA", 5, Team::One, Squad::NoSquad, 100),
+            pi("ClanB", 6, Team::One, Squad::NoSquad, 100),
+            pi("ClanOnlyHere", 7, Team::One, Squad::NoSquad, 100),
+            pi("Enemy", 8, Team::Two, Squad::Alpha, 100),
+            pi("EnemyClan", 9, Team::Two, Squad::NoSquad, 100),
+        ];
+        let mut tags = HashMap::new();
+        tags.insert(player_of(&list[4]), Some("LOL".to_string()));
+        tags.insert(player_of(&list[5]), Some("LOL".to_string()));
+        tags.insert(player_of(&list[6]), Some("ABC".to_string()));
+        tags.insert(player_of(&list[8]), Some("ABC".to_string()));
+
+        let names = movable(&list, Team::One, &tags).into_iter()
+            .map(|pi| pi.player_name.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["Lonely", "AloneInSquad", "ClanOnlyHere"]);
+    }
+
+    #[test]
+    fn recent_joiners_first() {
+        let old = pi("Old", 1, Team::One, Squad::NoSquad, 500);
+        let weak = pi("Weak", 2, Team::One, Squad::NoSquad, 10);
+        let new = pi("New", 3, Team::One, Squad::NoSquad, 0);
+        let then = Instant::now();
+        let now = then + Duration::from_secs(3600);
+        let mut candidates = vec![(&old, None), (&weak, Some(then)), (&new, Some(now))];
+
+        order(&mut candidates, now, Duration::from_secs(600), true);
+        let names = candidates.iter().map(|(pi, _)| pi.player_name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["New", "Old", "Weak"]);
+
+        order(&mut candidates, now, Duration::from_secs(600), false);
+        let names = candidates.iter().map(|(pi, _)| pi.player_name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["New", "Weak", "Old"]);
+    }
+}
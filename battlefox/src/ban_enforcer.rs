@@ -7,9 +7,10 @@ use battlefield_rcon::bf4::{Bf4Client, Event, BanListError, PlayerKickError};
 use battlefield_rcon::rcon::RconResult;
 use battlefox_database::{BfoxContext, DateTime};
 use battlefox_database::adkats::bans::BanStatus;
+use parking_lot::RwLock;
 use serde::{Deserialize, Serialize};
 
-use crate::Plugin;
+use crate::{ConfigError, Plugin};
 use crate::players::Players;
 
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
@@ -18,13 +19,13 @@ pub struct Config {
 }
 
 pub struct BanEnforcer {
-    config: Config,
+    config: RwLock<Arc<Config>>,
     db: BfoxContext,
 }
 
 impl BanEnforcer {
     pub fn new(config: Config, _players: Arc<Players>, db: BfoxContext) -> Self {
-        Self { config, db }
+        Self { config: RwLock::new(Arc::new(config)), db }
     }
 
     async fn event(self: Arc<Self>, bf4: Arc<Bf4Client>, event: Event) -> RconResult<()> {
@@ -82,7 +83,13 @@ impl BanEnforcer {
 #[async_trait]
 impl Plugin for BanEnforcer {
     const NAME: &'static str = "ban_enforcer";
-    fn enabled(&self) -> bool { self.config.enabled }
+    type Config = Config;
+    fn enabled(&self) -> bool { self.config.read().enabled }
+
+    async fn reconfigure(self: &Arc<Self>, _bf4: &Arc<Bf4Client>, config: Config) -> Result<(), ConfigError> {
+        *self.config.write() = Arc::new(config);
+        Ok(())
+    }
 
     async fn event(self: Arc<Self>, bf4: Arc<Bf4Client>, event: Event) -> RconResult<()> {
         self.event(bf4, event).await
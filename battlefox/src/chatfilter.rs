@@ -0,0 +1,364 @@
+//! Punishes bad language in chat.
+//!
+//! There are two lists: `slurs`, which are punished right away, and `swearing`, which usually just
+//! gets a warning. Each list has its own ladder of punishments, and every offence moves a player
+//! (by EA GUID) one step further up the ladder. Offences are forgotten after a while.
+//!
+//! Messages are normalised before checking, so that `5h!t`, `SHIIIT` and `s h i t` all count.
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use battlefield_rcon::{bf4::{Bf4Client, CommmoRose, Eaid, Event}, rcon::RconResult};
+use chrono::{DateTime, Utc};
+use parking_lot::{Mutex, RwLock};
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::commands::Commands;
+use crate::playermute::PlayerMute;
+use crate::punishment::{self, Punishment};
+use crate::roles::{Role, Roles};
+use crate::state::{self, Persistent};
+use crate::{ConfigError, Plugin};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    enabled: bool,
+    /// Players with this role or higher may say whatever they want.
+    immune: Role,
+    /// Offences older than this are forgotten.
+    #[serde(with = "humantime_serde")]
+    forget_after: Duration,
+    slurs: WordList,
+    swearing: WordList,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct WordList {
+    /// Matched against whole words, after normalising both. Repeated letters in the message
+    /// still match (`fuuuck` is `fuck`), but missing ones don't (`as` isn't `ass`).
+    #[serde(default, deserialize_with = "deserialize_words")]
+    words: Vec<String>,
+    /// Matched against the lowercased message, and against the normalised one with and without
+    /// repeated letters squashed.
+    #[serde(default, deserialize_with = "deserialize_patterns")]
+    patterns: Vec<Regex>,
+    /// The first offence gets the first punishment, and so on. After the last one, it stays at the
+    /// last one.
+    punishments: Vec<Punishment>,
+}
+
+fn deserialize_words<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    let words = Vec::<String>::deserialize(deserializer)?;
+    Ok(words.iter().map(|word| normalise_word(word)).filter(|word| !word.is_empty()).collect())
+}
+
+fn deserialize_patterns<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Regex>, D::Error> {
+    use serde::de::Error;
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|pattern| Regex::new(pattern).map_err(|err| D::Error::custom(format!("invalid pattern \"{}\": {}", pattern, err))))
+        .collect()
+}
+
+impl Config {
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.slurs.punishments.is_empty() || self.swearing.punishments.is_empty() {
+            return Err(ConfigError::Invalid("each list needs at least one punishment".to_string()));
+        }
+        Ok(())
+    }
+}
+
+impl WordList {
+    fn matches(&self, lowercase: &str, normalised: &[String]) -> bool {
+        let joined = normalised.join(" ");
+        let squashed = squash(&joined);
+        normalised.iter().any(|word| self.words.iter().any(|listed| stretches(word, listed)))
+            || self.patterns.iter().any(|pattern| {
+                pattern.is_match(lowercase) || pattern.is_match(&joined) || pattern.is_match(&squashed)
+            })
+    }
+
+    fn punishment(&self, offence: usize) -> &Punishment {
+        punishment::step(&self.punishments, offence).unwrap() // unwrap: validated to be non-empty.
+    }
+}
+
+/// Leetspeak which always stands for a letter. `1`, `!` and `|` only count as `i` between two
+/// letters, they're too common on their own (`fuck!`).
+fn unleet(c: char) -> char {
+    match c {
+        '0' => 'o',
+        '3' => 'e',
+        '4' | '@' => 'a',
+        '5' | '$' => 's',
+        '7' | '+' => 't',
+        '8' => 'b',
+        '9' => 'g',
+        c => c,
+    }
+}
+
+/// Lowercases, strips punctuation around the word, undoes leetspeak, and drops everything but
+/// letters. Repeated letters are kept, see `stretches`.
+fn normalise_word(word: &str) -> String {
+    let word = word.trim_matches(|c: char| c.is_ascii_punctuation() && unleet(c) == c);
+    let chars: Vec<char> = word.chars().map(|c| unleet(c.to_ascii_lowercase())).collect();
+    let is_letter = |i: Option<usize>| i.and_then(|i| chars.get(i)).is_some_and(char::is_ascii_lowercase);
+    let mut out = String::with_capacity(chars.len());
+    for (i, &c) in chars.iter().enumerate() {
+        let c = match c {
+            '1' | '!' | '|' if is_letter(i.checked_sub(1)) && is_letter(Some(i + 1)) => 'i',
+            c => c,
+        };
+        if c.is_ascii_lowercase() {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// `shiiit` -> `shit`.
+fn squash(word: &str) -> String {
+    let mut out = String::with_capacity(word.len());
+    for c in word.chars() {
+        if !out.ends_with(c) {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Runs of the same letter, `ass` -> `[('a', 1), ('s', 2)]`.
+fn runs(word: &str) -> Vec<(char, usize)> {
+    let mut runs: Vec<(char, usize)> = Vec::new();
+    for c in word.chars() {
+        match runs.last_mut() {
+            Some((last, n)) if *last == c => *n += 1,
+            _ => runs.push((c, 1)),
+        }
+    }
+    runs
+}
+
+/// Whether `word` is `listed`, possibly with some letters repeated more often.
+fn stretches(word: &str, listed: &str) -> bool {
+    let (word, listed) = (runs(word), runs(listed));
+    word.len() == listed.len() && word.iter().zip(&listed).all(|((a, n), (b, m))| a == b && n >= m)
+}
+
+/// Normalises every word. Runs of single letters (`s h i t`) are glued back together.
+fn normalise(msg: &str) -> Vec<String> {
+    let mut words: Vec<String> = Vec::new();
+    let mut spelled = String::new();
+    for word in msg.split_whitespace().map(normalise_word).filter(|word| !word.is_empty()) {
+        if word.len() == 1 {
+            spelled.push_str(&word);
+            continue;
+        }
+        if !spelled.is_empty() {
+            words.push(std::mem::take(&mut spelled));
+        }
+        words.push(word);
+    }
+    if !spelled.is_empty() {
+        words.push(spelled);
+    }
+    words
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Severity {
+    Slur,
+    Swearing,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Offences {
+    slurs: usize,
+    swearing: usize,
+    last: Option<DateTime<Utc>>,
+}
+
+/// What we remember across restarts, in `state/chatfilter.yaml`.
+#[derive(Debug, Serialize, Deserialize)]
+struct ChatFilterState {
+    offences: Vec<(Eaid, Offences)>,
+}
+
+impl Persistent for ChatFilterState {
+    const VERSION: u32 = 1;
+}
+
+pub struct ChatFilter {
+    config: RwLock<Arc<Config>>,
+    roles: Arc<Roles>,
+    commands: Arc<Commands>,
+    playermute: Arc<PlayerMute>,
+    offences: Mutex<HashMap<Eaid, Offences>>,
+}
+
+impl ChatFilter {
+    pub fn new(roles: Arc<Roles>, commands: Arc<Commands>, playermute: Arc<PlayerMute>, config: Config) -> Self {
+        let offences = state::load::<ChatFilterState>(Self::NAME)
+            .map(|state| state.offences.into_iter().collect())
+            .unwrap_or_default();
+        Self {
+            config: RwLock::new(Arc::new(config)),
+            roles,
+            commands,
+            playermute,
+            offences: Mutex::new(offences),
+        }
+    }
+
+    fn config(&self) -> Arc<Config> {
+        self.config.read().clone()
+    }
+
+    fn check(config: &Config, msg: &str) -> Option<Severity> {
+        let lowercase = msg.to_lowercase();
+        let normalised = normalise(msg);
+        if config.slurs.matches(&lowercase, &normalised) {
+            Some(Severity::Slur)
+        } else if config.swearing.matches(&lowercase, &normalised) {
+            Some(Severity::Swearing)
+        } else {
+            None
+        }
+    }
+
+    /// Counts the offence, and returns how many there were, including this one.
+    fn offend(&self, config: &Config, eaid: Eaid, severity: Severity) -> usize {
+        let now = Utc::now();
+        let mut offences = self.offences.lock();
+        let entry = offences.entry(eaid).or_default();
+        let forgotten = entry.last.is_some_and(|last| {
+            chrono::Duration::from_std(config.forget_after).is_ok_and(|forget| last + forget < now)
+        });
+        if forgotten {
+            *entry = Offences::default();
+        }
+        entry.last = Some(now);
+        let count = match severity {
+            Severity::Slur => &mut entry.slurs,
+            Severity::Swearing => &mut entry.swearing,
+        };
+        *count += 1;
+        *count
+    }
+}
+
+#[async_trait]
+impl Plugin for ChatFilter {
+    const NAME: &'static str = "chatfilter";
+    type Config = Config;
+
+    fn enabled(&self) -> bool {
+        self.config().enabled
+    }
+
+    fn validate(config: &Config) -> Result<(), ConfigError> {
+        config.validate()
+    }
+
+    async fn reconfigure(self: &Arc<Self>, _bf4: &Arc<Bf4Client>, config: Config) -> Result<(), ConfigError> {
+        *self.config.write() = Arc::new(config);
+        Ok(())
+    }
+
+    async fn persist(self: &Arc<Self>, _bf4: &Arc<Bf4Client>) {
+        let offences = self.offences.lock().iter()
+            .map(|(&eaid, offences)| (eaid, offences.clone()))
+            .collect();
+        state::save(Self::NAME, &ChatFilterState { offences });
+    }
+
+    async fn event(self: Arc<Self>, bf4: Arc<Bf4Client>, event: Event) -> RconResult<()> {
+        if let Event::Chat { player, msg, .. } = event {
+            // `/...` is hidden from everyone else anyway.
+            if msg.as_str().starts_with('/') || self.commands.is_command(msg.as_str()) || CommmoRose::decode(&msg).is_ok() {
+                return Ok(());
+            }
+            let config = self.config();
+            let severity = match Self::check(&config, msg.as_str()) {
+                Some(severity) => severity,
+                None => return Ok(()),
+            };
+            if self.roles.judge(&player, &bf4).await.has(config.immune) {
+                return Ok(());
+            }
+
+            let offence = self.offend(&config, player.eaid, severity);
+            let list = match severity {
+                Severity::Slur => &config.slurs,
+                Severity::Swearing => &config.swearing,
+            };
+            punishment::punish(&bf4, &self.playermute, Self::NAME, &player, list.punishment(offence), "Watch your language").await;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for ChatFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChatFilter")
+            .field("config", &self.config())
+            .field("offences", &*self.offences.lock())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn normalising() {
+        assert_eq!(normalise_word("5H!T"), "shit");
+        assert_eq!(normalise_word("shit!"), "shit");
+        assert_eq!(normalise_word("(fuck)"), "fuck");
+        assert_eq!(normalise_word("!!1!"), "");
+        assert_eq!(normalise_word("@$$"), "ass");
+        assert_eq!(squash("shiiiiit"), "shit");
+        assert!(stretches("shiiiiit", "shit"));
+        assert!(stretches("asss", "ass"));
+        assert!(!stretches("as", "ass"));
+        assert!(!stretches("shits", "shit"));
+        assert_eq!(normalise("you s h i t  head"), vec!["you", "shit", "head"]);
+        assert_eq!(normalise("a b"), vec!["ab"]);
+    }
+
+    #[test]
+    fn checking() {
+        let config: Config = serde_yaml::from_str(include_str!("../configs/chatfilter.yaml")).unwrap();
+        config.validate().unwrap();
+        assert_eq!(ChatFilter::check(&config, "what the fuuuck"), Some(Severity::Swearing));
+        assert_eq!(ChatFilter::check(&config, "f u c k this"), Some(Severity::Swearing));
+        assert_eq!(ChatFilter::check(&config, "nice shot"), None);
+        assert_eq!(ChatFilter::check(&config, "I'm a Scunthorpe fan"), None);
+        assert_eq!(ChatFilter::check(&config, "oh shit!"), Some(Severity::Swearing));
+        assert_eq!(ChatFilter::check(&config, "sh!t, again..."), Some(Severity::Swearing));
+
+        assert_eq!(config.swearing.punishment(1), &Punishment::Warn);
+        assert_eq!(config.swearing.punishment(50), &Punishment::Warn);
+        assert_eq!(config.slurs.punishment(1), &Punishment::Kill);
+        assert!(matches!(config.slurs.punishment(50), Punishment::Tempban(_)));
+    }
+
+    #[test]
+    fn repeated_letters() {
+        let config: Config = serde_yaml::from_str(r#"
+enabled: true
+immune: moderator
+forget_after: 1day
+slurs: { punishments: [ kill ] }
+swearing: { words: [ ass ], punishments: [ warn ] }
+"#).unwrap();
+        assert_eq!(ChatFilter::check(&config, "as if"), None);
+        assert_eq!(ChatFilter::check(&config, "kiss my ass!"), Some(Severity::Swearing));
+        assert_eq!(ChatFilter::check(&config, "kiss my @$$$$"), Some(Severity::Swearing));
+    }
+}
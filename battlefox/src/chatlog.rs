@@ -0,0 +1,222 @@
+//! Writes all chat into AdKats' `tbl_chatlog`, so that admins can look into what was said.
+//!
+//! Messages are buffered and inserted in batches, away from event handling, so a slow database
+//! never holds up other plugins. If the database is unreachable or busy, the messages are kept
+//! and retried with the next batch. Batches which fail for other reasons are dropped, they would
+//! only fail again. Optionally, old messages of this server are deleted.
+//!
+//! The schema only knows whether a message went to everyone, the team, or the squad. Which team
+//! or squad that was isn't stored. Messages from the server to a single player have no subset at
+//! all, and aren't logged.
+
+use std::{sync::Arc, time::{Duration, Instant}};
+
+use async_trait::async_trait;
+use battlefield_rcon::{bf4::{Bf4Client, Event, Visibility}, rcon::RconResult};
+use battlefox_database::{adkats::chatlog::{ChatLogEntry, ChatSubset}, is_transient, BfoxContext, DateTime};
+use parking_lot::{Mutex, RwLock};
+use serde::Deserialize;
+use tokio::sync::Notify;
+
+use crate::{ConfigError, Plugin};
+
+/// If the database is down for long, we drop the oldest messages beyond this many.
+const MAX_BUFFERED: usize = 10_000;
+
+/// How often to delete old messages, if `retention` is set.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    enabled: bool,
+    /// `ServerID` of this server in AdKats' `tbl_servers`.
+    server_id: u16,
+    /// Insert at least this often...
+    #[serde(with = "humantime_serde")]
+    flush_interval: Duration,
+    /// ...or as soon as this many messages piled up.
+    batch_size: usize,
+    /// Delete this server's messages older than this, including those AdKats logged itself. Keeps
+    /// them forever if left out.
+    #[serde(default, with = "humantime_serde")]
+    retention: Option<Duration>,
+}
+
+/// `None` for messages to a single player, which we don't log.
+fn subset(vis: &Visibility) -> Option<ChatSubset> {
+    match vis {
+        Visibility::All => Some(ChatSubset::Global),
+        Visibility::Team(_) => Some(ChatSubset::Team),
+        Visibility::Squad(_, _) => Some(ChatSubset::Squad),
+        Visibility::Player(_) => None,
+    }
+}
+
+pub struct ChatLog {
+    config: RwLock<Arc<Config>>,
+    db: BfoxContext,
+    buffer: Mutex<Vec<ChatLogEntry>>,
+    /// Wakes up the flush loop early, when the buffer is full.
+    full: Notify,
+}
+
+impl ChatLog {
+    pub fn new(db: BfoxContext, config: Config) -> Self {
+        Self {
+            config: RwLock::new(Arc::new(config)),
+            db,
+            buffer: Mutex::new(Vec::new()),
+            full: Notify::new(),
+        }
+    }
+
+    fn config(&self) -> Arc<Config> {
+        self.config.read().clone()
+    }
+
+    fn log(&self, vis: &Visibility, soldier_name: String, eaid: Option<String>, message: String) {
+        let subset = match subset(vis) {
+            Some(subset) => subset,
+            None => return,
+        };
+        let entry = ChatLogEntry {
+            time: DateTime::now_utc(),
+            subset,
+            soldier_name,
+            eaid,
+            message,
+        };
+        let len = {
+            let mut buffer = self.buffer.lock();
+            buffer.push(entry);
+            buffer.len()
+        };
+        if len >= self.config().batch_size {
+            self.full.notify_one();
+        }
+    }
+
+    async fn flush(&self) {
+        let entries = std::mem::take(&mut *self.buffer.lock());
+        if entries.is_empty() {
+            return;
+        }
+        match self.db.insert_chat_log(self.config().server_id, &entries).await {
+            Ok(()) => (),
+            Err(err) if is_transient(&err) => {
+                warn!("[{}] Failed to write {} chat messages, will try again: {:?}", Self::NAME, entries.len(), err);
+                self.requeue(entries);
+            },
+            Err(err) => {
+                error!("[{}] Failed to write {} chat messages, dropping them: {:?}", Self::NAME, entries.len(), err);
+            },
+        }
+    }
+
+    /// Puts messages which couldn't be written back in front of the buffer.
+    fn requeue(&self, entries: Vec<ChatLogEntry>) {
+        let mut buffer = self.buffer.lock();
+        // keep the order, older ones first.
+        let newer = std::mem::replace(&mut *buffer, entries);
+        buffer.extend(newer);
+        if buffer.len() > MAX_BUFFERED {
+            let dropped = buffer.len() - MAX_BUFFERED;
+            buffer.drain(..dropped);
+            warn!("[{}] Dropped the {} oldest chat messages.", Self::NAME, dropped);
+        }
+    }
+
+    async fn prune(&self) {
+        let config = self.config();
+        if let Some(retention) = config.retention {
+            match self.db.prune_chat_log(config.server_id, retention).await {
+                Ok(0) => (),
+                Ok(n) => info!("[{}] Deleted {} chat messages older than {}.", Self::NAME, n, humantime::format_duration(retention)),
+                Err(err) => warn!("[{}] Failed to delete old chat messages: {:?}", Self::NAME, err),
+            }
+        }
+    }
+
+    async fn flush_loop(&self) {
+        let mut last_prune: Option<Instant> = None;
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(self.config().flush_interval) => (),
+                _ = self.full.notified() => (),
+            }
+            self.flush().await;
+
+            if last_prune.is_none_or(|last| last.elapsed() >= PRUNE_INTERVAL) {
+                last_prune = Some(Instant::now());
+                self.prune().await;
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Plugin for ChatLog {
+    const NAME: &'static str = "chatlog";
+    type Config = Config;
+
+    fn enabled(&self) -> bool {
+        self.config().enabled
+    }
+
+    async fn reconfigure(self: &Arc<Self>, _bf4: &Arc<Bf4Client>, config: Config) -> Result<(), ConfigError> {
+        *self.config.write() = Arc::new(config);
+        Ok(())
+    }
+
+    async fn start(self: &Arc<Self>, _bf4: &Arc<Bf4Client>) {
+        let myself = self.clone();
+        tokio::spawn(async move { myself.flush_loop().await });
+    }
+
+    async fn persist(self: &Arc<Self>, _bf4: &Arc<Bf4Client>) {
+        // on shutdown, this is our last chance.
+        self.flush().await;
+    }
+
+    async fn event(self: Arc<Self>, _bf4: Arc<Bf4Client>, event: Event) -> RconResult<()> {
+        match event {
+            Event::Chat { vis, player, msg } => {
+                self.log(&vis, player.name.to_string(), Some(player.eaid.to_string()), msg.to_string());
+            },
+            Event::ServerChat { vis, msg } => {
+                self.log(&vis, "Server".to_string(), None, msg.to_string());
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for ChatLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChatLog")
+            .field("config", &self.config())
+            .field("buffered", &self.buffer.lock().len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ascii::IntoAsciiString;
+    use battlefield_rcon::bf4::{Squad, Team};
+
+    use super::*;
+
+    #[test]
+    fn subsets() {
+        let config: Config = serde_yaml::from_str(include_str!("../configs/chatlog.yaml")).unwrap();
+        assert_eq!(config.retention, None);
+        let config: Config = serde_yaml::from_str(&format!("{}retention: 90days\n", include_str!("../configs/chatlog.yaml"))).unwrap();
+        assert_eq!(config.retention, Some(Duration::from_secs(90 * 24 * 60 * 60)));
+        assert_eq!(subset(&Visibility::All), Some(ChatSubset::Global));
+        assert_eq!(subset(&Visibility::Team(Team::One)), Some(ChatSubset::Team));
+        assert_eq!(subset(&Visibility::Squad(Team::Two, Squad::Alpha)), Some(ChatSubset::Squad));
+        assert_eq!(subset(&Visibility::Player("Alice".into_ascii_string().unwrap())), None);
+    }
+}
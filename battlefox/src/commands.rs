@@ -1,96 +1,579 @@
 //! Handling user commands and other chat goodies.
+//!
+//! Plugins register their commands here (usually in `Plugin::start`), together with typed
+//! arguments, aliases, the `Role` needed to use them and a help text. This plugin then parses
+//! chat messages, checks roles, and calls the right handler. `!help` is generated from the
+//! registry.
+//!
+//! Every command works with any of the prefixes `!`, `/`, `@` and `#`.
 
-use std::sync::Arc;
-use std::sync::Mutex;
+use std::{future::Future, sync::Arc, time::Duration};
 
-use battlefield_rcon::bf4::Bf4Client;
-use battlefield_rcon::bf4::Event::Chat;
-use battlefield_rcon::rcon::RconResult;
-use combine::Stream;
-use futures::stream::StreamExt;
-use combine::parser::token::Token;
-use combine::stream::position;
-use combine::{Parser, EasyParser, many1, sep_by, token};
-use combine::parser::char::{letter, space};
+use async_trait::async_trait;
+use battlefield_rcon::{
+    bf4::{Bf4Client, Event, Map, Player, Visibility},
+    rcon::RconResult,
+};
+use futures::future::BoxFuture;
+use itertools::Itertools;
+use parking_lot::RwLock;
 
-pub trait Command {
-    type Parameter;
+use crate::{guard::Guard, players::{MatchError, Players}, reload::ConfigReloader, roles::{Role, Roles}, supervisor::Supervisor, Plugin, UPTIME};
 
-    fn target(arg: Self::Parameter);
+/// Characters a chat message has to start with to be considered a command.
+pub const PREFIXES: [char; 4] = ['!', '/', '@', '#'];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgKind {
+    /// (Part of) the name of a player who is currently on the server.
+    Player,
+    /// For example `30m`, `2h`, `3d`.
+    Duration,
+    /// Map short name, for example `pearl` or `metro`.
+    Map,
+    /// A single word.
+    Word,
+    /// Everything until the end of the message. Has to be the last argument.
+    Text,
 }
 
-pub trait ParserCommand : Command {
-    fn parser<Input: Stream>() -> Box<dyn Parser<Input, Output = Self::Parameter, PartialState = ()>>;
+#[derive(Debug, Clone)]
+struct ArgSpec {
+    name: &'static str,
+    kind: ArgKind,
+    optional: bool,
 }
 
-pub struct CmdLifetime {
-    handle: usize,
-    commands: Arc<Commands>,
+#[derive(Debug, Clone)]
+pub enum Arg {
+    Player(Player),
+    Duration(Duration),
+    Map(Map),
+    Word(String),
+    Text(String),
 }
 
-impl Drop for CmdLifetime {
-    fn drop(&mut self) {
-        self.commands.cancel(self.handle);
+/// The parsed arguments of a command invocation.
+///
+/// Arguments are looked up by the name they were declared with. Optional arguments which weren't
+/// given are absent.
+#[derive(Debug, Clone, Default)]
+pub struct Args(Vec<(&'static str, Arg)>);
+
+impl Args {
+    pub fn get(&self, name: &str) -> Option<&Arg> {
+        self.0.iter().find(|(n, _)| *n == name).map(|(_, arg)| arg)
+    }
+
+    pub fn player(&self, name: &str) -> Option<&Player> {
+        match self.get(name) {
+            Some(Arg::Player(player)) => Some(player),
+            _ => None,
+        }
+    }
+
+    pub fn duration(&self, name: &str) -> Option<Duration> {
+        match self.get(name) {
+            Some(Arg::Duration(duration)) => Some(*duration),
+            _ => None,
+        }
+    }
+
+    pub fn map(&self, name: &str) -> Option<Map> {
+        match self.get(name) {
+            Some(Arg::Map(map)) => Some(*map),
+            _ => None,
+        }
+    }
+
+    pub fn word(&self, name: &str) -> Option<&str> {
+        match self.get(name) {
+            Some(Arg::Word(word)) => Some(word),
+            _ => None,
+        }
+    }
+
+    pub fn text(&self, name: &str) -> Option<&str> {
+        match self.get(name) {
+            Some(Arg::Text(text)) => Some(text),
+            _ => None,
+        }
     }
 }
 
-struct Inner {
+/// Everything a command handler gets to know about how it was invoked.
+#[derive(Debug, Clone)]
+pub struct Invocation {
+    /// Who typed the command, together with their role.
+    pub player: Guard<Player, Role>,
+    /// Where the command was typed in, e.g. all chat or team chat.
+    pub vis: Visibility,
+    pub args: Args,
+}
 
+/// Declaration of a command, built like `Command::new(Self::NAME, "mute", "Mutes a player").arg(...)`.
+#[derive(Debug, Clone)]
+pub struct Command {
+    owner: &'static str,
+    /// May consist of several words, e.g. `bfox reload`.
+    name: &'static str,
+    aliases: Vec<&'static str>,
+    args: Vec<ArgSpec>,
+    /// Can be overridden in `configs/roles.yaml`.
+    role: Role,
+    help: &'static str,
 }
 
-impl Inner {
-    fn rebuild() {
-        debug!("Rebuilding commands parser")
+impl Command {
+    /// `owner` is the name of the plugin, the command is unavailable while that plugin is paused.
+    pub fn new(owner: &'static str, name: &'static str, help: &'static str) -> Self {
+        Self {
+            owner,
+            name,
+            aliases: Vec::new(),
+            args: Vec::new(),
+            role: Role::Player,
+            help,
+        }
+    }
+
+    pub fn alias(mut self, alias: &'static str) -> Self {
+        self.aliases.push(alias);
+        self
     }
-}
 
-pub enum Matcher {
-    /// For example `ban` will match `!ban PocketWolfy`, `/ban` etc.
-    Exact(String),
+    pub fn arg(self, name: &'static str, kind: ArgKind) -> Self {
+        self.push_arg(name, kind, false)
+    }
 
-    /// For example `pearl`
-    ExactOrPrefix {
-        prefix: String,
-        minlen: usize,
+    pub fn opt_arg(self, name: &'static str, kind: ArgKind) -> Self {
+        self.push_arg(name, kind, true)
     }
+
+    fn push_arg(mut self, name: &'static str, kind: ArgKind, optional: bool) -> Self {
+        if let Some(last) = self.args.last() {
+            assert!(last.kind != ArgKind::Text, "Command {}: Text arguments have to come last", self.name);
+            assert!(optional || !last.optional, "Command {}: Required arguments can't follow optional ones", self.name);
+        }
+        self.args.push(ArgSpec { name, kind, optional });
+        self
+    }
+
+    /// The role needed to use this command, by default everyone may use it.
+    pub fn role(mut self, role: Role) -> Self {
+        self.role = role;
+        self
+    }
+
+    /// For example `!mute <player> <duration> [reason]`.
+    pub fn usage(&self) -> String {
+        let mut usage = format!("!{}", self.name);
+        for arg in self.args.iter() {
+            if arg.optional {
+                usage += &format!(" [{}]", arg.name);
+            } else {
+                usage += &format!(" <{}>", arg.name);
+            }
+        }
+        usage
+    }
+
+    /// The name, followed by the aliases.
+    fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        std::iter::once(self.name).chain(self.aliases.iter().copied())
+    }
+
+    /// A name or alias which `other` also uses, if any.
+    fn clash(&self, other: &Command) -> Option<&'static str> {
+        self.names().find(|name| other.names().any(|other| other.eq_ignore_ascii_case(name)))
+    }
+
+    /// How many words of `words` this command's name or one of its aliases matches.
+    fn matches(&self, words: &[&str]) -> Option<usize> {
+        self.names()
+            .filter_map(|name| {
+                let name = name.split(' ').collect_vec();
+                let matched = name.len() <= words.len()
+                    && name.iter().zip(words).all(|(a, b)| a.eq_ignore_ascii_case(b));
+                matched.then_some(name.len())
+            })
+            .max()
+    }
+}
+
+type Handler = Box<dyn Fn(Arc<Bf4Client>, Invocation) -> BoxFuture<'static, ()> + Send + Sync>;
+
+struct Registered {
+    command: Command,
+    handler: Handler,
 }
 
 pub struct Commands {
-    inner: Mutex<Inner>,
+    commands: RwLock<Vec<Arc<Registered>>>,
+    players: Arc<Players>,
+    roles: Arc<Roles>,
+    reloader: Arc<ConfigReloader>,
+    supervisor: Arc<Supervisor>,
 }
 
 impl Commands {
-    pub fn new() -> Self {
+    pub fn new(players: Arc<Players>, roles: Arc<Roles>, reloader: Arc<ConfigReloader>, supervisor: Arc<Supervisor>) -> Self {
         Self {
-            inner: Mutex::new(Inner {
-            }),
+            commands: RwLock::new(Vec::new()),
+            players,
+            roles,
+            reloader,
+            supervisor,
+        }
+    }
+
+    /// Adds a command. The handler is called with the already parsed arguments, only after
+    /// checking the player's role.
+    pub fn register<F, Fut>(&self, command: Command, handler: F)
+    where
+        F: Fn(Arc<Bf4Client>, Invocation) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let mut commands = self.commands.write();
+        if let Some((existing, name)) = commands.iter().find_map(|r| command.clash(&r.command).map(|name| (r, name))) {
+            warn!("Command !{} of plugin {} clashes with !{} of plugin {} over \"{}\", ignoring it.",
+                command.name, command.owner, existing.command.name, existing.command.owner, name);
+            return;
         }
+        debug!("Registered command {}", command.usage());
+        commands.push(Arc::new(Registered {
+            command,
+            handler: Box::new(move |bf4, inv| Box::pin(handler(bf4, inv))),
+        }));
     }
 
-    pub async fn run(&self, bf4: Arc<Bf4Client>) -> RconResult<()> {
-        let mut events = bf4.event_stream().await?;
-        while let Some(event) = events.next().await {
-            match event {
-                Ok(Chat { vis, player, msg }) => {
+    /// Whether `msg` invokes a registered command, no matter if the player may use it.
+    ///
+    /// Plugins which look at chat on their own can use this to ignore commands.
+    pub fn is_command(&self, msg: &str) -> bool {
+        match split_command(msg) {
+            Some(words) => self.find(&words).is_some(),
+            None => false,
+        }
+    }
 
-                },
-                _ => (),
+    /// The best-matching command and how many words its name took up.
+    fn find(&self, words: &[&str]) -> Option<(Arc<Registered>, usize)> {
+        self.commands.read().iter()
+            .filter_map(|r| r.command.matches(words).map(|n| (r.clone(), n)))
+            // on ties, the first registered command wins.
+            .rev()
+            .max_by_key(|(_, n)| *n)
+    }
+
+    fn may_use(&self, player: &Guard<Player, Role>, command: &Command) -> bool {
+        player.has(self.roles.required(command.name, command.role))
+    }
+
+    /// Commands the player may currently use, sorted by name.
+    fn available(&self, player: &Guard<Player, Role>) -> Vec<Arc<Registered>> {
+        self.commands.read().iter()
+            .filter(|r| self.may_use(player, &r.command) && !self.supervisor.is_paused(r.command.owner))
+            .cloned()
+            .sorted_by_key(|r| r.command.name)
+            .collect()
+    }
+
+    async fn handle_chat_msg(&self, bf4: Arc<Bf4Client>, vis: Visibility, player: Player, msg: &str) {
+        let words = match split_command(msg) {
+            Some(words) => words,
+            None => return,
+        };
+        let (registered, n) = match self.find(&words) {
+            Some(found) => found,
+            None => return, // not ours, maybe some plugin is looking at it on its own.
+        };
+        let command = &registered.command;
+
+        let player = self.roles.judge(&player, &bf4).await;
+        if !self.may_use(&player, command) {
+            let _ = bf4.say(format!("You are not allowed to use !{}.", command.name), &*player).await;
+            return;
+        }
+        if self.supervisor.is_paused(command.owner) {
+            let _ = bf4.say(format!("!{} is currently disabled.", command.name), &*player).await;
+            return;
+        }
+
+        match self.parse_args(command, &words[n..]).await {
+            Ok(args) => {
+                debug!("{} ({}) used {}", *player, player.role(), command.usage());
+                (registered.handler)(bf4, Invocation { player, vis, args }).await;
+            },
+            Err(msg) => {
+                let _ = bf4.say(msg, &*player).await;
+            },
+        }
+    }
+
+    /// Parses the words after the command name. `Err` contains the message for the player.
+    async fn parse_args(&self, command: &Command, mut words: &[&str]) -> Result<Args, String> {
+        let mut args = Vec::new();
+        for spec in command.args.iter() {
+            if words.is_empty() {
+                if spec.optional {
+                    break;
+                }
+                return Err(format!("Usage: {}", command.usage()));
             }
+
+            let word = words[0];
+            let arg = match spec.kind {
+                ArgKind::Text => {
+                    let text = words.join(" ");
+                    words = &[];
+                    args.push((spec.name, Arg::Text(text)));
+                    continue;
+                },
+                ArgKind::Word => Arg::Word(word.to_string()),
+                ArgKind::Player => match self.players.get_best_player_match(word).await {
+                    Ok(player) => Arg::Player(player),
+                    Err(MatchError::NoMatches) => return Err(format!("No player matches \"{}\".", word)),
+                    Err(MatchError::TooMany) => return Err(format!("Several players match \"{}\", please be more specific.", word)),
+                },
+                ArgKind::Duration => match humantime::parse_duration(word) {
+                    Ok(duration) => Arg::Duration(duration),
+                    Err(_) => return Err(format!("\"{}\" is not a duration, try for example 30m, 2h or 3d.", word)),
+                },
+                ArgKind::Map => match Map::try_from_short(word.to_ascii_lowercase().as_str()) {
+                    Some(map) => Arg::Map(map),
+                    None => return Err(format!("\"{}\" is not a map name.", word)),
+                },
+            };
+            args.push((spec.name, arg));
+            words = &words[1..];
         }
+        // extra words are ignored.
+        Ok(Args(args))
+    }
 
-        Ok(())
+    fn register_builtin(self: &Arc<Self>) {
+        let myself = self.clone();
+        self.register(
+            Command::new(Self::NAME, "help", "Lists commands, or explains one of them.")
+                .opt_arg("command", ArgKind::Text),
+            move |bf4, inv| {
+                let myself = myself.clone();
+                async move {
+                    let lines = match inv.args.text("command") {
+                        Some(text) => myself.help_for(&inv.player, text),
+                        None => myself.help_overview(&inv.player),
+                    };
+                    let _ = bf4.say_lines(lines, &*inv.player).await;
+                }
+            });
+
+        let myself = self.clone();
+        self.register(
+            Command::new(Self::NAME, "bfox", "Lists the subcommands of !bfox."),
+            move |bf4, inv| {
+                let myself = myself.clone();
+                async move {
+                    let subs = myself.available(&inv.player).iter()
+                        .filter_map(|r| r.command.name.strip_prefix("bfox "))
+                        .join(", ");
+                    let _ = bf4.say(format!("Subcommands of !bfox are: {}", subs), &*inv.player).await;
+                }
+            });
+
+        self.register(
+            Command::new(Self::NAME, "bfox version", "Shows which version of BattleFox is running.")
+                .alias("bfox ver")
+                .alias("bfox v"),
+            |bf4, inv| async move {
+                let _ = bf4.say(format!("BattleFox {}", crate::GIT_DESCRIBE), &*inv.player).await;
+            });
+
+        self.register(
+            Command::new(Self::NAME, "bfox uptime", "Shows how long BattleFox has been running."),
+            |bf4, inv| async move {
+                let elapsed = UPTIME.elapsed();
+                let _ = bf4.say(format!("Uptime: {}", humantime::format_duration(elapsed)), &*inv.player).await;
+            });
+
+        let myself = self.clone();
+        self.register(
+            Command::new(Self::NAME, "bfox reload", "Reloads the config of one plugin, or of all of them.")
+                .opt_arg("plugin", ArgKind::Word)
+                .role(Role::Admin),
+            move |bf4, inv| {
+                let myself = myself.clone();
+                async move {
+                    // either just the one plugin, or everything.
+                    let names = match inv.args.word("plugin") {
+                        Some(name) => vec![name.to_ascii_lowercase()],
+                        None => myself.reloader.names().into_iter().map(|name| name.to_string()).collect(),
+                    };
+                    let mut lines = Vec::new();
+                    for name in names {
+                        match myself.reloader.reload(&bf4, &name).await {
+                            Ok(()) => lines.push(format!("Reloaded {}.", name)),
+                            Err(err) => lines.push(format!("{}: {}", name, err)),
+                        }
+                    }
+                    let _ = bf4.say_lines(lines, &*inv.player).await;
+                }
+            });
+
+        let myself = self.clone();
+        self.register(
+            Command::new(Self::NAME, "bfox role", "Shows your role and EA GUID, moderators can look up others too.")
+                .opt_arg("player", ArgKind::Player),
+            move |bf4, inv| {
+                let myself = myself.clone();
+                async move {
+                    let target = inv.args.player("player").unwrap_or(&*inv.player);
+                    if target != &*inv.player && !inv.player.has(Role::Moderator) {
+                        let _ = bf4.say("You can only look up your own role.", &*inv.player).await;
+                        return;
+                    }
+                    let target = myself.roles.judge(target, &bf4).await;
+                    let _ = bf4.say(format!("{} is {} ({})", *target, target.role(), target.eaid), &*inv.player).await;
+                }
+            });
+
+        let myself = self.clone();
+        self.register(
+            Command::new(Self::NAME, "bfox plugins", "Shows which plugins are running.")
+                .role(Role::Admin),
+            move |bf4, inv| {
+                let myself = myself.clone();
+                async move {
+                    let _ = bf4.say_lines(myself.supervisor.status_lines(), &*inv.player).await;
+                }
+            });
+
+        for (name, help, paused) in [
+            ("bfox plugin enable", "Resumes a plugin which was disabled with !bfox plugin disable.", false),
+            ("bfox plugin disable", "Pauses a plugin until it's enabled again, even across restarts.", true),
+        ] {
+            let myself = self.clone();
+            self.register(
+                Command::new(Self::NAME, name, help)
+                    .arg("plugin", ArgKind::Word)
+                    .role(Role::Admin),
+                move |bf4, inv| {
+                    let myself = myself.clone();
+                    async move {
+                        let name = inv.args.word("plugin").unwrap().to_ascii_lowercase(); // required arg.
+                        let msg = match myself.supervisor.set_paused(&name, paused) {
+                            Ok(()) => format!("Plugin {} is now {}.", name, if paused { "disabled" } else { "enabled" }),
+                            Err(err) => err.to_string(),
+                        };
+                        let _ = bf4.say(msg, &*inv.player).await;
+                    }
+                });
+        }
+    }
+
+    fn help_overview(&self, player: &Guard<Player, Role>) -> Vec<String> {
+        let names = self.available(player).iter()
+            .map(|r| format!("!{}", r.command.name))
+            .collect_vec();
+        let mut lines = vec!["Commands:".to_string()];
+        for chunk in names.chunks(6) {
+            lines.push(chunk.join(", "));
+        }
+        lines.push("Type !help <command> to learn more.".to_string());
+        lines
     }
 
-    pub fn add_stage() {
+    fn help_for(&self, player: &Guard<Player, Role>, text: &str) -> Vec<String> {
+        let text = text.trim_start_matches(&PREFIXES[..]);
+        let words = text.split(' ').filter(|s| !s.is_empty()).collect_vec();
+        match self.find(&words) {
+            Some((registered, _)) if self.may_use(player, &registered.command) => {
+                let command = &registered.command;
+                let mut lines = vec![command.usage(), command.help.to_string()];
+                if !command.aliases.is_empty() {
+                    lines.push(format!("Aliases: {}", command.aliases.iter().map(|a| format!("!{}", a)).join(", ")));
+                }
+                lines
+            },
+            _ => vec![format!("There is no command !{}.", text)],
+        }
+    }
+}
 
+/// Strips the prefix and splits into words. `None` if it's not a command at all.
+fn split_command(msg: &str) -> Option<Vec<&str>> {
+    let rest = msg.strip_prefix(&PREFIXES[..])?;
+    let words = rest.split(' ').filter(|s| !s.is_empty()).collect_vec();
+    if words.is_empty() {
+        None
+    } else {
+        Some(words)
     }
+}
+
+#[async_trait]
+impl Plugin for Commands {
+    const NAME: &'static str = "commands";
+    type Config = ();
 
-    pub fn add_command() {
+    /// Also hosts `!bfox plugin enable`, so pausing would be a one-way street.
+    fn pausable(&self) -> bool { false }
 
+    async fn start(self: &Arc<Self>, _bf4: &Arc<Bf4Client>) {
+        self.register_builtin();
     }
 
-    fn cancel(&self, handle: usize) {
+    async fn event(self: Arc<Self>, bf4: Arc<Bf4Client>, event: Event) -> RconResult<()> {
+        if let Event::Chat { vis, player, msg } = event {
+            self.handle_chat_msg(bf4, vis, player, msg.as_str()).await;
+        }
+        Ok(())
+    }
+}
 
+impl std::fmt::Debug for Commands {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Commands")
+            .field("commands", &self.commands.read().iter().map(|r| r.command.name).collect_vec())
+            .finish()
     }
 }
 
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn matching() {
+        let cmd = Command::new("test", "bfox reload", "").alias("bfox r");
+        assert_eq!(cmd.matches(&["bfox", "reload", "mapvote"]), Some(2));
+        assert_eq!(cmd.matches(&["BFOX", "r"]), Some(2));
+        assert_eq!(cmd.matches(&["bfox"]), None);
+        assert_eq!(cmd.matches(&["reload"]), None);
+    }
+
+    #[test]
+    fn clashes() {
+        let punish = Command::new("teamkilling", "p", "").alias("punish");
+        assert_eq!(Command::new("other", "punish", "").clash(&punish), Some("punish"));
+        assert_eq!(Command::new("other", "x", "").alias("P").clash(&punish), Some("P"));
+        assert_eq!(Command::new("other", "pun", "").alias("ish").clash(&punish), None);
+    }
+
+    #[test]
+    fn usage() {
+        let cmd = Command::new("test", "mute", "")
+            .arg("player", ArgKind::Player)
+            .arg("duration", ArgKind::Duration)
+            .opt_arg("reason", ArgKind::Text);
+        assert_eq!(cmd.usage(), "!mute <player> <duration> [reason]");
+    }
+
+    #[test]
+    fn split() {
+        assert_eq!(split_command("!help  me"), Some(vec!["help", "me"]));
+        assert_eq!(split_command("/v"), Some(vec!["v"]));
+        assert_eq!(split_command("hello"), None);
+        assert_eq!(split_command("!"), None);
+    }
+}
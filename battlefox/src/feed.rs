@@ -0,0 +1,178 @@
+//! A live feed of what's happening on the server, for the web API's WebSocket.
+//!
+//! This carries the RCON events (kills, chat, joins, ...) as well as things BattleFox did itself,
+//! like mapvote updates or teamkill punishments. Plugins publish into it with `Feed::publish`.
+//! The last few items are kept around, so that freshly connected clients don't start out empty.
+
+use std::{collections::VecDeque, sync::Arc};
+
+use battlefield_rcon::bf4::{Envelope, Event};
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::broadcast;
+
+/// How many items we keep around for backfilling.
+pub const BACKFILL_MAX: usize = 200;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FeedItem {
+    /// What kind of thing happened. RCON events are named after the event, e.g. `kill` or
+    /// `round_over`. BattleFox's own things are prefixed with the plugin name, e.g. `mapvote.status`.
+    pub kind: String,
+    pub time: DateTime<Utc>,
+    pub data: Value,
+}
+
+impl FeedItem {
+    /// Whether this item is wanted by a client which asked for `kinds`.
+    ///
+    /// `mapvote` matches all of `mapvote.*`. No kinds at all means everything.
+    pub fn matches(&self, kinds: &[String]) -> bool {
+        kinds.is_empty() || kinds.iter().any(|kind| {
+            self.kind == *kind
+                || (self.kind.starts_with(kind.as_str()) && self.kind[kind.len()..].starts_with('.'))
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Feed {
+    sender: broadcast::Sender<Arc<FeedItem>>,
+    recent: Mutex<VecDeque<Arc<FeedItem>>>,
+}
+
+impl Feed {
+    pub fn new() -> Self {
+        Self {
+            sender: broadcast::channel(BACKFILL_MAX).0,
+            recent: Mutex::new(VecDeque::with_capacity(BACKFILL_MAX)),
+        }
+    }
+
+    pub fn publish(&self, kind: impl Into<String>, data: impl Serialize) {
+        let data = match serde_json::to_value(data) {
+            Ok(data) => data,
+            Err(err) => {
+                error!("Failed to serialize feed item: {}", err);
+                return;
+            }
+        };
+        self.push(FeedItem {
+            kind: kind.into(),
+            time: Utc::now(),
+            data,
+        });
+    }
+
+    fn push(&self, item: FeedItem) {
+        let item = Arc::new(item);
+
+        // keep the lock while sending, so that `subscribe` never sees an item twice or not at all.
+        let mut recent = self.recent.lock();
+        if recent.len() >= BACKFILL_MAX {
+            recent.pop_front();
+        }
+        recent.push_back(item.clone());
+        let _ = self.sender.send(item); // fails only when nobody is listening, which is fine.
+    }
+
+    /// Publishes an RCON event, timestamped with when it was received rather than now.
+    pub fn publish_event(&self, envelope: &Envelope) {
+        let (kind, data) = match split_event(&envelope.event) {
+            Some(split) => split,
+            None => return,
+        };
+        self.push(FeedItem {
+            kind,
+            time: envelope.received_at.into(),
+            data,
+        });
+    }
+
+    /// Returns the last `backfill` items, and a receiver for everything after those.
+    pub fn subscribe(&self, backfill: usize) -> (Vec<Arc<FeedItem>>, broadcast::Receiver<Arc<FeedItem>>) {
+        let recent = self.recent.lock();
+        let skip = recent.len().saturating_sub(backfill);
+        let backfill = recent.iter().skip(skip).cloned().collect();
+        (backfill, self.sender.subscribe())
+    }
+}
+
+/// The kind of an event, as in `FeedItem::kind`, e.g. `kill`.
+pub fn event_kind(event: &Event) -> Option<String> {
+    split_event(event).map(|(kind, _)| kind)
+}
+
+/// An event's kind, and its fields.
+fn split_event(event: &Event) -> Option<(String, Value)> {
+    let (kind, data) = match serde_json::to_value(event) {
+        // struct and newtype variants look like `{ "Kill": { ... } }`.
+        Ok(Value::Object(map)) if map.len() == 1 => map.into_iter().next().unwrap(), // unwrap: len is 1.
+        // unit variants are just `"Variant"`.
+        Ok(Value::String(variant)) => (variant, Value::Null),
+        Ok(other) => {
+            warn!("Unexpected serialization of event {:?}: {}", event, other);
+            return None;
+        },
+        Err(err) => {
+            error!("Failed to serialize event {:?}: {}", event, err);
+            return None;
+        },
+    };
+    Some((snake_case(&kind), data))
+}
+
+/// `RoundOverTeamScores` -> `round_over_team_scores`.
+fn snake_case(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 4);
+    for (i, c) in s.chars().enumerate() {
+        if c.is_ascii_uppercase() {
+            if i > 0 {
+                out.push('_');
+            }
+            out.push(c.to_ascii_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn kinds() {
+        assert_eq!(snake_case("RoundOverTeamScores"), "round_over_team_scores");
+        assert_eq!(snake_case("Kill"), "kill");
+
+        let item = FeedItem { kind: "mapvote.status".to_string(), time: Utc::now(), data: Value::Null };
+        assert!(item.matches(&[]));
+        assert!(item.matches(&["mapvote".to_string()]));
+        assert!(item.matches(&["kill".to_string(), "mapvote.status".to_string()]));
+        assert!(!item.matches(&["map".to_string()]));
+        assert!(!item.matches(&["kill".to_string()]));
+    }
+
+    #[test]
+    fn events_keep_receive_time() {
+        use std::time::{Duration, Instant, SystemTime};
+        use battlefield_rcon::bf4::{Event, ServerId, Team};
+
+        let feed = Feed::new();
+        let received_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        feed.publish_event(&Envelope {
+            seq: 0,
+            received: Instant::now(),
+            received_at,
+            server: ServerId("127.0.0.1:47200".to_string()),
+            event: Event::RoundOver { winning_team: Team::One },
+        });
+        let (backfill, _) = feed.subscribe(BACKFILL_MAX);
+        assert_eq!(backfill[0].kind, "round_over");
+        assert_eq!(backfill[0].time, DateTime::<Utc>::from(received_at));
+    }
+}
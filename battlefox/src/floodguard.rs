@@ -0,0 +1,224 @@
+//! Stops players from flooding the chat, so that it (and mapvote's output) stays readable.
+//!
+//! Sending too many messages in a short time, or the same message over and over, is an offence.
+//! Offences escalate along a ladder of punishments, and are forgotten after a while.
+//! Commands don't count, they're answered by BattleFox and not spam.
+
+use std::{collections::{HashMap, VecDeque}, sync::Arc, time::{Duration, Instant}};
+
+use async_trait::async_trait;
+use battlefield_rcon::{bf4::{Bf4Client, CommmoRose, Eaid, Event}, rcon::RconResult};
+use parking_lot::{Mutex, RwLock};
+use serde::Deserialize;
+
+use crate::commands::Commands;
+use crate::playermute::PlayerMute;
+use crate::punishment::{self, Punishment};
+use crate::roles::{Role, Roles};
+use crate::{ConfigError, Plugin};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    enabled: bool,
+    /// Players with this role or higher may spam.
+    immune: Role,
+    /// More than this many messages within `window` is flooding.
+    max_messages: usize,
+    #[serde(with = "humantime_serde")]
+    window: Duration,
+    /// The same message this many times within `duplicate_window` is flooding too.
+    max_duplicates: usize,
+    #[serde(with = "humantime_serde")]
+    duplicate_window: Duration,
+    /// Offences older than this are forgotten.
+    #[serde(with = "humantime_serde")]
+    forget_after: Duration,
+    punishments: Vec<Punishment>,
+}
+
+impl Config {
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.punishments.is_empty() {
+            return Err(ConfigError::Invalid("needs at least one punishment".to_string()));
+        }
+        if self.max_duplicates < 2 {
+            return Err(ConfigError::Invalid("max_duplicates must be at least 2".to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct History {
+    /// Recent messages, lowercased and trimmed, oldest first.
+    messages: VecDeque<(Instant, String)>,
+    offences: usize,
+    last_offence: Option<Instant>,
+}
+
+impl History {
+    /// Remembers the message, and tells whether it was one too many.
+    fn record(&mut self, config: &Config, now: Instant, msg: &str) -> bool {
+        let keep = config.window.max(config.duplicate_window);
+        while matches!(self.messages.front(), Some((when, _)) if now.saturating_duration_since(*when) > keep) {
+            self.messages.pop_front();
+        }
+        let msg = msg.trim().to_lowercase();
+
+        let within = |window: Duration| self.messages.iter().filter(move |(when, _)| now.saturating_duration_since(*when) <= window);
+        let recent = within(config.window).count() + 1;
+        let duplicates = within(config.duplicate_window).filter(|(_, earlier)| *earlier == msg).count() + 1;
+        self.messages.push_back((now, msg));
+
+        if recent > config.max_messages || duplicates >= config.max_duplicates {
+            // start over, one burst is one offence.
+            self.messages.clear();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether there are no offences left to remember, either none at all or only old ones.
+    fn forgotten(&self, config: &Config, now: Instant) -> bool {
+        self.last_offence.is_none_or(|last| now.saturating_duration_since(last) > config.forget_after)
+    }
+
+    /// Counts an offence, and returns how many there were, including this one.
+    fn offend(&mut self, config: &Config, now: Instant) -> usize {
+        if self.forgotten(config, now) {
+            self.offences = 0;
+        }
+        self.last_offence = Some(now);
+        self.offences += 1;
+        self.offences
+    }
+}
+
+pub struct FloodGuard {
+    config: RwLock<Arc<Config>>,
+    roles: Arc<Roles>,
+    commands: Arc<Commands>,
+    playermute: Arc<PlayerMute>,
+    histories: Mutex<HashMap<Eaid, History>>,
+}
+
+impl FloodGuard {
+    pub fn new(roles: Arc<Roles>, commands: Arc<Commands>, playermute: Arc<PlayerMute>, config: Config) -> Self {
+        Self {
+            config: RwLock::new(Arc::new(config)),
+            roles,
+            commands,
+            playermute,
+            histories: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn config(&self) -> Arc<Config> {
+        self.config.read().clone()
+    }
+}
+
+#[async_trait]
+impl Plugin for FloodGuard {
+    const NAME: &'static str = "floodguard";
+    type Config = Config;
+
+    fn enabled(&self) -> bool {
+        self.config().enabled
+    }
+
+    fn validate(config: &Config) -> Result<(), ConfigError> {
+        config.validate()
+    }
+
+    async fn reconfigure(self: &Arc<Self>, _bf4: &Arc<Bf4Client>, config: Config) -> Result<(), ConfigError> {
+        *self.config.write() = Arc::new(config);
+        Ok(())
+    }
+
+    async fn event(self: Arc<Self>, bf4: Arc<Bf4Client>, event: Event) -> RconResult<()> {
+        match event {
+            Event::Chat { player, msg, .. } => {
+                if self.commands.is_command(msg.as_str()) || CommmoRose::decode(&msg).is_ok() {
+                    return Ok(());
+                }
+                let config = self.config();
+
+                let now = Instant::now();
+                let flooded = self.histories.lock().entry(player.eaid).or_default().record(&config, now, msg.as_str());
+                if !flooded || self.roles.judge(&player, &bf4).await.has(config.immune) {
+                    return Ok(());
+                }
+                let offence = self.histories.lock().entry(player.eaid).or_default().offend(&config, now);
+                // unwrap: validated to be non-empty.
+                let punishment = punishment::step(&config.punishments, offence).unwrap();
+                punishment::punish(&bf4, &self.playermute, Self::NAME, &player, punishment, "Don't spam the chat").await;
+            },
+            Event::Leave { player, .. } => {
+                // keep them if they're still being punished, so that leaving doesn't reset it. Whoever
+                // was kept like that earlier goes once their offences are forgotten.
+                let config = self.config();
+                let now = Instant::now();
+                let mut histories = self.histories.lock();
+                if histories.get(&player.eaid).is_some_and(|history| history.forgotten(&config, now)) {
+                    histories.remove(&player.eaid);
+                }
+                histories.retain(|_, history| history.offences == 0 || !history.forgotten(&config, now));
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for FloodGuard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FloodGuard")
+            .field("config", &self.config())
+            .field("histories", &*self.histories.lock())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn flooding() {
+        let config: Config = serde_yaml::from_str(include_str!("../configs/floodguard.yaml")).unwrap();
+        config.validate().unwrap();
+        let start = Instant::now();
+        let at = |secs: f32| start + Duration::from_secs_f32(secs);
+
+        // many different messages quickly.
+        let mut history = History::default();
+        let flooded = (0..config.max_messages + 1)
+            .map(|i| history.record(&config, at(i as f32 * 0.1), &format!("message {}", i)))
+            .collect::<Vec<_>>();
+        assert_eq!(flooded.iter().filter(|&&f| f).count(), 1);
+        assert!(flooded.last().unwrap());
+
+        // the same ones, slowly.
+        let mut history = History::default();
+        let slow = config.window.as_secs_f32() + 1.0;
+        for i in 0..config.max_duplicates - 1 {
+            assert!(!history.record(&config, at(i as f32 * slow), "gg"));
+        }
+        assert!(history.record(&config, at((config.max_duplicates - 1) as f32 * slow), " GG "));
+
+        // chatting normally.
+        let mut history = History::default();
+        for i in 0..20 {
+            assert!(!history.record(&config, at(i as f32 * 30.0), &format!("message {}", i)));
+        }
+
+        // offences are forgotten.
+        assert!(history.forgotten(&config, at(0.0)));
+        assert_eq!(history.offend(&config, at(0.0)), 1);
+        assert!(!history.forgotten(&config, at(1.0)));
+        assert_eq!(history.offend(&config, at(1.0)), 2);
+        assert_eq!(history.offend(&config, at(1.0) + config.forget_after + Duration::from_secs(1)), 1);
+    }
+}
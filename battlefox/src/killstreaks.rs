@@ -0,0 +1,230 @@
+//! Announces kill streaks (kills without dying), multikills (kills in quick succession), and
+//! when someone ends a streak.
+//!
+//! Streaks are per life: dying, including by suicide, resets them. Announcements are capped, so
+//! that a busy round doesn't drown out the chat.
+
+use std::{collections::{HashMap, VecDeque}, sync::Arc, time::{Duration, Instant}};
+
+use async_trait::async_trait;
+use battlefield_rcon::{bf4::{Bf4Client, Eaid, Event, Player, Visibility}, rcon::RconResult};
+use parking_lot::{Mutex, RwLock};
+use serde::Deserialize;
+
+use crate::{ConfigError, Plugin};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    enabled: bool,
+    /// Announced when a player reaches that many kills without dying.
+    streaks: Vec<Milestone>,
+    /// Announced when a player gets that many kills, each within `multikill_window` of the last.
+    multikills: Vec<Milestone>,
+    #[serde(with = "humantime_serde")]
+    multikill_window: Duration,
+    /// Announce the end of streaks of at least this many kills, unless it was a suicide.
+    ended_from: usize,
+    ended_message: String,
+    /// At most this many announcements within `per`, the rest are dropped.
+    max_messages: usize,
+    #[serde(with = "humantime_serde")]
+    per: Duration,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Milestone {
+    kills: usize,
+    message: String,
+}
+
+impl Config {
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.streaks.iter().chain(self.multikills.iter()).any(|milestone| milestone.kills == 0) {
+            return Err(ConfigError::Invalid("milestones need at least one kill".to_string()));
+        }
+        if self.multikills.iter().any(|milestone| milestone.kills == 1) {
+            return Err(ConfigError::Invalid("a multikill needs at least two kills".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Fills in `{name}`, `{kills}` and `{by}`.
+fn fill(message: &str, name: &str, kills: usize, by: &str) -> String {
+    message
+        .replace("{name}", name)
+        .replace("{kills}", &kills.to_string())
+        .replace("{by}", by)
+}
+
+#[derive(Debug, Default)]
+struct Streak {
+    kills: usize,
+    /// Kills in the current multikill.
+    chain: usize,
+    last_kill: Option<Instant>,
+}
+
+#[derive(Debug, Default)]
+struct Streaks {
+    streaks: HashMap<Eaid, Streak>,
+}
+
+impl Streaks {
+    /// Counts the kill, and returns what to announce.
+    fn kill(&mut self, config: &Config, killer: Option<&Player>, victim: &Player, now: Instant) -> Vec<String> {
+        let mut messages = Vec::new();
+
+        let ended = self.streaks.remove(&victim.eaid);
+        let killer = match killer {
+            Some(killer) if killer != victim => killer,
+            _ => return messages, // suicide, or the server killed them. Nobody to credit for ending it.
+        };
+        if let Some(ended) = ended {
+            if ended.kills >= config.ended_from {
+                messages.push(fill(&config.ended_message, victim.name.as_str(), ended.kills, killer.name.as_str()));
+            }
+        }
+
+        let streak = self.streaks.entry(killer.eaid).or_default();
+        streak.kills += 1;
+        if streak.last_kill.is_some_and(|last| now.saturating_duration_since(last) <= config.multikill_window) {
+            streak.chain += 1;
+        } else {
+            streak.chain = 1;
+        }
+        streak.last_kill = Some(now);
+
+        let (kills, chain) = (streak.kills, streak.chain);
+        if let Some(milestone) = config.streaks.iter().find(|milestone| milestone.kills == kills) {
+            messages.push(fill(&milestone.message, killer.name.as_str(), kills, ""));
+        }
+        if let Some(milestone) = config.multikills.iter().find(|milestone| milestone.kills == chain) {
+            messages.push(fill(&milestone.message, killer.name.as_str(), chain, ""));
+        }
+        messages
+    }
+}
+
+pub struct KillStreaks {
+    config: RwLock<Arc<Config>>,
+    streaks: Mutex<Streaks>,
+    /// When recent announcements went out, for the cap.
+    announced: Mutex<VecDeque<Instant>>,
+}
+
+impl KillStreaks {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config: RwLock::new(Arc::new(config)),
+            streaks: Mutex::new(Streaks::default()),
+            announced: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    fn config(&self) -> Arc<Config> {
+        self.config.read().clone()
+    }
+
+    /// Whether another announcement fits under the cap. Counts it if so.
+    fn may_announce(&self, config: &Config, now: Instant) -> bool {
+        let mut announced = self.announced.lock();
+        while matches!(announced.front(), Some(&when) if now.saturating_duration_since(when) > config.per) {
+            announced.pop_front();
+        }
+        if announced.len() < config.max_messages {
+            announced.push_back(now);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[async_trait]
+impl Plugin for KillStreaks {
+    const NAME: &'static str = "killstreaks";
+    type Config = Config;
+
+    fn enabled(&self) -> bool {
+        self.config().enabled
+    }
+
+    fn validate(config: &Config) -> Result<(), ConfigError> {
+        config.validate()
+    }
+
+    async fn reconfigure(self: &Arc<Self>, _bf4: &Arc<Bf4Client>, config: Config) -> Result<(), ConfigError> {
+        *self.config.write() = Arc::new(config);
+        Ok(())
+    }
+
+    async fn event(self: Arc<Self>, bf4: Arc<Bf4Client>, event: Event) -> RconResult<()> {
+        match event {
+            Event::Kill { killer, victim, .. } => {
+                let config = self.config();
+                let now = Instant::now();
+                let messages = self.streaks.lock().kill(&config, killer.as_ref(), &victim, now);
+                for message in messages {
+                    if self.may_announce(&config, now) {
+                        let _ = bf4.say(message, Visibility::All).await;
+                    }
+                }
+            },
+            Event::Leave { player, .. } => {
+                self.streaks.lock().streaks.remove(&player.eaid);
+            },
+            Event::RoundOver { .. } | Event::LevelLoaded { .. } => {
+                self.streaks.lock().streaks.clear();
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for KillStreaks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KillStreaks")
+            .field("config", &self.config())
+            .field("streaks", &*self.streaks.lock())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testutil::player;
+
+    #[test]
+    fn streaks() {
+        let config: Config = serde_yaml::from_str(include_str!("../configs/killstreaks.yaml")).unwrap();
+        config.validate().unwrap();
+        let (alice, bob) = (player("Alice", 1), player("Bobby", 2));
+        let start = Instant::now();
+        let slow = config.multikill_window + Duration::from_secs(1);
+
+        let mut streaks = Streaks::default();
+        let mut all = Vec::new();
+        for i in 0..10 {
+            all.extend(streaks.kill(&config, Some(&alice), &bob, start + slow * i));
+        }
+        assert_eq!(all, vec!["Alice is on a killing spree! (5 kills)", "Alice is unstoppable! (10 kills)"]);
+
+        // a double kill, then her streak ends.
+        let later = start + slow * 20;
+        assert!(streaks.kill(&config, Some(&alice), &bob, later).is_empty());
+        assert_eq!(streaks.kill(&config, Some(&alice), &bob, later + Duration::from_secs(1)), vec!["Alice got a double kill!"]);
+        assert_eq!(streaks.kill(&config, Some(&bob), &alice, later), vec!["Bobby ended Alice's streak of 12 kills!"]);
+        assert_eq!(streaks.streaks[&bob.eaid].kills, 1);
+        assert!(!streaks.streaks.contains_key(&alice.eaid));
+
+        // suicides end streaks quietly.
+        for i in 0..5 {
+            streaks.kill(&config, Some(&bob), &alice, later + slow * i);
+        }
+        assert!(streaks.kill(&config, Some(&bob), &bob, later + slow * 6).is_empty());
+        assert!(!streaks.streaks.contains_key(&bob.eaid));
+    }
+}
@@ -9,7 +9,7 @@ use parking_lot::RwLock;
 use serde::{Serialize, Deserialize};
 use battlelog::{get_loadout, get_users, search_user};
 
-use crate::Plugin;
+use crate::{ConfigError, Plugin};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
@@ -22,7 +22,7 @@ struct Inner {
 }
 
 pub struct LoadoutEnforcer {
-    config: Config,
+    config: RwLock<Arc<Config>>,
 
     inner: RwLock<Inner>, // no Arc here, because we pass around `Arc<LoadoutEnforcer>`, so it would be redundant.
 }
@@ -30,13 +30,17 @@ pub struct LoadoutEnforcer {
 impl LoadoutEnforcer {
     pub fn new(config: Config) -> Self {
         Self {
-            config,
+            config: RwLock::new(Arc::new(config)),
             inner: RwLock::new(Inner {
                 persona_ids: HashMap::new(),
             }),
         }
     }
 
+    fn config(&self) -> Arc<Config> {
+        self.config.read().clone()
+    }
+
     async fn update_players(self: &Arc<Self>, bf4: &Arc<Bf4Client>) {
         trace!("[{}] Updating players/personaId map.", Self::NAME);
 
@@ -119,15 +123,26 @@ impl LoadoutEnforcer {
 #[async_trait]
 impl Plugin for LoadoutEnforcer {
     const NAME: &'static str = "loadoutenforcer";
+    type Config = Config;
 
     fn enabled(&self) -> bool {
-        self.config.enabled
+        self.config.read().enabled
+    }
+
+    async fn reconfigure(self: &Arc<Self>, _bf4: &Arc<Bf4Client>, config: Config) -> Result<(), ConfigError> {
+        *self.config.write() = Arc::new(config);
+        Ok(())
     }
 
     async fn start(self: &Arc<Self>, bf4: &Arc<Bf4Client>) {
         self.update_players(bf4).await;
     }
 
+    async fn resume(self: &Arc<Self>, bf4: &Arc<Bf4Client>) {
+        // we missed joins and leaves while paused.
+        self.update_players(bf4).await;
+    }
+
     async fn event(self: Arc<Self>, bf4: Arc<Bf4Client>, ev: Event) -> RconResult<()> {
         match ev {
             Event::Authenticated { player } => {
@@ -200,9 +215,10 @@ impl Plugin for LoadoutEnforcer {
                         if selected_kit < 4 {
                             let weapon_codes = &current_loadout.kits[selected_kit];
                             trace!("[{}] {} > Weapon codes: {:?}", Self::NAME, player.name, weapon_codes);
+                            let config = self.config();
                             for weapon_code in weapon_codes.iter() {
-                                if self.config.banned_weapons.contains_key(weapon_code) {
-                                    let kill_message = &self.config.banned_weapons[weapon_code];
+                                if config.banned_weapons.contains_key(weapon_code) {
+                                    let kill_message = &config.banned_weapons[weapon_code];
 
                                     let _ = dbg!(bf4.kill(player.name.clone()).await);
                                     let _ = bf4.say(kill_message.to_string(), player.clone()).await;
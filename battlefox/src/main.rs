@@ -8,22 +8,23 @@ extern crate battlelog;
 
 use ascii::{IntoAsciiString};
 use async_trait::async_trait;
-use battlefield_rcon::bf4::Event;
+use battlefield_rcon::bf4::{Envelope, Event};
 use battlefield_rcon::bf4::error::Bf4Error;
 use battlefield_rcon::rcon::RconResult;
 use battlefox_database::BfoxContext;
 use dotenv::dotenv;
-use futures::StreamExt;
+use futures::{FutureExt, StreamExt};
 use itertools::Itertools;
 use players::Players;
-use serde::{de::DeserializeOwned};
+use serde::{de::DeserializeOwned, Serialize};
 use thiserror::Error;
 use weaponforcer::WeaponEnforcer;
 use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Write};
+use std::panic::AssertUnwindSafe;
 use std::path::Path;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use std::{env::var, sync::Arc};
 use vips::Vips;
 
@@ -34,18 +35,45 @@ use mapvote::{
     Mapvote,
 };
 
-use crate::admins::Admins;
+use crate::afkkicker::AfkKicker;
+use crate::announcer::Announcer;
+use crate::balancer::Balancer;
 use crate::ban_enforcer::BanEnforcer;
+use crate::chatfilter::ChatFilter;
+use crate::chatlog::ChatLog;
+use crate::commands::Commands;
+use crate::feed::Feed;
+use crate::floodguard::FloodGuard;
+use crate::killstreaks::KillStreaks;
 use crate::loadoutforcer::LoadoutEnforcer;
+use crate::pingkicker::PingKicker;
 use crate::playermute::PlayerMute;
+use crate::profiles::Profiles;
+use crate::reload::ConfigReloader;
+use crate::roles::Roles;
+use crate::roundsummary::RoundSummary;
+use crate::sessionstats::SessionStats;
+use crate::supervisor::{RestartPolicy, Supervisor};
 use crate::teamkilling::TeamKilling;
+use crate::vipmanager::VipManager;
+use crate::webapi::WebApi;
+use crate::welcome::Welcome;
 
+pub mod afkkicker;
+pub mod announcer;
+pub mod balancer;
 pub mod guard;
-// pub mod commands;
+pub mod feed;
+pub mod floodguard;
+pub mod chatfilter;
+pub mod chatlog;
+pub mod commands;
+pub mod killstreaks;
 pub mod mapmanager;
 pub mod mapvote;
+#[cfg(feature = "metrics")]
+pub mod metrics;
 pub mod vips;
-pub mod admins;
 pub mod players;
 mod stv;
 pub mod weaponforcer;
@@ -53,9 +81,23 @@ pub mod playerreport;
 pub mod humanlang;
 mod logging;
 mod playermute;
+pub mod pingkicker;
 mod teamkilling;
 mod ban_enforcer;
 pub mod loadoutforcer;
+pub mod profiles;
+pub mod punishment;
+pub mod reload;
+pub mod roles;
+pub mod roundsummary;
+pub mod sessionstats;
+pub mod state;
+pub mod supervisor;
+#[cfg(test)]
+mod testutil;
+pub mod vipmanager;
+pub mod webapi;
+pub mod welcome;
 
 // Instead of `cargo build`, set env vars:
 //     RUSTFLAGS='--cfg take_git_version_from_env'
@@ -85,11 +127,45 @@ fn get_rcon_coninfo() -> anyhow::Result<RconConnectionInfo> {
 }
 
 #[derive(Error, Debug)]
-enum ConfigError {
+pub enum ConfigError {
     #[error("Failed to deserialize config.")]
     Serde(#[from] serde_yaml::Error),
     #[error("Failed to open config file")]
     Io(#[from] std::io::Error),
+    #[error("Invalid config: {0}")]
+    Invalid(String),
+    #[error("Plugin does not support reloading its config, restart BattleFox instead")]
+    ReloadUnsupported,
+}
+
+fn config_path(plugin_name: &str) -> String {
+    format!("configs/{}.yaml", plugin_name)
+}
+
+/// Where plugins keep what they need to remember across restarts, as opposed to configs,
+/// which are written by humans.
+fn state_path(name: &str) -> String {
+    format!("state/{}.yaml", name)
+}
+
+/// Writes some state to `state/<name>.yaml`, read it back with `load_config(state_path(name))`.
+///
+/// Plugins should rather use `state::save`, which also records a version.
+fn save_state<T: Serialize>(name: &str, state: &T) -> anyhow::Result<()> {
+    std::fs::create_dir_all("state")?;
+    // write to a temporary file first, so that being killed mid-write doesn't leave half a file.
+    let path = state_path(name);
+    let tmp = format!("{}.tmp", path);
+    let mut file = File::create(&tmp)?;
+    file.write_all(serde_yaml::to_string(state)?.as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(tmp, path)?;
+    Ok(())
+}
+
+fn invalid_at_startup(name: &str, err: ConfigError) -> ConfigError {
+    error!("{}: {}", config_path(name), err);
+    err
 }
 
 fn load_config<T: DeserializeOwned>(path: impl AsRef<Path>) -> Result<T, ConfigError> {
@@ -104,7 +180,8 @@ fn load_config<T: DeserializeOwned>(path: impl AsRef<Path>) -> Result<T, ConfigE
 /// This is the trait you want to implement when you're creating a new plugin.
 ///
 /// The simplest way to use this trait is to implement the `event` function.
-/// The `run` function can be overridden, but it invokes the `event` function and does some common
+/// If you also need the sequence number or receive time of events, implement `envelope` instead.
+/// The `run` function can be overridden, but it invokes the `envelope` function and does some common
 /// error-handling. If you override `run`, then you lose that.
 ///
 /// Remember to do something like `async fn enabled(&self) -> bool { self.config.enabled }`
@@ -112,25 +189,77 @@ fn load_config<T: DeserializeOwned>(path: impl AsRef<Path>) -> Result<T, ConfigE
 #[async_trait]
 pub trait Plugin : Send + Sync + 'static {
     const NAME: &'static str;
+
+    /// What gets deserialized from `configs/<NAME>.yaml`. Use `()` if there is no config file.
+    type Config: DeserializeOwned + Send + 'static;
+
     fn enabled(&self) -> bool { true }
 
+    /// Checks a config before it's used, both at startup and on reload. At startup, an invalid
+    /// config keeps BattleFox from starting. On reload, the old config stays in place.
+    ///
+    /// Put everything here which would make the plugin panic or misbehave later on, so that the
+    /// rest of the plugin can rely on it.
+    fn validate(_config: &Self::Config) -> Result<(), ConfigError> { Ok(()) }
+
+    /// Called when `configs/<NAME>.yaml` has changed (or an admin asked for a reload), was
+    /// deserialized successfully and passed `validate`.
+    ///
+    /// When returning `Err`, the old config must stay in place.
+    ///
+    /// Changes to `enabled` only take effect after a restart.
+    async fn reconfigure(self: &Arc<Self>, _bf4: &Arc<Bf4Client>, _config: Self::Config) -> Result<(), ConfigError> {
+        Err(ConfigError::ReloadUnsupported)
+    }
+
+    /// What the supervisor should do when `run` returns `Err` or panics.
+    fn restart_policy(&self) -> RestartPolicy { RestartPolicy::default() }
+
+    /// Whether admins may pause this plugin at runtime.
+    fn pausable(&self) -> bool { true }
+
+    /// Called when an admin paused the plugin. From now on, `run` isn't running anymore.
+    ///
+    /// Stop anything which runs in the background here, but keep the plugin's state.
+    async fn pause(self: &Arc<Self>, _bf4: &Arc<Bf4Client>) { }
+
+    /// Called when an admin resumed the plugin, right before `run` is invoked again.
+    ///
+    /// Events which happened while paused were missed, so catch up here if needed.
+    async fn resume(self: &Arc<Self>, _bf4: &Arc<Bf4Client>) { }
+
+    /// Called once before `run`, only if the plugin is enabled.
+    ///
+    /// When the plugin gets restarted after a failure, only `run` is called again, so this is the
+    /// place to spawn background tasks.
     async fn start(self: &Arc<Self>, _bf4: &Arc<Bf4Client>) { }
 
+    /// Called every few minutes and on shutdown, only if the plugin is enabled.
+    ///
+    /// Save whatever should survive a restart here with `state::save`, and load it back with
+    /// `state::load` in the constructor.
+    async fn persist(self: &Arc<Self>, _bf4: &Arc<Bf4Client>) { }
+
     /// You *can* implement this, but you may be more interested in `event`.
     ///
-    /// In case `run` is overridden, `event` does nothing.
+    /// In case `run` is overridden, `envelope` and `event` do nothing.
     async fn run(self: Arc<Self>, bf4: Arc<Bf4Client>) -> RconResult<()> {
         info!("Plugin {} is {}.", Self::NAME, if self.enabled() { "enabled" } else { "disabled" });
         if self.enabled() {
-            self.start(&bf4).await;
-
-            let mut stream = bf4.event_stream().await?;
-            while let Some(event) = stream.next().await {
-                match event {
-                    Ok(event) => {
+            let mut stream = bf4.envelope_stream().await?;
+            while let Some(envelope) = stream.next().await {
+                match envelope {
+                    Ok(envelope) => {
                         let bf4 = bf4.clone();
                         let self_clone = self.clone();
-                        tokio::spawn(async move { self_clone.event(bf4, event).await });
+                        tokio::spawn(async move {
+                            // a panic here only loses this one event, the plugin keeps running.
+                            if let Err(panic) = AssertUnwindSafe(self_clone.envelope(bf4, envelope)).catch_unwind().await {
+                                error!("[{}] panicked while handling an event: {}", Self::NAME, supervisor::panic_message(&*panic));
+                                #[cfg(feature = "metrics")]
+                                metrics::PLUGIN_ERRORS.with_label_values(&[Self::NAME]).inc();
+                            }
+                        });
                     },
                     Err(Bf4Error::Rcon(err)) => {
                         // This is likely a disconnected event thing.
@@ -146,6 +275,16 @@ pub trait Plugin : Send + Sync + 'static {
         Ok(())
     }
 
+    /// Like `event`, but with the sequence number, receive time and server of the event.
+    ///
+    /// Events get handled in separately spawned tasks, so they may be processed out of order.
+    /// Use `envelope.received` rather than `Instant::now()` when timing matters.
+    ///
+    /// In case `envelope` is overridden, `event` does nothing.
+    async fn envelope(self: Arc<Self>, bf4: Arc<Bf4Client>, envelope: Envelope) -> RconResult<()> {
+        self.event(bf4, envelope.event).await
+    }
+
     async fn event(self: Arc<Self>, _bf4: Arc<Bf4Client>, _ev: Event) -> RconResult<()> {
         // do nothing unless overridden.
         Ok(())
@@ -154,9 +293,22 @@ pub trait Plugin : Send + Sync + 'static {
 
 /// Just a helper trait to avoid trait object and associated constants clashing.
 #[async_trait]
-trait Plugin2: Sync + Send {
+pub(crate) trait Plugin2: Sync + Send {
     async fn run(self: Arc<Self>, bf4: Arc<Bf4Client>) -> RconResult<()>;
+    /// Calls `Plugin::start`, but only if the plugin is enabled.
+    async fn start(self: Arc<Self>, bf4: Arc<Bf4Client>);
     fn name(&self) -> &'static str;
+    fn enabled(&self) -> bool;
+    fn restart_policy(&self) -> RestartPolicy;
+    fn pausable(&self) -> bool;
+    /// Calls `Plugin::pause`, but only if the plugin is enabled.
+    async fn pause(self: Arc<Self>, bf4: Arc<Bf4Client>);
+    /// Calls `Plugin::resume`, but only if the plugin is enabled.
+    async fn resume(self: Arc<Self>, bf4: Arc<Bf4Client>);
+    /// Loads `configs/<NAME>.yaml` again and hands it to `Plugin::reconfigure`.
+    async fn reload_config(self: Arc<Self>, bf4: Arc<Bf4Client>) -> Result<(), ConfigError>;
+    /// Calls `Plugin::persist`, but only if the plugin is enabled.
+    async fn persist(self: Arc<Self>, bf4: Arc<Bf4Client>);
 }
 
 #[async_trait]
@@ -166,66 +318,197 @@ impl<T: Plugin> Plugin2 for T {
         // todo!()
     }
 
+    async fn start(self: Arc<Self>, bf4: Arc<Bf4Client>) {
+        if Plugin::enabled(&*self) {
+            Plugin::start(&self, &bf4).await;
+        }
+    }
+
     fn name(&self) -> &'static str {
         Self::NAME
     }
+
+    fn enabled(&self) -> bool {
+        Plugin::enabled(self)
+    }
+
+    fn restart_policy(&self) -> RestartPolicy {
+        Plugin::restart_policy(self)
+    }
+
+    fn pausable(&self) -> bool {
+        Plugin::pausable(self)
+    }
+
+    async fn pause(self: Arc<Self>, bf4: Arc<Bf4Client>) {
+        if Plugin::enabled(&*self) {
+            Plugin::pause(&self, &bf4).await;
+        }
+    }
+
+    async fn resume(self: Arc<Self>, bf4: Arc<Bf4Client>) {
+        if Plugin::enabled(&*self) {
+            Plugin::resume(&self, &bf4).await;
+        }
+    }
+
+    async fn reload_config(self: Arc<Self>, bf4: Arc<Bf4Client>) -> Result<(), ConfigError> {
+        let config: T::Config = load_config(config_path(Self::NAME))?;
+        T::validate(&config)?;
+        self.reconfigure(&bf4, config).await
+    }
+
+    async fn persist(self: Arc<Self>, bf4: Arc<Bf4Client>) {
+        if Plugin::enabled(&*self) {
+            Plugin::persist(&self, &bf4).await;
+        }
+    }
+}
+
+/// How often plugins get to save their state, in addition to on shutdown.
+const PERSIST_INTERVAL: Duration = Duration::from_secs(60 * 2);
+
+async fn persist_all(plugins: &[Arc<dyn Plugin2>], bf4: &Arc<Bf4Client>) {
+    for plugin in plugins {
+        // a panic here shouldn't keep the other plugins from saving.
+        if let Err(panic) = AssertUnwindSafe(plugin.clone().persist(bf4.clone())).catch_unwind().await {
+            error!("[{}] panicked while saving its state: {}", plugin.name(), supervisor::panic_message(&*panic));
+        }
+    }
+}
+
+/// Ctrl+C, or SIGTERM (e.g. `docker stop` or systemd) on Unix.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => (),
+                    _ = terminate.recv() => (),
+                }
+                return;
+            },
+            Err(err) => error!("Can't listen for SIGTERM, only for Ctrl+C: {}", err),
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Hands every event to the feed, and counts it for the metrics. Each plugin has its own stream,
+/// so doing this in a plugin would count each event once per plugin, and would publish in whatever
+/// order the plugin tasks happen to run. Here, events go out in `Envelope::seq` order.
+async fn dispatch_events(bf4: Arc<Bf4Client>, feed: Arc<Feed>) {
+    let mut stream = match bf4.envelope_stream().await {
+        Ok(stream) => stream,
+        Err(err) => {
+            error!("Can't publish events to the feed: {:?}", err);
+            return;
+        },
+    };
+    while let Some(envelope) = stream.next().await {
+        match envelope {
+            Ok(envelope) => {
+                #[cfg(feature = "metrics")]
+                if let Some(kind) = feed::event_kind(&envelope.event) {
+                    metrics::EVENTS.with_label_values(&[&kind]).inc();
+                }
+                feed.publish_event(&envelope);
+            },
+            Err(Bf4Error::Rcon(_)) => break,
+            Err(_) => (),
+        }
+    }
 }
 
 pub struct App {
     plugins: BTreeMap<String, Arc<dyn Plugin2>>,
+    reloader: Arc<ConfigReloader>,
+    supervisor: Arc<Supervisor>,
+    feed: Arc<Feed>,
 }
 
 impl App {
     pub fn new() -> Self {
         Self {
-            plugins: BTreeMap::new()
+            plugins: BTreeMap::new(),
+            reloader: Arc::new(ConfigReloader::new()),
+            supervisor: Arc::new(Supervisor::new()),
+            feed: Arc::new(Feed::new()),
         }
     }
 
-    fn has_plugin<P: Plugin, C: DeserializeOwned>(&mut self, f: impl FnOnce(C) -> P) -> Result<Arc<P>, ConfigError> {
-        let config: C = load_config(format!("configs/{}.yaml", P::NAME))?;
+    /// Handle for reloading plugin configs, e.g. from an admin command.
+    pub fn config_reloader(&self) -> Arc<ConfigReloader> {
+        self.reloader.clone()
+    }
+
+    /// Handle for looking at which plugins are running, and for pausing them.
+    pub fn supervisor(&self) -> Arc<Supervisor> {
+        self.supervisor.clone()
+    }
+
+    /// The live feed, which gets every RCON event once `run` is called.
+    pub fn feed(&self) -> Arc<Feed> {
+        self.feed.clone()
+    }
+
+    fn has_plugin<P: Plugin>(&mut self, f: impl FnOnce(P::Config) -> P) -> Result<Arc<P>, ConfigError> {
+        let config: P::Config = load_config(config_path(P::NAME))?;
+        P::validate(&config).map_err(|err| invalid_at_startup(P::NAME, err))?;
         self.has_plugin_noconfig(f(config))
     }
 
-    fn has_plugin_arc<P: Plugin, C: DeserializeOwned>(&mut self, f: impl FnOnce(C) -> Arc<P>) -> Result<Arc<P>, ConfigError> {
-        let config: C = load_config(format!("configs/{}.yaml", P::NAME))?;
+    fn has_plugin_arc<P: Plugin>(&mut self, f: impl FnOnce(P::Config) -> Arc<P>) -> Result<Arc<P>, ConfigError> {
+        let config: P::Config = load_config(config_path(P::NAME))?;
+        P::validate(&config).map_err(|err| invalid_at_startup(P::NAME, err))?;
         let p = f(config);
-        let exists = self.plugins.insert(P::NAME.to_string(), p.clone());
-        if exists.is_some() {
-            panic!("Double-loading of plugins is forbidden. Plugin: {}", P::NAME);
-        }
+        self.insert_plugin(p.clone());
         Ok(p)
     }
 
     fn has_plugin_noconfig<P: Plugin>(&mut self, p: P) -> Result<Arc<P>, ConfigError> {
         let p = Arc::new(p);
+        self.insert_plugin(p.clone());
+        Ok(p)
+    }
 
+    fn insert_plugin<P: Plugin>(&mut self, p: Arc<P>) {
         let exists = self.plugins.insert(P::NAME.to_string(), p.clone());
         if exists.is_some() {
             panic!("Double-loading of plugins is forbidden. Plugin: {}", P::NAME);
         }
-
-        Ok(p)
+        self.reloader.register(P::NAME, p);
     }
 
-    /// Invoke `run` on every loaded plugin, then wait for completion.
+    /// Invoke `run` on every loaded plugin under supervision, then wait for completion.
+    ///
+    /// Returns early when a plugin with `RestartPolicy::StopAll` fails.
     pub async fn run(&mut self, bf4: Arc<Bf4Client>) {
-        let jhs = self.plugins.iter()
-            .map(|(name, p)| {
-                let bf4 = bf4.clone();
-                let p = p.clone();
-                let jh = tokio::spawn(async move { p.run(bf4).await });
-                (name, jh)
-            })
-            .collect_vec();
-
-        // TODO: Abort everything when even just one jh returns prematurely and with Err.
-        for (name, jh) in jhs {
-            match jh.await.unwrap() {
-                Ok(_) => (),
-                Err(err) => error!("Plugin {} has quit with error: {:?}", name, err),
+        let reloader = self.reloader.clone();
+        let bf4_clone = bf4.clone();
+        tokio::spawn(async move { reloader.watch(bf4_clone).await });
+
+        let plugins = self.plugins.values().cloned().collect_vec();
+        let bf4_clone = bf4.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(PERSIST_INTERVAL).await;
+                persist_all(&plugins, &bf4_clone).await;
             }
-        }
+        });
+
+        tokio::spawn(dispatch_events(bf4.clone(), self.feed.clone()));
+
+        let plugins = self.plugins.values().cloned().collect_vec();
+        self.supervisor.clone().run(plugins, bf4).await;
+    }
+
+    /// Lets every plugin save its state, see `Plugin::persist`.
+    pub async fn persist(&self, bf4: Arc<Bf4Client>) {
+        let plugins = self.plugins.values().cloned().collect_vec();
+        persist_all(&plugins, &bf4).await;
     }
 }
 
@@ -253,27 +536,49 @@ async fn main() -> anyhow::Result<()> {
 
     // Initialize plugins and their dependencies.
     let mut app = App::new();
-    let admins = app.has_plugin(Admins::new)?;
+    let reloader = app.config_reloader();
+    let supervisor = app.supervisor();
+    let feed = app.feed();
     let players = app.has_plugin_noconfig(Players::new())?;
     let vips = app.has_plugin_noconfig(Vips::new())?;
+    let roles = app.has_plugin(|c| Roles::new(vips.clone(), roles::load_legacy_admins(), c))?;
+    let commands = app.has_plugin_noconfig(Commands::new(players.clone(), roles.clone(), reloader, supervisor.clone()))?;
+    let _vipmanager = app.has_plugin(|c| VipManager::new(vips.clone(), commands.clone(), c))?;
     let _weaponforcer = app.has_plugin(WeaponEnforcer::new)?;
     let _loadoutenforcer = app.has_plugin(LoadoutEnforcer::new)?;
     // let _playerreport = app.has_plugin(|c| PlayerReport::new(players.clone(), rabbitmq, c))?;
-    let _playermute = app.has_plugin(|c| PlayerMute::new(db.clone(), players.clone(), c))?;
+    let playermute = app.has_plugin(|c| PlayerMute::new(db.clone(), commands.clone(), c))?;
     let mapman = app.has_plugin(MapManager::new)?;
-    let _mapvote = app.has_plugin_arc(|c: MapVoteConfigJson|
-        Mapvote::new(mapman, vips, players.clone(), admins.clone(), MapVoteConfig::from_json(c))
+    let mapvote = app.has_plugin_arc(|c: MapVoteConfigJson|
+        Mapvote::new(mapman.clone(), vips, players.clone(), commands.clone(), feed.clone(), MapVoteConfig::from_json(c))
     )?;
-    let _tks = app.has_plugin(|c| TeamKilling::new(c, players.clone()))?;
-    let _ban_enforcer = app.has_plugin(|c| BanEnforcer::new(c, players, db))?;
+    let _tks = app.has_plugin(|c| TeamKilling::new(c, players.clone(), feed.clone(), commands.clone(), playermute.clone(), db.clone()))?;
+    let _ban_enforcer = app.has_plugin(|c| BanEnforcer::new(c, players.clone(), db.clone()))?;
+    let _announcer = app.has_plugin(|c| Announcer::new(mapvote.clone(), c))?;
+    let _welcome = app.has_plugin(|c| Welcome::new(db.clone(), roles.clone(), c))?;
+    let _balancer = app.has_plugin(|c| Balancer::new(roles.clone(), mapman.clone(), db.clone(), c))?;
+    let _pingkicker = app.has_plugin(|c| PingKicker::new(roles.clone(), mapman.clone(), c))?;
+    let _afkkicker = app.has_plugin(|c| AfkKicker::new(roles.clone(), c))?;
+    let _chatfilter = app.has_plugin(|c| ChatFilter::new(roles.clone(), commands.clone(), playermute.clone(), c))?;
+    let _floodguard = app.has_plugin(|c| FloodGuard::new(roles, commands.clone(), playermute, c))?;
+    let _profiles = app.has_plugin(|c| Profiles::new(mapman.clone(), c))?;
+    let _killstreaks = app.has_plugin(KillStreaks::new)?;
+    let _roundsummary = app.has_plugin(|c| RoundSummary::new(players.clone(), feed.clone(), c))?;
+    let _sessionstats = app.has_plugin(|c| SessionStats::new(db.clone(), commands.clone(), c))?;
+    let _chatlog = app.has_plugin(|c| ChatLog::new(db.clone(), c))?;
+    let _webapi = app.has_plugin(|c| WebApi::new(players, mapman, mapvote, supervisor, feed, c))?;
 
     // Connect to RCON.
     info!("Connecting to {}:{} with password ***...", rconinfo.ip, rconinfo.port);
     let bf4 = Bf4Client::connect((rconinfo.ip, rconinfo.port), rconinfo.password, harmless).await.unwrap();
     trace!("Connected!");
 
-    // Actually start all the plugins and wait for them to finish.
-    app.run(bf4).await;
+    // Actually start all the plugins and wait for them to finish, or for Ctrl+C.
+    tokio::select! {
+        _ = app.run(bf4.clone()) => (),
+        _ = shutdown_signal() => info!("Shutting down..."),
+    }
+    app.persist(bf4).await;
 
     Ok(())
 }
@@ -4,6 +4,7 @@ use async_trait::async_trait;
 use battlefield_rcon::bf4::GameMode;
 use itertools::Itertools;
 use lerp::Lerp;
+use parking_lot::RwLock;
 use serde::{Deserialize, Serialize};
 use std::sync::Mutex;
 use std::{cmp::Ordering, convert::TryFrom, sync::Arc, time::Duration};
@@ -15,10 +16,21 @@ use tokio::time::sleep;
 use self::{
     pool::{MapInPool, MapPool},
 };
-use crate::Plugin;
+use crate::state::{self, Persistent};
+use crate::{ConfigError, Plugin};
 
 pub mod pool;
 
+/// What we remember across restarts, in `state/mapman.yaml`.
+#[derive(Debug, Serialize, Deserialize)]
+struct MapManagerState {
+    map_history: Vec<Map>,
+}
+
+impl Persistent for MapManagerState {
+    const VERSION: u32 = 1;
+}
+
 /// Convenience thing for loading stuff from Json.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MapManagerConfig {
@@ -29,6 +41,16 @@ pub struct MapManagerConfig {
     leniency: usize,
 }
 
+impl MapManagerConfig {
+    /// Makes sure the config won't make us panic later on.
+    fn validate(&self) -> Result<(), ConfigError> {
+        if !self.pop_states.iter().any(|state| state.min_players == 0) {
+            return Err(ConfigError::Invalid("pop_states needs a state with min_players: 0".to_string()));
+        }
+        Ok(())
+    }
+}
+
 /// One "population level", for example for seeding, or for a full server.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PopState {
@@ -67,7 +89,7 @@ pub fn determine_popstate(states: &[PopState], pop: usize) -> &PopState {
 /////////////////////////////////////////////
 
 pub struct MapManager {
-    config: MapManagerConfig,
+    config: RwLock<Arc<MapManagerConfig>>,
     // enabled: bool,
     inner: Mutex<Inner>,
 
@@ -106,9 +128,25 @@ struct Inner {
     /// - It *may* be that `map_history.is_empty()`! You can't rely that current map is at `map_history[0]`.
     map_history: Vec<Map>,
 
+    overrides: SwitchOverrides,
+
     // current_map: Option<Map>,
 }
 
+/// Server settings which `switch_to` uses instead of its own, for example from the server
+/// profiles of the `profiles` plugin.
+#[derive(Debug, Clone, Default)]
+pub struct SwitchOverrides {
+    /// `vars.gameModeCounter`, in percent of the default tickets.
+    pub tickets: Option<usize>,
+    /// `Some(false)` makes every map infantry only, even those with vehicles in the pool.
+    pub vehicles: Option<bool>,
+    /// What the server is set to after switching, instead of hardcore.
+    pub preset: Option<Preset>,
+    /// `vars.vehicleSpawnDelay`, in percent of the default.
+    pub vehicle_spawn_delay: Option<usize>,
+}
+
 pub enum CallbackResult {
     KeepGoing,
     RemoveMe,
@@ -117,15 +155,43 @@ pub enum CallbackResult {
 #[async_trait]
 impl Plugin for MapManager {
     const NAME: &'static str = "mapman";
+    type Config = MapManagerConfig;
+
+    fn enabled(&self) -> bool { self.config.read().enabled }
+
+    fn validate(config: &MapManagerConfig) -> Result<(), ConfigError> {
+        config.validate()
+    }
+
+    async fn reconfigure(self: &Arc<Self>, bf4: &Arc<Bf4Client>, config: MapManagerConfig) -> Result<(), ConfigError> {
+        *self.config.write() = Arc::new(config);
 
-    fn enabled(&self) -> bool { self.config.enabled }
+        // the pools may have changed, so swap in the fitting (new) pop state, and with it the
+        // rcon maplist and mapvote options.
+        let pop = match self.get_pop_count(bf4).await {
+            Ok(pop) => pop,
+            Err(err) => {
+                error!("Reloaded MapManager config, but failed to get the player count, so the pop state stays as it is until the next join/leave: {:?}", err);
+                return Ok(());
+            }
+        };
+        let state = determine_popstate(&self.config().pop_states, pop).clone();
+        if let Err(mle) = self.change_pop_state(state, bf4).await {
+            error!("Reloaded MapManager config, but failed to apply the new pop state: {:?}", mle);
+        }
+        Ok(())
+    }
 
     async fn start(self: &Arc<Self>, bf4: &Arc<Bf4Client>) {
+        // The remembered history may be outdated if the map changed while we were gone.
+        // Do this first, since the mapvote looks at the current map when the pop state is set.
+        self.sync_current_map(bf4).await;
+
         // on start, get current player amounts (pop), then switch to that popstate initially.
         // In the constructor, popstate gets set to the base (0) case, but when we launch BattleFox,
         // it may not be on an empty server.
         let pop = self.get_pop_count(bf4).await.unwrap();
-        let state = determine_popstate(&self.config.pop_states, pop).clone();
+        let state = determine_popstate(&self.config().pop_states, pop).clone();
         match self.change_pop_state(state, bf4).await {
             Ok(()) => (),
             // Err(MapListError::Rcon(r)) => return Err(r),
@@ -136,6 +202,11 @@ impl Plugin for MapManager {
         let _ = self.current_map(&bf4).await;
     }
 
+    async fn persist(self: &Arc<Self>, _bf4: &Arc<Bf4Client>) {
+        let map_history = self.inner.lock().unwrap().map_history.clone();
+        state::save(Self::NAME, &MapManagerState { map_history });
+    }
+
     async fn event(self: Arc<Self>, bf4: Arc<Bf4Client>, event: Event) -> RconResult<()> {
         match event {
             // Join also catches the seeder bots joining, hence use Authenticated.
@@ -161,23 +232,31 @@ impl Plugin for MapManager {
 
 impl MapManager {
     pub fn new(config: MapManagerConfig) -> Self {
+        let map_history = state::load::<MapManagerState>(Self::NAME)
+            .map(|state| state.map_history)
+            .unwrap_or_default();
         let initial_popstate = config.pop_states
             .iter()
             .find(|state| state.min_players == 0)
             .unwrap()
             .clone(); // unwrap: safe because of guard.
         Self {
-            config,
+            config: RwLock::new(Arc::new(config)),
             inner: Mutex::new(Inner {
                 pop_state: initial_popstate,
                 pop: None,
-                map_history: Vec::new(),
+                map_history,
                 joins_leaves_since_pop: 0,
                 pool_change_callbacks: Vec::new(),
+                overrides: SwitchOverrides::default(),
             }),
         }
     }
 
+    fn config(&self) -> Arc<MapManagerConfig> {
+        self.config.read().clone()
+    }
+
     /// Registers a function to be called when the map pool selection changes.
     ///
     /// Used e.g. in mapvote.
@@ -193,6 +272,11 @@ impl MapManager {
         lock.pool_change_callbacks.push(b);
     }
 
+    /// Replaces the settings `switch_to` uses instead of its own.
+    pub fn set_overrides(&self, overrides: SwitchOverrides) {
+        self.inner.lock().unwrap().overrides = overrides;
+    }
+
     /// Checks whether the map is in the current pool.
     pub async fn is_in_current_pool(&self, map: Map) -> bool {
         self.inner.lock().unwrap().pop_state.pool.contains_map(map)
@@ -211,12 +295,16 @@ impl MapManager {
         mip: &MapInPool,
     ) -> Result<(), MapListError> {
         let pop = self.get_pop_count(bf4).await?;
-        let mut vehicles = pop >= self.config.vehicle_threshold;
+        let overrides = self.inner.lock().unwrap().overrides.clone();
+        let mut vehicles = overrides.vehicles.unwrap_or(pop >= self.config().vehicle_threshold);
 
         if let Some(vehicles_enabled) = mip.vehicles {
             trace!("Overriding vehicles enabled from {:?} to {:?}", vehicles, vehicles_enabled);
             vehicles = vehicles_enabled;
         };
+        if overrides.vehicles == Some(false) {
+            vehicles = false;
+        }
 
         // let pop_state = {
         //     let lock = self.inner.lock().await;
@@ -232,7 +320,7 @@ impl MapManager {
             _ => 400_f64,
         };
         // let tickets = ()
-        let tickets = tickets as usize;
+        let tickets = overrides.tickets.unwrap_or(tickets as usize);
 
         {
             let mut inner = self.inner.lock().unwrap();
@@ -241,7 +329,7 @@ impl MapManager {
             drop(inner);
         }
 
-        switch_map_to(bf4, &mip.map, &mip.mode, 1, vehicles, tickets).await?;
+        switch_map_to(bf4, &mip.map, &mip.mode, 1, vehicles, tickets, &overrides).await?;
 
         Ok(())
     }
@@ -295,7 +383,8 @@ impl MapManager {
 
         // now, check if we need to change the pop_state.
         let pop = self.get_pop_count(bf4).await?; // get true player count.
-        let next_state = determine_popstate(&self.config.pop_states, pop);
+        let config = self.config();
+        let next_state = determine_popstate(&config.pop_states, pop);
 
         let current_state = {
             let lock = self.inner.lock().unwrap();
@@ -308,7 +397,7 @@ impl MapManager {
             let diff_current = (pop as isize) - (current_state.min_players as isize); // +: pop higher than min players, -: pop fell below min_players.
             // let _diff_next = pop - isize::try_from(next_state.min_players).unwrap();
 
-            let leniency = isize::try_from(self.config.leniency).unwrap();
+            let leniency = isize::try_from(config.leniency).unwrap();
             if diff_current < -leniency {
                 // pop fell below the current state's min_players. Switch to proper pop state.
                 // This also means we're going "down"
@@ -369,6 +458,17 @@ impl MapManager {
         Ok(())
     }
 
+    /// Asks the server for the current map, and puts it in front of the history if it isn't there.
+    async fn sync_current_map(&self, bf4: &Bf4Client) {
+        if let Ok(info) = bf4.server_info().await {
+            let mut inner = self.inner.lock().unwrap();
+            if inner.map_history.first() != Some(&info.map) {
+                inner.map_history.insert(0, info.map);
+                inner.map_history.truncate(10);
+            }
+        }
+    }
+
     /// Get the current map (cached via `map_history` if possible).
     pub async fn current_map(&self, bf4: &Bf4Client) -> Option<Map> {
         let hist = {
@@ -453,6 +553,7 @@ pub async fn switch_map_to(
     rounds: usize,
     vehicles: bool,
     tickets: usize,
+    overrides: &SwitchOverrides,
 ) -> Result<(), MapListError> {
     let _ = bf4.set_preset(Preset::Custom).await;
     let _ = bf4.set_vehicles_spawn_allowed(vehicles).await;
@@ -477,9 +578,11 @@ pub async fn switch_map_to(
     trace!("MapList (step4): {:?}", bf4.maplist_list().await?);
 
     sleep(Duration::from_secs(10)).await;
-    let _ = bf4.set_tickets(std::cmp::max(100, tickets)).await;
-    let _ = bf4.set_vehicles_spawn_allowed(true).await;
-    let _ = bf4.set_preset(Preset::Hardcore).await;
+    let _ = bf4.set_tickets(overrides.tickets.unwrap_or(std::cmp::max(100, tickets))).await;
+    let _ = bf4.set_vehicles_spawn_allowed(overrides.vehicles.unwrap_or(true)).await;
+    let _ = bf4.set_preset(overrides.preset.unwrap_or(Preset::Hardcore)).await;
+    // after the preset, which may have reset it.
+    let _ = bf4.set_vehicle_spawn_delay(overrides.vehicle_spawn_delay.unwrap_or(100)).await;
 
     trace!("done.");
 
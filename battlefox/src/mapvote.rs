@@ -1,7 +1,10 @@
 #![allow(unused_variables, unused_imports)]
 
-use crate::admins::Admins;
-use crate::{UPTIME, Plugin};
+use crate::commands::{ArgKind, Command, Commands};
+use crate::feed::Feed;
+use crate::state::{self, Persistent};
+use crate::roles::Role;
+use crate::{ConfigError, Plugin};
 use crate::{guard::{
         recent::Age::{Old, Recent},
         Cases, Guard,
@@ -10,7 +13,7 @@ use crate::{guard::{
         CallbackResult, MapManager, PopState,
     }, mapvote::matching::matchmap_restrict, players::Players, stv::{CheckBallotResult, Profile, tracing::{Assignment, DetailedTracer, Distr}}, vips::{MaybeVip, Vips, YesVip}};
 
-use self::{config::MapVoteConfig, matching::{AltMatchers, AltMatchersInv}};
+use self::{config::{MapVoteConfig, MapVoteConfigJson}, matching::{AltMatchers, AltMatchersInv}};
 
 use super::stv::tracing::{NoTracer, StvAction, LoggingTracer, AnimTracer};
 use super::stv::Ballot;
@@ -44,6 +47,9 @@ use tokio::{
     time::{sleep, Interval},
 };
 
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+
 use num_rational::{BigRational as Rat, Ratio};
 use num_traits::{One, ToPrimitive};
 
@@ -77,14 +83,49 @@ struct Inner {
     config: Arc<MapVoteConfig>,
 }
 
+/// What we remember across restarts, in `state/mapvote.yaml`.
+///
+/// Only used again if the server is still on the same map and in the same pop state, otherwise
+/// the vote is over anyway.
+#[derive(Debug, Serialize, Deserialize)]
+struct MapvoteState {
+    map: Option<Map>,
+    popstate: String,
+    alternatives: MapPool,
+    votes: Vec<(Player, Ballot<MapInPool>)>,
+    nominations: Vec<(Player, HashSet<Map>)>,
+}
+
+impl Persistent for MapvoteState {
+    const VERSION: u32 = 1;
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AlternativeStatus {
+    #[serde(flatten)]
+    pub alternative: MapInPool,
+    /// Sum of the weights of all ballots which have this as first preference.
+    pub first_preferences: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MapvoteStatus {
+    pub alternatives: Vec<AlternativeStatus>,
+    /// How many players have voted.
+    pub ballots: usize,
+}
+
 #[derive(Debug)]
 pub struct Mapvote {
     inner: Mutex<Option<Inner>>,
     mapman: Arc<MapManager>,
     vips: Arc<Vips>,
     players: Arc<Players>,
-    config: Arc<MapVoteConfig>,
-    admins: Arc<Admins>,
+    config: RwLock<Arc<MapVoteConfig>>,
+    commands: Arc<Commands>,
+    feed: Arc<Feed>,
+    /// Loaded on startup, until the vote gets set up for the first time.
+    restored: RwLock<Option<MapvoteState>>,
 }
 
 impl Inner {
@@ -105,6 +146,49 @@ impl Inner {
         }).collect()
     }
 
+    fn status(&self) -> MapvoteStatus {
+        let alternatives = self.alternatives.iter()
+            .map(|mip| {
+                let first_preferences = self.votes.values()
+                    .filter(|ballot| ballot.preferences.first() == Some(mip))
+                    .fold(Rat::from_integer(BigInt::from(0)), |acc, ballot| acc + &ballot.weight);
+                AlternativeStatus {
+                    alternative: mip.clone(),
+                    first_preferences: first_preferences.to_f64().unwrap_or(0.0),
+                }
+            })
+            .collect();
+        MapvoteStatus {
+            alternatives,
+            ballots: self.votes.len(),
+        }
+    }
+
+    fn to_state(&self, map: Option<Map>) -> MapvoteState {
+        MapvoteState {
+            map,
+            popstate: self.popstate.name.clone(),
+            alternatives: self.alternatives.clone(),
+            votes: self.votes.iter().map(|(player, ballot)| (player.clone(), ballot.clone())).collect(),
+            nominations: self.nominations.iter().map(|(vip, maps)| ((**vip).clone(), maps.clone())).collect(),
+        }
+    }
+
+    /// Continues the vote from before a restart.
+    fn restore(&mut self, state: MapvoteState) {
+        self.alternatives = state.alternatives;
+        self.votes = state.votes.into_iter()
+            // keep the invariant that every ballot only has current options, and at least one.
+            .filter(|(_, ballot)| !ballot.preferences.is_empty()
+                && ballot.preferences.iter().all(|mip| self.alternatives.contains_map(mip.map)))
+            .collect();
+        self.nominations = state.nominations.into_iter()
+            // they were VIP when they nominated, which is all the guard is about here.
+            .map(|(player, maps)| (unsafe { Guard::new_raw(player, YesVip) }, maps))
+            .collect();
+        self.update_matchers(false);
+    }
+
     /// Gets the amount of nominations that the VIP has done this round.
     fn vip_n_noms(&self, vip: &Guard<Player, YesVip>) -> usize {
         if let Some(v) = self.nominations.get(vip) {
@@ -297,19 +381,33 @@ enum VoteResult {
     Inactive,
 }
 
-enum NomMapParseResult {
-    Ok(Map),
-    Empty,
-    Other,
-}
-
 #[async_trait]
 impl Plugin for Mapvote {
     const NAME: &'static str = "mapvote";
+    type Config = MapVoteConfigJson;
 
-    fn enabled(&self) -> bool { self.config.enabled }
+    fn enabled(&self) -> bool { self.config().enabled }
+
+    fn validate(config: &MapVoteConfigJson) -> Result<(), ConfigError> {
+        config.validate()
+    }
+
+    async fn reconfigure(self: &Arc<Self>, _bf4: &Arc<Bf4Client>, config: MapVoteConfigJson) -> Result<(), ConfigError> {
+        let config = Arc::new(MapVoteConfig::from_json(config));
+        *self.config.write() = config.clone();
+
+        let mut lock = self.inner.lock().await;
+        if let Some(inner) = &mut *lock {
+            inner.config = config;
+            // options_minlen and the reserved words may have changed.
+            inner.update_matchers(true);
+        }
+        Ok(())
+    }
 
     async fn start(self: &Arc<Self>, bf4: &Arc<Bf4Client>) {
+        self.register_commands();
+
         let mapvote = self.clone();
         let bf4 = bf4.clone();
         let _jh = tokio::spawn(async move {
@@ -320,6 +418,14 @@ impl Plugin for Mapvote {
         // TODO: join the spammer JoinHandle some day too.
     }
 
+    async fn persist(self: &Arc<Self>, bf4: &Arc<Bf4Client>) {
+        let map = self.mapman.current_map(bf4).await;
+        let lock = self.inner.lock().await;
+        if let Some(inner) = &*lock {
+            state::save(Self::NAME, &inner.to_state(map));
+        }
+    }
+
     async fn event(self: Arc<Self>, bf4: Arc<Bf4Client>, event: Event) -> RconResult<()> {
         match event {
             Event::Chat { vis, player, msg } => {
@@ -327,7 +433,7 @@ impl Plugin for Mapvote {
             }
             Event::LevelLoaded { level_name, game_mode, rounds_played, rounds_total } => {
                 tokio::spawn(async move {
-                    tokio::time::sleep(self.config.vote_start_interval).await;
+                    tokio::time::sleep(self.config().vote_start_interval).await;
                     self.start_new_vote().await;
                 });
             }
@@ -346,7 +452,8 @@ impl Mapvote {
         mapman: Arc<MapManager>,
         vips: Arc<Vips>,
         players: Arc<Players>,
-        admins: Arc<Admins>,
+        commands: Arc<Commands>,
+        feed: Arc<Feed>,
         config: MapVoteConfig,
     ) -> Arc<Self> {
         let myself = Arc::new(Self {
@@ -354,8 +461,10 @@ impl Mapvote {
             mapman: mapman.clone(),
             vips,
             players,
-            config: Arc::new(config),
-            admins,
+            config: RwLock::new(Arc::new(config)),
+            commands,
+            feed,
+            restored: RwLock::new(state::load(Self::NAME)),
         });
 
 
@@ -385,6 +494,126 @@ impl Mapvote {
         myself
     }
 
+    fn config(&self) -> Arc<MapVoteConfig> {
+        self.config.read().clone()
+    }
+
+    /// The current options and how many first preferences each has, for outside observers like
+    /// the web API. `None` while the mapvote isn't set up yet.
+    pub async fn status(&self) -> Option<MapvoteStatus> {
+        self.inner.lock().await.as_ref().map(Inner::status)
+    }
+
+    fn register_commands(self: &Arc<Self>) {
+        let myself = self.clone();
+        self.commands.register(
+            Command::new(Self::NAME, "v", "Shows the current mapvote and your ballot."),
+            move |bf4, inv| {
+                let myself = myself.clone();
+                async move {
+                    let mut messages = Vec::new();
+                    let lock = myself.inner.lock().await;
+                    if let Some(inner) = &*lock {
+                        messages.push(inner.fmt_options());
+                        inner.fmt_personal_status(&mut messages, &*inv.player);
+                    } else {
+                        messages.push("Mapvote is currently inactive, try again later :)".to_owned());
+                    }
+
+                    drop(lock);
+                    let _ = bf4.say_lines(messages, &*inv.player).await;
+                }
+            });
+
+        let myself = self.clone();
+        self.commands.register(
+            Command::new(Self::NAME, "ballots", "Lists everyone's ballot."),
+            move |bf4, inv| {
+                let myself = myself.clone();
+                async move {
+                    let lock = myself.inner.lock().await;
+                    if let Some(inner) = &*lock {
+                        let lines = inner.votes.iter().map(|(p, bal)| {
+                            let mut prefs = bal.preferences.iter().map(|mip| mip.map.short());
+                            format!("{}: {}* {}", p.name, bal.weight.numer(), prefs.join(">"))
+                        }).collect_vec();
+                        drop(lock);
+                        for line in lines {
+                            let _ = bf4.say(line, &*inv.player).await;
+                            tokio::time::sleep(Duration::from_millis(200)).await;
+                        }
+                    }
+                }
+            });
+
+        let myself = self.clone();
+        self.commands.register(
+            Command::new(Self::NAME, "nominate", "VIPs can add a map to the vote. Append \"inf\" for infantry only.")
+                .alias("nom")
+                .opt_arg("map", ArgKind::Map)
+                .opt_arg("vehicles", ArgKind::Word),
+            move |bf4, inv| {
+                let myself = myself.clone();
+                async move {
+                    let map = inv.args.map("map");
+                    let vehicles = inv.args.word("vehicles").map(|val| !val.eq_ignore_ascii_case("inf"));
+                    let vip = myself
+                        .vips
+                        .get_player_use(&inv.player, &bf4, |g| async {
+                            myself.handle_nomination(bf4.clone(), g, map, vehicles).await;
+                        })
+                        .await;
+                    if let Ok(vip) = vip {
+                        vip.await
+                    }
+                }
+            });
+
+        let myself = self.clone();
+        self.commands.register(
+            Command::new(Self::NAME, "anim", "Turns the vote result animation at round end on or off for you.")
+                .opt_arg("on/off", ArgKind::Word),
+            move |bf4, inv| {
+                let myself = myself.clone();
+                async move {
+                    let yesno = match inv.args.word("on/off") {
+                        Some("no" | "false" | "off" | "0" | "-") => false,
+                        _ => true,
+                    };
+                    let mut opt_inner = myself.inner.lock().await;
+                    if let Some(inner) = &mut *opt_inner {
+                        inner.anim_override_override.insert((*inv.player).clone(), yesno);
+                        drop(opt_inner);
+                        let _ = bf4.say(format!("Animation of vote result calculation at round end: {}", yesno), inv.vis).await;
+                    }
+                }
+            });
+
+        let myself = self.clone();
+        self.commands.register(
+            Command::new(Self::NAME, "bfox endvote", "Ends the current vote and switches to the winner.")
+                .role(Role::Admin),
+            move |bf4, inv| {
+                let myself = myself.clone();
+                async move {
+                    let _ = bf4.say("Ending vote.", &*inv.player).await;
+                    myself.handle_round_over(&bf4).await;
+                }
+            });
+
+        let myself = self.clone();
+        self.commands.register(
+            Command::new(Self::NAME, "bfox startvote", "Starts a new vote.")
+                .role(Role::Admin),
+            move |bf4, inv| {
+                let myself = myself.clone();
+                async move {
+                    let _ = bf4.say("Starting new vote.", &*inv.player).await;
+                    myself.start_new_vote().await;
+                }
+            });
+    }
+
     async fn on_popstate_changed(&self, bf4: &Bf4Client, popstate: PopState) {
         let mut lock = self.inner.lock().await;
         if let Some(inner) = &mut *lock {
@@ -420,7 +649,7 @@ impl Mapvote {
                 // to avoid duplicates in the options
                 .without_many(&inner.alternatives.to_mapset())
                 // saturating sub clamps the result to 0 if it would be negative.
-                .choose_random(self.config.n_options.saturating_sub(inner.alternatives.pool.len()));
+                .choose_random(self.config().n_options.saturating_sub(inner.alternatives.pool.len()));
             debug!("alternatives_additions = {:#?}", alternatives_additions);
 
             inner
@@ -553,9 +782,16 @@ impl Mapvote {
                 popstate,
                 matchers: AltMatchers::new(),
                 matchmap: AltMatchersInv::new(),
-                config: self.config.clone(),
+                config: self.config(),
             };
-            init.set_up_new_vote(self.config.n_options, None);
+            let restored = self.restored.write().take();
+            match restored {
+                Some(state) if state.popstate == init.popstate.name && state.map == self.mapman.current_map(bf4).await => {
+                    info!("Continuing the mapvote from before the restart, with {} ballots.", state.votes.len());
+                    init.restore(state);
+                },
+                _ => init.set_up_new_vote(self.config().n_options, None),
+            }
             info!("Popstate initialized! New: {}", init.popstate.name);
             *lock = Some(init);
         }
@@ -588,7 +824,7 @@ impl Mapvote {
 
             self.broadcast_status(&bf4).await;
 
-            tokio::time::sleep(self.config.spammer_interval).await;
+            tokio::time::sleep(self.config().spammer_interval).await;
         }
     }
 
@@ -647,6 +883,7 @@ impl Mapvote {
 
             // so now we have a ballot which can be cast. Let's check for existing ballot, and cast it!
             let old = inner.votes.insert((**player).to_owned(), ballot.to_owned());
+            self.feed.publish("mapvote.status", inner.status());
             VoteResult::Ok {
                 new: ballot,
                 old,
@@ -674,7 +911,7 @@ impl Mapvote {
                 Right(notvip) => {
                     let _ = bf4.say_lines(vec![
                         format!("Skipped {}: Currently not in options", list),
-                        self.config.vip_nom.clone(),
+                        self.config().vip_nom.clone(),
                     ], player).await;
                 }
             }
@@ -743,26 +980,26 @@ impl Mapvote {
         &self,
         bf4: Arc<Bf4Client>,
         player: Guard<Player, MaybeVip>,
-        map: NomMapParseResult,
+        map: Option<Map>,
         vehicles: Option<bool>
     ) {
         match player.cases() {
             Left(player) => {
                 // make sure the map was parsed correctly
                 match map {
-                    NomMapParseResult::Ok(map) => {
+                    Some(map) => {
                         let mut futures = Vec::new();
                         let mut lock = self.inner.lock().await;
                         // make sure we have a mapvote actually going at all.
                         if let Some(inner) = &mut *lock {
                             // make sure people don't nominate excessively much.
-                            if inner.alternatives.pool.len() < self.config.max_options {
+                            if inner.alternatives.pool.len() < self.config().max_options {
                                 // make sure map isn't already in the options
                                 if !inner.alternatives.pool.iter().any(|mip| mip.map == map) {
                                     // make sure the map is in the pool
                                     if inner.popstate.pool.contains_map(map) {
                                         // make sure this VIP hasn't exceeded their nomination limit this round.
-                                        if inner.vip_n_noms(&player) < self.config.max_noms_per_vip {
+                                        if inner.vip_n_noms(&player) < self.config().max_noms_per_vip {
                                             // Check if the nominated map has recently been played
                                             if !self.mapman.is_recently_played(&map) {
                                                 // phew, that was a lot of ifs...
@@ -770,7 +1007,7 @@ impl Mapvote {
                                                 inner.vip_nom(&player, map, vehicles);
                                                 info!("The new alternatives are {:?}.", inner.alternatives);
 
-                                                let announce = self.config.announce_nominator.unwrap_or(true);
+                                                let announce = self.config().announce_nominator.unwrap_or(true);
                                                 if announce {
                                                     futures.push(bf4.say_lines(vec![
                                                         format!("Our beloved VIP {} has nominated {}!", &*player, map.Pretty()),
@@ -792,7 +1029,7 @@ impl Mapvote {
                                         } else {
                                             futures.push(bf4.say_lines(vec![
                                                 format!("Apologies, {}, you can't nominate more maps.", &*player),
-                                                format!("The maximum nominations per round per VIP are {}.", self.config.max_noms_per_vip),
+                                                format!("The maximum nominations per round per VIP are {}.", self.config().max_noms_per_vip),
                                             ], player.get().into()));
                                         }
                                     } else {
@@ -821,7 +1058,7 @@ impl Mapvote {
                         drop(lock); // very important to free this lock before we do rcon calls.
                         join_all(futures).await;
                     }
-                    NomMapParseResult::Empty => {
+                    None => {
                         // print which maps can be nominated.
                         let mut futures = Vec::new();
                         let lock = self.inner.lock().await;
@@ -845,18 +1082,12 @@ impl Mapvote {
                         drop(lock);
                         join_all(futures).await;
                     }
-                    NomMapParseResult::Other => {
-                        let _ = bf4.say_lines(vec![
-                            "You are VIP! But I couldn't understand which map you want to nominate :(",
-                            "Example usage: !nominate metro",
-                        ], player.get());
-                    }
                 }
             }
             Right(player) => {
                 let _ = bf4.say_lines(vec![
                     format!("Sorry {}, but you are not a VIP (yet), and thus can't nominate maps :(", &*player),
-                    self.config.vip_ad.clone(),
+                    self.config().vip_ad.clone(),
                 ], &*player).await;
             }
         }
@@ -870,145 +1101,32 @@ impl Mapvote {
         mut msg: AsciiString,
     ) -> RconResult<()> {
         msg.make_ascii_lowercase();
-        let split = msg.as_str()
-            .split(' ')
-            .filter(|&s| !s.is_empty())
-            .collect::<Vec<_>>();
-
-        if split.is_empty() {
-            return Ok(())
+        if self.commands.is_command(msg.as_str()) {
+            return Ok(()); // `!v`, `!nominate` etc are handled via the commands plugin.
         }
 
-        match split[0] {
-            "/v" | "!v" => {
-                let mut messages = Vec::new();
-                let lock = self.inner.lock().await;
-                if let Some(inner) = &*lock {
-                    messages.push(inner.fmt_options());
-                    inner.fmt_personal_status(&mut messages, &player);
-                } else {
-                    messages.push("Mapvote is currently inactive, try again later :)".to_owned());
-                }
-
-                drop(lock);
-                let _ = bf4.say_lines(messages, player).await;
-            }
-            "/bfox" => {
-                if split.len() == 1 {
-                    let _ = bf4.say("Subcommands of /bfox are: version, uptime, endvote", player).await;
-                } else {
-                    match split[1] {
-                        "version" | "ver" | "v" => { let _ = bf4.say(format!("BattleFox {}", crate::GIT_DESCRIBE), player).await; },
-                        "uptime" => {
-                            let elapsed = UPTIME.elapsed();
-                            let _ = bf4.say(format!("Uptime: {}", humantime::format_duration(elapsed)), player).await;
-                        },
-                        "endvote" => {
-                            if self.admins.is_admin(&player.name) {
-                                let _ = bf4.say("Ending vote.", player).await;
-                                self.handle_round_over(&bf4).await;
-                            } else {
-                                let _ = bf4.say("You are not admin (according to bfox config).", player).await;
-                            }
-                        },
-                        "startvote" => {
-                            if self.admins.is_admin(&player.name) {
-                                let _ = bf4.say("Starting new vote.", player).await;
-                                self.start_new_vote().await;
-                            } else {
-                                let _ = bf4.say("You are not admin (according to bfox config).", player).await;
-                            }
-                        },
-                        _ => (),
-                    }
-                }
-            },
-            "/ballots" => {
-                let lock = self.inner.lock().await;
-                if let Some(inner) = &*lock {
-                    let lines = inner.votes.iter().map(|(p, bal)| {
-                        let mut prefs = bal.preferences.iter().map(|mip| mip.map.short());
-                        format!("{}: {}* {}", p.name, bal.weight.numer(), prefs.join(">"))
-                    }).collect_vec();
-                    drop(lock);
-                    tokio::spawn(async move {
-                        for line in lines {
-                            let _ = bf4.say(line, &player).await;
-                            tokio::time::sleep(Duration::from_millis(200)).await;
-                        }
-                    });
-                }
-            },
-            "!nominate" | "/nominate" | "!nom" | "/nom" => {
-                let map = match split.get(1) {
-                    Some(&word) => match Map::try_from_short(word) {
-                        Some(map) => NomMapParseResult::Ok(map),
-                        None => NomMapParseResult::Other,
-                    },
-                    None => NomMapParseResult::Empty,
-                };
-
-                let vehicles = split.get(2).map(|val| !val.eq_ignore_ascii_case("inf"));
-
-                tokio::spawn({
-                    let player = player.clone();
-                    let bf4 = bf4.clone();
-                    let myself = self.clone();
-                    async move {
-                        let vip = myself
-                            .vips
-                            .get_player_use(&player, &bf4, |g| async {
-                                myself.handle_nomination(bf4.clone(), g, map, vehicles).await;
-                            })
+        // try parsing !metro pearl etc
+        if !msg.is_empty() && (msg[0] == '/' || msg[0] == '!') {
+            let vis = if msg[0] == '/' {
+                Visibility::Player(player.name.clone())
+            } else {
+                vis
+            };
+            let inner_lock = self.inner.lock().await;
+            if let Some(inner) = &*inner_lock {
+                // extract matchmap and then drop the lock immediately.
+                let matchmap = inner.matchmap.clone();
+                drop(inner_lock);
+                match parse_maps(&msg.as_str()[1..], &matchmap) {
+                    ParseMapsResult::Ok(maps) => self.handle_maps(&bf4, player, maps, vis).await?,
+                    ParseMapsResult::Nothing => {}, // silently ignore
+                    ParseMapsResult::NotAMapName { orig } => {
+                        let _ = bf4
+                            .say(
+                                format!("{}: \"{}\" is not a valid map name.", player, orig),
+                                player,
+                            )
                             .await;
-                        if let Ok(vip) = vip {
-                            vip.await
-                        }
-                    }
-                });
-            }
-            "!anim" | "/anim" => {
-                let yesno = if split.len() >= 2 {
-                    match split[1] {
-                        "yes" | "true" | "on" | "1" | "+" => true,
-                        "no" | "false" | "off" | "0" | "-" => false,
-                        _ => true,
-                    }
-                } else {
-                    true
-                };
-                let mut opt_inner = self.inner.lock().await;
-                if let Some(inner) = &mut *opt_inner {
-                    inner.anim_override_override.insert(player.clone(), yesno);
-                    drop(opt_inner);
-                    let _ = bf4.say(format!("Animation of vote result calculation at round end: {}", yesno), vis).await;
-                }
-            }
-            _ => {
-                // if no command matched, try parsing !metro pearl etc
-                if !msg.is_empty() && (msg[0] == '/' || msg[0] == '!') {
-                    let vis = if msg[0] == '/' {
-                        Visibility::Player(player.name.clone())
-                    } else {
-                        vis
-                    };
-                    let inner_lock = self.inner.lock().await;
-                    if let Some(inner) = &*inner_lock {
-                        // extract matchmap and then drop the lock immediately.
-                        let matchmap = inner.matchmap.clone();
-                        drop(inner_lock);
-                        match parse_maps(&msg.as_str()[1..], &matchmap) {
-                            ParseMapsResult::Ok(maps) => self.handle_maps(&bf4, player, maps, vis).await?,
-                            ParseMapsResult::Nothing => {}, // silently ignore
-                            ParseMapsResult::NotAMapName { orig } => {
-                                let _ = bf4
-                                    .say(
-                                        format!("{}: \"{}\" is not a valid map name.", player, orig),
-                                        player,
-                                    )
-                                    .await;
-                            }
-                        }
                     }
                 }
             }
@@ -1023,7 +1141,8 @@ impl Mapvote {
         let mut lock = self.inner.lock().await;
         if let Some(inner) = &mut *lock {
             info!("Starting a new vote: {:#?}", &inner.votes);
-            inner.set_up_new_vote(self.config.n_options, Some(recent_maps));
+            inner.set_up_new_vote(self.config().n_options, Some(recent_maps));
+            self.feed.publish("mapvote.status", inner.status());
         }
     }
 
@@ -1031,8 +1150,8 @@ impl Mapvote {
         let recent_maps = self.mapman.recent_maps();
         self.broadcast_status(bf4).await; // send everyone the voting options.
         // let's wait like 10 seconds because people might still vote in the end screen.
-        let _ = bf4.say(format!("Mapvote is still going for {}s! Hurry!", self.config.endscreen_votetime.as_secs()), Visibility::All).await;
-        tokio::time::sleep(self.config.endscreen_votetime - Duration::from_secs(7)).await; // FIXME: Replace with checked substraction.
+        let _ = bf4.say(format!("Mapvote is still going for {}s! Hurry!", self.config().endscreen_votetime.as_secs()), Visibility::All).await;
+        tokio::time::sleep(self.config().endscreen_votetime - Duration::from_secs(7)).await; // FIXME: Replace with checked substraction.
         self.broadcast_status(bf4).await; // send everyone the voting options.
         tokio::time::sleep(Duration::from_secs(7)).await; // FIXME: ^
 
@@ -1048,7 +1167,7 @@ impl Mapvote {
                 // get each player's votes, so we can simulate how the votes go later.
                 let assignment = inner.to_assignment();
 
-                // inner.set_up_new_vote(self.config.n_options, Some(recent_maps));
+                // inner.set_up_new_vote(self.config().n_options, Some(recent_maps));
                 Some((profile, assignment, inner.anim_override_override.clone()))
             } else {
                 None
@@ -1064,6 +1183,7 @@ impl Mapvote {
 
             if let Some(winner) = profile.vanilla_stv_1(&mut tracer) { // <----- STV winner gets calculated here!
                 info!("Winner: {}", winner.map.Pretty());
+                self.feed.publish("mapvote.winner", &winner);
 
                 let alts_start = profile.alts.iter()
                     .sorted_by(|a, b| Ord::cmp(&profile.score(b), &profile.score(a)))
@@ -1076,14 +1196,15 @@ impl Mapvote {
                     info!("Animation for {}:\n{}", player.name, anim.join("\n"));
                 }
 
+                let config = self.config();
                 let mut jhs = Vec::new();
                 for (player, frames) in animation {
                     let bf4clone = bf4.clone();
                     let animate = *anim_override_override
                         .get(&player)
-                        .unwrap_or_else(|| self.config.animate_override
+                        .unwrap_or_else(|| config.animate_override
                             .get(&player.name)
-                            .unwrap_or(&self.config.animate));
+                            .unwrap_or(&config.animate));
 
                     let winner = winner.clone();
                     jhs.push(tokio::spawn(async move {
@@ -1099,7 +1220,7 @@ impl Mapvote {
                 }
                 join_all(jhs).await;
 
-                tokio::time::sleep(self.config.endscreen_post_votetime).await;
+                tokio::time::sleep(self.config().endscreen_post_votetime).await;
 
                 self.mapman.switch_to(bf4, &winner).await.unwrap();
             } else {
@@ -1113,7 +1234,7 @@ impl Mapvote {
     /// - Can't be less than 1
     fn vip_vote_weight(&self) -> Ratio<BigInt> {
         let mut weight = Rat::one();
-        let config_weight = self.config.vip_vote_weight.unwrap_or(2);
+        let config_weight = self.config().vip_vote_weight.unwrap_or(2);
         for n in 1..config_weight {
             weight += Rat::one();
         }
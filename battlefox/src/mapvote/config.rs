@@ -3,6 +3,8 @@ use std::{collections::{HashMap, HashSet}, time::Duration};
 use ascii::{AsciiString, IntoAsciiString};
 use serde::{Deserialize, Serialize};
 
+use crate::ConfigError;
+
 #[derive(Debug)]
 pub struct MapVoteConfig {
     pub enabled: bool,
@@ -88,3 +90,16 @@ impl MapVoteConfig {
         }
     }
 }
+
+impl MapVoteConfigJson {
+    /// Makes sure the config won't make us panic later on.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.n_options > self.max_options {
+            return Err(ConfigError::Invalid("n_options must not be larger than max_options".to_string()));
+        }
+        if self.endscreen_votetime < 7 {
+            return Err(ConfigError::Invalid("endscreen_votetime must be at least 7 seconds".to_string()));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,66 @@
+//! Prometheus metrics, served by the web API at `/metrics`. Only with the `metrics` cargo feature.
+//!
+//! Counters are bumped by the plugins as things happen. Gauges which describe the current state of
+//! the server are filled in just before every scrape, from what the plugins already know anyway.
+
+use std::time::Instant;
+
+use battlefield_rcon::bf4::{Bf4Client, Team};
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram, register_int_counter, register_int_counter_vec, register_int_gauge_vec,
+    register_gauge, Encoder, Gauge, Histogram, IntCounter, IntCounterVec, IntGaugeVec, TextEncoder,
+};
+
+use crate::mapmanager::MapManager;
+use crate::mapvote::Mapvote;
+use crate::players::Players;
+
+lazy_static! {
+    pub static ref PLAYERS: IntGaugeVec = register_int_gauge_vec!(
+        "bfox_players", "Players on the server, per team.", &["team"]).unwrap();
+    pub static ref RCON_ROUNDTRIP: Histogram = register_histogram!(
+        "bfox_rcon_roundtrip_seconds", "Time for a simple RCON query to come back.",
+        vec![0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5]).unwrap();
+    pub static ref EVENTS: IntCounterVec = register_int_counter_vec!(
+        "bfox_events_total", "RCON events received, per kind.", &["kind"]).unwrap();
+    pub static ref PLUGIN_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "bfox_plugin_errors_total", "Plugin failures and panics while handling events.", &["plugin"]).unwrap();
+    pub static ref TK_PUNISHMENTS: IntCounter = register_int_counter!(
+        "bfox_teamkill_punishments_total", "Teamkill punishments handed out, from warnings to bans.").unwrap();
+    pub static ref MAPVOTE_PARTICIPATION: Gauge = register_gauge!(
+        "bfox_mapvote_participation_ratio", "Fraction of the players on the server who have voted.").unwrap();
+    pub static ref POPSTATE: IntGaugeVec = register_int_gauge_vec!(
+        "bfox_popstate", "1 for the currently active population state.", &["name"]).unwrap();
+}
+
+fn team_label(team: Team) -> String {
+    format!("{:?}", team).to_lowercase()
+}
+
+/// Refreshes the gauges and renders everything in the Prometheus text format.
+pub async fn gather(bf4: &Bf4Client, players: &Players, mapman: &MapManager, mapvote: &Mapvote) -> String {
+    let started = Instant::now();
+    if bf4.server_info().await.is_ok() {
+        RCON_ROUNDTRIP.observe(started.elapsed().as_secs_f64());
+    }
+
+    let players = players.players(bf4).await;
+    PLAYERS.reset();
+    for player in players.values() {
+        PLAYERS.with_label_values(&[&team_label(player.team)]).inc();
+    }
+
+    if let Some(status) = mapvote.status().await {
+        let ratio = if players.is_empty() { 0.0 } else { status.ballots as f64 / players.len() as f64 };
+        MAPVOTE_PARTICIPATION.set(ratio);
+    }
+
+    POPSTATE.reset();
+    POPSTATE.with_label_values(&[&mapman.popstate().await.name]).set(1);
+
+    let mut buf = Vec::new();
+    // only fails on a broken writer, and a Vec isn't one.
+    let _ = TextEncoder::new().encode(&prometheus::gather(), &mut buf);
+    String::from_utf8(buf).unwrap_or_default()
+}
@@ -0,0 +1,290 @@
+//! Warns and eventually kicks players whose ping is too high.
+//!
+//! We sample everyone's ping with `admin.listPlayers` every so often, and look at the average of
+//! the last few samples. Someone above the limit is warned a few times first, and only kicked when
+//! the server is close to full, since a laggy player is still better than an empty slot.
+//!
+//! BF4 reports a ping of 0 or 65535 when it doesn't know it (still loading in, or the ping is
+//! blocked). Those samples are ignored, they say nothing about the player's connection.
+
+use std::{collections::{HashMap, HashSet, VecDeque}, sync::{atomic::{AtomicBool, Ordering}, Arc}, time::{Duration, Instant}};
+
+use async_trait::async_trait;
+use battlefield_rcon::{bf4::{Bf4Client, Eaid, Event, Player, PlayerKickError, Visibility}, rcon::RconResult};
+use parking_lot::{Mutex, RwLock};
+use serde::Deserialize;
+
+use crate::mapmanager::MapManager;
+use crate::roles::{Role, Roles};
+use crate::{ConfigError, Plugin};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    enabled: bool,
+    /// How often to sample pings.
+    #[serde(with = "humantime_serde")]
+    interval: Duration,
+    /// The average is over this many of the most recent samples. Nothing happens until someone
+    /// has that many.
+    samples: usize,
+    /// Nothing happens in the first moments after joining, pings are all over the place then.
+    #[serde(with = "humantime_serde")]
+    grace: Duration,
+    /// How many times someone is warned before they can be kicked.
+    warnings: usize,
+    /// Only kick when at least this many players are on the server.
+    kick_at_players: usize,
+    /// Players with this role or higher are left alone.
+    immune: Role,
+    #[serde(default)]
+    whitelist: HashSet<Eaid>,
+    /// Highest allowed average ping in ms, when the popstate has no entry in `popstates`.
+    max_ping: u32,
+    /// Popstate name -> highest allowed average ping.
+    #[serde(default)]
+    popstates: HashMap<String, u32>,
+}
+
+impl Config {
+    fn max_ping(&self, popstate: &str) -> u32 {
+        self.popstates.get(popstate).copied().unwrap_or(self.max_ping)
+    }
+}
+
+#[derive(Debug)]
+struct Pings {
+    since: Instant,
+    samples: VecDeque<u32>,
+    warned: usize,
+}
+
+impl Pings {
+    fn new(since: Instant) -> Self {
+        Self { since, samples: VecDeque::new(), warned: 0 }
+    }
+
+    fn sample(&mut self, ping: i32, keep: usize) {
+        if ping <= 0 || ping >= 65535 {
+            return; // unknown, see module docs.
+        }
+        self.samples.push_back(ping as u32);
+        while self.samples.len() > keep {
+            self.samples.pop_front();
+        }
+    }
+
+    fn average(&self) -> Option<u32> {
+        if self.samples.is_empty() {
+            None
+        } else {
+            Some(self.samples.iter().sum::<u32>() / self.samples.len() as u32)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Verdict {
+    Fine,
+    /// Too early to tell, or too high but the server isn't full enough to kick.
+    Wait,
+    Warn { average: u32 },
+    Kick { average: u32 },
+}
+
+fn verdict(pings: &Pings, config: &Config, max_ping: u32, players: usize) -> Verdict {
+    if pings.since.elapsed() < config.grace || pings.samples.len() < config.samples {
+        return Verdict::Wait;
+    }
+    match pings.average() {
+        Some(average) if average > max_ping => {
+            if pings.warned < config.warnings {
+                Verdict::Warn { average }
+            } else if players >= config.kick_at_players {
+                Verdict::Kick { average }
+            } else {
+                Verdict::Wait
+            }
+        },
+        _ => Verdict::Fine,
+    }
+}
+
+pub struct PingKicker {
+    config: RwLock<Arc<Config>>,
+    roles: Arc<Roles>,
+    mapman: Arc<MapManager>,
+    pings: Mutex<HashMap<Player, Pings>>,
+    paused: AtomicBool,
+}
+
+impl PingKicker {
+    pub fn new(roles: Arc<Roles>, mapman: Arc<MapManager>, config: Config) -> Self {
+        Self {
+            config: RwLock::new(Arc::new(config)),
+            roles,
+            mapman,
+            pings: Mutex::new(HashMap::new()),
+            paused: AtomicBool::new(false),
+        }
+    }
+
+    fn config(&self) -> Arc<Config> {
+        self.config.read().clone()
+    }
+
+    async fn sample_loop(&self, bf4: Arc<Bf4Client>) {
+        loop {
+            tokio::time::sleep(self.config().interval).await;
+            if self.paused.load(Ordering::Relaxed) {
+                continue;
+            }
+            self.sample(&bf4).await;
+        }
+    }
+
+    async fn sample(&self, bf4: &Arc<Bf4Client>) {
+        let config = self.config();
+        let list = match bf4.list_players(Visibility::All).await {
+            Ok(list) => list,
+            Err(err) => {
+                warn!("[{}] Couldn't list players: {:?}", Self::NAME, err);
+                return;
+            }
+        };
+        let max_ping = config.max_ping(&self.mapman.popstate().await.name);
+
+        let verdicts = {
+            let mut pings = self.pings.lock();
+            let online = list.iter()
+                .map(|pi| Player { name: pi.player_name.clone(), eaid: pi.eaid })
+                .collect::<HashSet<_>>();
+            pings.retain(|player, _| online.contains(player));
+
+            let now = Instant::now();
+            list.iter()
+                .filter_map(|pi| {
+                    let player = Player { name: pi.player_name.clone(), eaid: pi.eaid };
+                    let entry = pings.entry(player.clone()).or_insert_with(|| Pings::new(now));
+                    entry.sample(pi.ping, config.samples);
+                    match verdict(entry, &config, max_ping, list.len()) {
+                        Verdict::Fine => {
+                            entry.warned = 0;
+                            None
+                        },
+                        Verdict::Wait => None,
+                        verdict => Some((player, verdict)),
+                    }
+                })
+                .collect::<Vec<_>>()
+        };
+
+        for (player, verdict) in verdicts {
+            if config.whitelist.contains(&player.eaid) || self.roles.judge(&player, bf4).await.has(config.immune) {
+                continue;
+            }
+            match verdict {
+                Verdict::Warn { average } => {
+                    if let Some(pings) = self.pings.lock().get_mut(&player) {
+                        pings.warned += 1;
+                    }
+                    let _ = bf4.say(format!("{}: Your ping is {}ms, the limit here is {}ms. Please check your connection, or you may be kicked.",
+                        player.name, average, max_ping), player).await;
+                },
+                Verdict::Kick { average } => {
+                    info!("[{}] Kicking {} for a ping of {}ms (limit {}ms).", Self::NAME, player, average, max_ping);
+                    match bf4.kick(player.name.clone(), format!("Ping too high ({}ms, limit is {}ms)", average, max_ping)).await {
+                        Ok(()) | Err(PlayerKickError::PlayerNotFound) => (),
+                        Err(err) => warn!("[{}] Failed to kick {}: {:?}", Self::NAME, player, err),
+                    }
+                    self.pings.lock().remove(&player);
+                },
+                Verdict::Fine | Verdict::Wait => (),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Plugin for PingKicker {
+    const NAME: &'static str = "pingkicker";
+    type Config = Config;
+
+    fn enabled(&self) -> bool {
+        self.config().enabled
+    }
+
+    async fn reconfigure(self: &Arc<Self>, _bf4: &Arc<Bf4Client>, config: Config) -> Result<(), ConfigError> {
+        *self.config.write() = Arc::new(config);
+        Ok(())
+    }
+
+    async fn start(self: &Arc<Self>, bf4: &Arc<Bf4Client>) {
+        let myself = self.clone();
+        let bf4 = bf4.clone();
+        tokio::spawn(async move { myself.sample_loop(bf4).await });
+    }
+
+    async fn pause(self: &Arc<Self>, _bf4: &Arc<Bf4Client>) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    async fn resume(self: &Arc<Self>, _bf4: &Arc<Bf4Client>) {
+        self.paused.store(false, Ordering::Relaxed);
+    }
+
+    async fn event(self: Arc<Self>, _bf4: Arc<Bf4Client>, event: Event) -> RconResult<()> {
+        match event {
+            // start the grace period on joining, not on the first sample.
+            Event::Authenticated { player } => {
+                self.pings.lock().insert(player, Pings::new(Instant::now()));
+            },
+            Event::Leave { player, .. } => {
+                self.pings.lock().remove(&player);
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for PingKicker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PingKicker")
+            .field("config", &self.config())
+            .field("pings", &*self.pings.lock())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn verdicts() {
+        let mut config: Config = serde_yaml::from_str(include_str!("../configs/pingkicker.yaml")).unwrap();
+        config.grace = Duration::ZERO;
+        let max = config.max_ping;
+
+        let mut pings = Pings::new(Instant::now());
+        for _ in 0..config.samples {
+            pings.sample(max as i32 + 100, config.samples);
+        }
+        // unknown pings don't count.
+        pings.sample(0, config.samples);
+        pings.sample(65535, config.samples);
+        assert_eq!(pings.average(), Some(max + 100));
+
+        assert!(matches!(verdict(&pings, &config, max, 64), Verdict::Warn { .. }));
+        pings.warned = config.warnings;
+        assert!(matches!(verdict(&pings, &config, max, 64), Verdict::Kick { .. }));
+        // not full enough to kick.
+        assert_eq!(verdict(&pings, &config, max, config.kick_at_players - 1), Verdict::Wait);
+        // a more lenient popstate.
+        assert_eq!(verdict(&pings, &config, max + 200, 64), Verdict::Fine);
+
+        let mut fresh = Pings::new(Instant::now());
+        fresh.sample(max as i32 + 100, config.samples);
+        assert_eq!(verdict(&fresh, &config, max, 64), Verdict::Wait);
+    }
+}
@@ -1,43 +1,87 @@
-use std::{collections::{HashMap}, convert::TryInto, sync::{Arc}};
+use std::{collections::{HashMap}, convert::TryInto, sync::{Arc}, time::Duration};
 
 use ascii::AsciiString;
 use async_trait::async_trait;
 use battlefield_rcon::{bf4::{Bf4Client, CommmoRose, Eaid, Event, Player}, rcon::RconResult};
-use battlefox_database::BfoxContext;
+use battlefox_database::{BfoxContext, DateTime};
 use battlefox_database::adkats::mutes::BfoxMutedPlayer;
 use itertools::Itertools;
+use parking_lot::RwLock;
 use serde::{Serialize, Deserialize};
 use battlefox_shared::mute::MuteType;
 use tokio::sync::Mutex;
 
-use crate::Plugin;
-use crate::players::{MatchError, Players};
+use crate::{ConfigError, Plugin};
+use crate::commands::{ArgKind, Command, Commands};
+use crate::guard::Guard;
+use crate::roles::Role;
+use crate::state::{self, Persistent};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PlayerMuteConfig {
     enabled: bool,
-    mute_permissions: HashMap<AsciiString, bool>,
 }
 
 pub struct PlayerMute {
-    players: Arc<Players>,
+    commands: Arc<Commands>,
     offenses: Arc<Mutex<HashMap<Eaid, MutedPlayerInfo>>>,
-    config: PlayerMuteConfig,
+    config: RwLock<PlayerMuteConfig>,
     db: BfoxContext,
 }
 
 struct MutedPlayerInfo {
     infractions: usize,
     mute_type: MuteType,
-    #[allow(dead_code)]
     reason: Option<String>
 }
 
+/// What we remember across restarts, in `state/playermute.yaml`. The mutes themselves are in the
+/// database, this is about how often muted players talked anyway.
+#[derive(Debug, Serialize, Deserialize)]
+struct PlayerMuteState {
+    offenses: Vec<SavedOffense>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedOffense {
+    eaid: Eaid,
+    infractions: usize,
+    /// `MuteType as i32`, like in the database.
+    mute_type: i32,
+    reason: Option<String>,
+}
+
+impl Persistent for PlayerMuteState {
+    const VERSION: u32 = 1;
+}
+
 #[async_trait]
 impl Plugin for PlayerMute {
     const NAME: &'static str = "playermute";
+    type Config = PlayerMuteConfig;
+
+    fn enabled(&self) -> bool { self.config.read().enabled }
+
+    async fn reconfigure(self: &Arc<Self>, _bf4: &Arc<Bf4Client>, config: PlayerMuteConfig) -> Result<(), ConfigError> {
+        *self.config.write() = config;
+        Ok(())
+    }
+
+    async fn start(self: &Arc<Self>, _bf4: &Arc<Bf4Client>) {
+        self.register_commands();
+    }
 
-    fn enabled(&self) -> bool { self.config.enabled }
+    async fn persist(self: &Arc<Self>, _bf4: &Arc<Bf4Client>) {
+        let offenses = self.offenses.lock().await.iter()
+            .map(|(&eaid, info)| SavedOffense {
+                eaid,
+                infractions: info.infractions,
+                mute_type: info.mute_type as i32,
+                reason: info.reason.clone(),
+            })
+            .collect();
+        state::save(Self::NAME, &PlayerMuteState { offenses });
+    }
 
     async fn event(self: Arc<Self>, bf4: Arc<Bf4Client>, event: Event) -> RconResult<()> {
         match event {
@@ -71,9 +115,6 @@ impl Plugin for PlayerMute {
                         let _ = dbg!(bf4.kill(player.name.clone()).await);
                         let _ = bf4.say("You are muted and are not allowed to talk in the server. You'll be kicked next time.", player.clone()).await;
                     }
-
-                    // Mute/Unmute command handling
-                    let _ = self.handle_chat_msg(bf4, player, msg).await;
                 }
             },
             Event::RoundOver { .. } => {
@@ -90,157 +131,130 @@ impl Plugin for PlayerMute {
 
 
 impl PlayerMute {
-    pub fn new(db: BfoxContext, players: Arc<Players>, config: PlayerMuteConfig) -> Self {
+    pub fn new(db: BfoxContext, commands: Arc<Commands>, config: PlayerMuteConfig) -> Self {
+        let offenses = state::load::<PlayerMuteState>(Self::NAME)
+            .map(|state| state.offenses.into_iter()
+                .filter_map(|saved| Some((saved.eaid, MutedPlayerInfo {
+                    infractions: saved.infractions,
+                    mute_type: saved.mute_type.try_into().ok()?,
+                    reason: saved.reason,
+                })))
+                .collect())
+            .unwrap_or_default();
         Self {
             db,
-            players,
-            config,
-            offenses: Arc::new(Mutex::new(HashMap::new())),
+            commands,
+            config: RwLock::new(config),
+            offenses: Arc::new(Mutex::new(offenses)),
         }
     }
 
-    async fn handle_chat_msg(
-        &self,
-        bf4: Arc<Bf4Client>,
-        player: Player,
-        mut msg: AsciiString,
-    ) -> RconResult<()> {
-
-        msg.make_ascii_lowercase();
-        let split = msg.as_str()
-            .split(' ')
-            .filter(|&s| !s.is_empty())
-            .collect::<Vec<_>>();
-
-        if split.is_empty() {
-            return Ok(())
-        }
-
-        match split[0].chars().next().unwrap() {
-            '/' | '!' | '@' | '#' => (),
-            _ => return Ok(())
-        }
+    /// Mutes a player until the round is over, for other plugins punishing someone.
+    ///
+    /// This isn't stored in the database, since round mutes are gone at the end of the round anyway.
+    pub async fn mute_for_round(&self, eaid: Eaid, reason: String) {
+        self.offenses.lock().await.entry(eaid).or_insert(MutedPlayerInfo {
+            infractions: 0,
+            mute_type: MuteType::Round,
+            reason: Some(reason),
+        });
+    }
 
-        let cmd = split[0]
-            .trim_start_matches('/')
-            .trim_start_matches('!')
-            .trim_start_matches('@')
-            .trim_start_matches('#');
-
-        if cmd.eq("mute") || cmd.eq("unmute") {
-            // Admin check
-            if !self.config.mute_permissions.contains_key(&player.name) {
-                // TODO: Should we notify about no permissions or just ignore?
-                return Ok(())
-            }
+    fn register_commands(self: &Arc<Self>) {
+        let myself = self.clone();
+        self.commands.register(
+            Command::new(Self::NAME, "mute", "Mutes a player. Type is r (round), d<days> like d2, or p (permanent).")
+                .arg("player", ArgKind::Player)
+                .arg("type", ArgKind::Word)
+                .arg("reason", ArgKind::Text)
+                .role(Role::Moderator),
+            move |bf4, inv| {
+                let myself = myself.clone();
+                async move {
+                    // all args are required.
+                    let target = inv.args.player("player").unwrap().clone();
+                    let mute_type = inv.args.word("type").unwrap().to_ascii_lowercase();
+                    let reason = inv.args.text("reason").unwrap().to_string();
+                    myself.handle_mute(bf4, inv.player, target, &mute_type, reason).await;
+                }
+            });
+
+        let myself = self.clone();
+        self.commands.register(
+            Command::new(Self::NAME, "unmute", "Removes the mute of a player.")
+                .arg("player", ArgKind::Player)
+                .role(Role::Moderator),
+            move |bf4, inv| {
+                let myself = myself.clone();
+                async move {
+                    let target = inv.args.player("player").unwrap().clone(); // required arg.
+                    myself.handle_unmute(bf4, inv.player, target).await;
+                }
+            });
+    }
 
-            if cmd.eq("mute") && split.len() < 4 {
-                let _ = bf4.say("\nMute player with: /mute <soldierName> <[r|d<days>|p]> <reason>\n\t/mute xfileFIN d2 reason\n\t/mute xfileFIN p permanent mute", &player).await;
+    async fn handle_mute(&self, bf4: Arc<Bf4Client>, player: Guard<Player, Role>, target: Player, mute_type_str: &str, reason: String) {
+        info!("Match for {} / {}", target.name, target.eaid);
 
-                return Ok(())
-            }
-            else if cmd.eq("unmute") && split.len() < 2 {
-                let _ = bf4.say("\nUnMute player with: /unmute <soldierName>", &player).await;
+        let mut mute_type = mute_type_str.chars();
+        let mut mute_player = BfoxMutedPlayer {
+            eaid: target.eaid.to_string(),
+            type_: 0,
+            end_date: None,
+            kicks: None,
+            reason: Some(reason.clone())
+        };
 
-                return Ok(())
-            }
+        match mute_type.next().unwrap() {
+            'r' => {
+                mute_player.type_ = MuteType::Round as i32;
 
-            let target = split[1];
-            info!("Target player {}", target);
-
-            match self.players.get_best_player_match(target).await {
-                Ok(best_match) => {
-                    info!("Match for {} / {}", best_match.name, best_match.eaid);
-
-                    match cmd {
-                        "mute" => {
-                            let reason = split[3..].join(" ");
-
-                            let mut mute_type = split[2].chars();
-                            let mut mute_player = BfoxMutedPlayer {
-                                eaid: best_match.eaid.to_string(),
-                                type_: 0,
-                                end_date: None,
-                                kicks: None,
-                                reason: Some(reason.clone())
-                            };
-
-                            match mute_type.next().unwrap() {
-                                'r' => {
-                                    mute_player.type_ = MuteType::Round as i32;
-
-                                },
-                                'd' => {
-                                    mute_player.type_ = MuteType::Days as i32;
-                                    if split[2].len() < 2 {
-                                        let _ = bf4.say("Invalid mute type\n\tr (round)\n\td (days) -> d2 (two days)\n\tp (permanent)", &player).await;
-                                        return Ok(())
-                                    }
-                                    match mute_type.as_str().parse::<i64>() {
-                                        Ok(_n) => {
-                                            todo!("There's some uncommented code here because of the diesel to sqlx migration"); // TODO: !!!
-                                            // mute_player.end_date = Some(Utc::now().naive_utc().add(Duration::days(n)).date());
-                                        },
-                                        Err(_) => {
-                                            let _ = bf4.say("Invalid mute type\n\tr (round)\n\td (days) -> d2 (two days)\n\tp (permanent)", &player).await;
-                                            return Ok(())
-                                        },
-                                    }
-                                },
-                                'p' => {
-                                    mute_player.type_ = MuteType::Permanent as i32;
-
-                                },
-                                _ => {
-                                    let _ = bf4.say("Invalid mute type\n\tr (round)\n\td (days) -> d2 (two days)\n\tp (permanent)", &player).await;
-                                    return Ok(())
-                                }
-                            }
+            },
+            'd' => {
+                mute_player.type_ = MuteType::Days as i32;
+                if mute_type_str.len() < 2 {
+                    let _ = bf4.say("Invalid mute type\n\tr (round)\n\td (days) -> d2 (two days)\n\tp (permanent)", &*player).await;
+                    return
+                }
+                match mute_type.as_str().parse::<u64>() {
+                    Ok(days) if days > 0 => {
+                        mute_player.end_date = Some(DateTime::now_utc() + Duration::from_secs(days * 60 * 60 * 24));
+                    },
+                    _ => {
+                        let _ = bf4.say("Invalid mute type\n\tr (round)\n\td (days) -> d2 (two days)\n\tp (permanent)", &*player).await;
+                        return
+                    },
+                }
+            },
+            'p' => {
+                mute_player.type_ = MuteType::Permanent as i32;
 
-                            let mut lock = self.offenses.lock().await;
-                            if self.try_add_mute(best_match.eaid, mute_player, &mut lock) {
-                                let _ = bf4.say(format!("{} has been muted for {}", best_match.name, reason), &player).await;
-                                let _ = bf4.say(format!("You have been muted for {}", reason), &best_match).await;
-                                return Ok(())
-                            }
-                        },
-                        "unmute" => {
-                            match self.try_get_muted_player(&best_match.eaid.to_string()) {
-                                Some(_) => {
-                                    let mut lock = self.offenses.lock().await;
-                                    if self.try_remove_mute(&best_match.eaid, &mut lock) {
-                                        let _ = bf4.say(format!("Mute for player {} has been removed.", best_match.name), &player).await;
-                                        let _ = bf4.say("You have been unmuted.", &best_match).await;
-                                        return Ok(())
-                                    }
-                                },
-                                _ => {
-                                    let _ = bf4.say(format!("Player {} wasn't muted.", best_match.name), &player).await;
-                                    return Ok(())
-                                }
-                            }
-                        },
-                        _ => warn!("Something went wrong in the mute addon.")
-                    }
-                },
-                Err(error) => {
-                    match error {
-                        MatchError::NoMatches => {
-                            warn!("No matches for {}", target);
-
-                            let _ = bf4.say(format!("Couldn't find player with name {}", target), player).await;
-                        },
-                        MatchError::TooMany => {
-                            warn!("Too many matches for {}", target);
-
-                            let _ = bf4.say(format!("Found too many players with name {}", target), player).await;
-                        },
-                    }
-                },
+            },
+            _ => {
+                let _ = bf4.say("Invalid mute type\n\tr (round)\n\td (days) -> d2 (two days)\n\tp (permanent)", &*player).await;
+                return
             }
         }
 
-        Ok(())
+        let mut lock = self.offenses.lock().await;
+        if self.try_add_mute(target.eaid, mute_player, &mut lock).await {
+            let _ = bf4.say(format!("{} has been muted for {}", target.name, reason), &*player).await;
+            let _ = bf4.say(format!("You have been muted for {}", reason), &target).await;
+        }
+    }
+
+    async fn handle_unmute(&self, bf4: Arc<Bf4Client>, player: Guard<Player, Role>, target: Player) {
+        // round mutes from other plugins are only in memory.
+        let in_memory = self.offenses.lock().await.contains_key(&target.eaid);
+        if !in_memory && self.try_get_muted_player(&target.eaid.to_string()).await.is_none() {
+            let _ = bf4.say(format!("Player {} wasn't muted.", target.name), &*player).await;
+            return;
+        }
+        let mut lock = self.offenses.lock().await;
+        if self.try_remove_mute(&target.eaid, &mut lock).await {
+            let _ = bf4.say(format!("Mute for player {} has been removed.", target.name), &*player).await;
+            let _ = bf4.say("You have been unmuted.", &target).await;
+        }
     }
 
     async fn update_players(&self) -> anyhow::Result<()> {
@@ -273,92 +287,77 @@ impl PlayerMute {
     async fn remove_round_mutes(&self) -> anyhow::Result<()> {
         debug!("Removing round muted players");
 
-        let lock = self.offenses.lock().await;
+        let mut lock = self.offenses.lock().await;
         let ids = lock.iter()
             .filter(|(_, mp)| mp.mute_type == MuteType::Round)
             .map(|(eaid, _)| eaid.to_string())
             .collect_vec();
+        lock.retain(|_, mp| mp.mute_type != MuteType::Round);
         drop(lock);
 
         self.db.delete_muted_players(&ids).await?;
         Ok(())
     }
 
-    async fn add_kicked(&self, _eaid: &Eaid) {
-        todo!()
-        // debug!("Adding kick for {}", eaid.to_string());
-
-        // let mut player = self.db.get_muted_player(eaid.to_string()).await?;
-        // player.kicks = Some(player.kicks.unwrap_or(0) + 1);
-        // match replace_into_muted_player(&con, &player) {
-        //     Ok(_) => debug!("Added kick for {}", eaid.to_string()),
-        //     Err(_) => debug!("Failed to add kick for {}", eaid.to_string()),
-        // }
+    /// Counts the kick in the database. Round mutes from other plugins have no row there, so for
+    /// them this does nothing.
+    async fn add_kicked(&self, eaid: &Eaid) {
+        debug!("Adding kick for {}", eaid);
+        match self.db.add_muted_player_kick(eaid.to_string()).await {
+            Ok(()) => debug!("Added kick for {}", eaid),
+            Err(err) => error!("Failed to add kick for {}: {:?}", eaid, err),
+        }
     }
 
-    fn try_get_muted_player(&self, _eaid: &str) -> Option<BfoxMutedPlayer> {
-        todo!()
-        // match establish_connection() {
-        //     Ok(con) => {
-        //         if let Ok(player) = get_muted_player(&con, eaid) {
-        //             return Some(player);
-        //         }
-        //     },
-        //     Err(error) => error!("Failed to connect to database: {}", error),
-        // }
-
-        // None
+    async fn try_get_muted_player(&self, eaid: &str) -> Option<BfoxMutedPlayer> {
+        match self.db.get_muted_player(eaid).await {
+            Ok(player) => player,
+            Err(err) => {
+                error!("Failed to look up the mute of {}: {:?}", eaid, err);
+                None
+            },
+        }
     }
 
-    fn try_remove_mute(&self, _eaid: &Eaid, _muted_players: &mut HashMap<Eaid, MutedPlayerInfo>) -> bool {
-        todo!()
-        // debug!("Removing player {} from muted players", eaid.to_string());
-
-        // match establish_connection() {
-        //     Ok(con) => {
-        //         let result = delete_muted_player(&con, eaid.to_string());
-        //         match result {
-        //             Ok(_) => {
-        //                 debug!("Removed mute from: {:#?}", eaid);
-        //                 if muted_players.remove(eaid).is_none() {
-        //                     return false
-        //                 }
-
-        //                 return true
-        //             },
-        //             Err(err) => error!("Error trying to remove mute from {}: {}", eaid, err),
-        //         }
-        //     },
-        //     Err(error) => error!("Failed to connect to database: {}", error),
-        // }
-
-        // false
+    async fn try_remove_mute(&self, eaid: &Eaid, muted_players: &mut HashMap<Eaid, MutedPlayerInfo>) -> bool {
+        debug!("Removing player {} from muted players", eaid);
+
+        match self.db.delete_muted_players(&[eaid.to_string()]).await {
+            Ok(()) => {
+                debug!("Removed mute from: {:#?}", eaid);
+                muted_players.remove(eaid);
+                true
+            },
+            Err(err) => {
+                error!("Error trying to remove mute from {}: {:?}", eaid, err);
+                false
+            },
+        }
     }
 
-    fn try_add_mute(&self, _eaid: Eaid, _player: BfoxMutedPlayer, _muted_players: &mut HashMap<Eaid, MutedPlayerInfo>) -> bool {
-        todo!()
-        // debug!("Adding player {} to muted players", eaid.to_string());
-
-        // match establish_connection() {
-        //     Ok(con) => {
-        //         let result = replace_into_muted_player(&con, &player);
-        //         match result {
-        //             Ok(_) => {
-        //                 muted_players.entry(eaid).or_insert(MutedPlayerInfo {
-        //                     infractions: 0,
-        //                     mute_type: player.type_.try_into().unwrap(),
-        //                     reason: player.reason.clone()
-        //                 });
-
-        //                 debug!("Added mute for: {:#?}", eaid);
-        //                 return true
-        //             },
-        //             Err(err) => error!("Error trying to remove mute from {}: {}", eaid, err),
-        //         }
-        //     },
-        //     Err(error) => error!("Failed to connect to database: {}", error),
-        // }
-
-        // false
+    async fn try_add_mute(&self, eaid: Eaid, player: BfoxMutedPlayer, muted_players: &mut HashMap<Eaid, MutedPlayerInfo>) -> bool {
+        debug!("Adding player {} to muted players", eaid);
+
+        let mute_type = match player.type_.try_into() {
+            Ok(mute_type) => mute_type,
+            Err(()) => return false,
+        };
+        match self.db.replace_into_muted_player(&player).await {
+            Ok(()) => {
+                // replaces an earlier mute, but keeps counting how often they talked anyway.
+                let infractions = muted_players.get(&eaid).map_or(0, |info| info.infractions);
+                muted_players.insert(eaid, MutedPlayerInfo {
+                    infractions,
+                    mute_type,
+                    reason: player.reason.clone(),
+                });
+                debug!("Added mute for: {:#?}", eaid);
+                true
+            },
+            Err(err) => {
+                error!("Error trying to add a mute for {}: {:?}", eaid, err);
+                false
+            },
+        }
     }
-}
\ No newline at end of file
+}
@@ -11,6 +11,7 @@ use strsim::levenshtein;
 use tokio::sync::Mutex;
 
 use crate::Plugin;
+use crate::supervisor::RestartPolicy;
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct PlayerInServer {
@@ -221,6 +222,12 @@ impl Players {
 #[async_trait]
 impl Plugin for Players {
     const NAME: &'static str = "players";
+    type Config = ();
+
+    /// Lots of other plugins rely on the player list, no point in running without it.
+    fn restart_policy(&self) -> RestartPolicy { RestartPolicy::StopAll }
+
+    fn pausable(&self) -> bool { false }
 
     async fn event(self: Arc<Self>, _bf4: Arc<Bf4Client>, event: Event) -> RconResult<()> {
         let now = Instant::now();
@@ -0,0 +1,246 @@
+//! Server settings which follow the population, like a seeding mode with infantry only and
+//! more tickets.
+//!
+//! Each popstate of `MapManager` can have a profile. When the popstate changes, the profile's
+//! settings are applied at the end of the round, so that nothing changes in the middle of one.
+//! Tickets, vehicles and preset are also handed to `MapManager`, which would otherwise set its
+//! own when switching maps. When the next round starts, the new profile is announced.
+
+use std::sync::{atomic::{AtomicBool, Ordering}, Arc};
+use std::{collections::HashMap, time::Duration};
+
+use async_trait::async_trait;
+use battlefield_rcon::{bf4::{defs::Preset, Bf4Client, Event, Visibility}, rcon::{RconError, RconResult}};
+use parking_lot::{Mutex, RwLock};
+use serde::Deserialize;
+
+use crate::mapmanager::{CallbackResult, MapManager, SwitchOverrides};
+use crate::{ConfigError, Plugin};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    enabled: bool,
+    /// Popstate name -> profile. Popstates without one leave the settings alone.
+    #[serde(default)]
+    profiles: HashMap<String, Profile>,
+}
+
+/// Settings left out stay as they are.
+#[derive(Debug, Clone, Deserialize)]
+struct Profile {
+    /// For the announcement, e.g. "Seeding mode". Defaults to the popstate name.
+    name: Option<String>,
+    /// Instead of the generated "Seeding mode active: infantry only, 200% tickets".
+    announce: Option<String>,
+    /// In percent of the default, `vars.gameModeCounter`.
+    tickets: Option<usize>,
+    preset: Option<Preset>,
+    /// `false` for infantry only.
+    vehicles: Option<bool>,
+    /// In percent of the default, `vars.vehicleSpawnDelay`.
+    vehicle_spawn_delay: Option<usize>,
+    /// In percent of the default, `vars.roundTimeLimit`.
+    round_time: Option<usize>,
+    /// The server's own idle kick, `vars.idleTimeout`. `0s` disables it.
+    #[serde(default, with = "humantime_serde")]
+    idle_timeout: Option<Duration>,
+}
+
+impl Profile {
+    fn overrides(&self) -> SwitchOverrides {
+        SwitchOverrides {
+            tickets: self.tickets,
+            vehicles: self.vehicles,
+            preset: self.preset,
+            vehicle_spawn_delay: self.vehicle_spawn_delay,
+        }
+    }
+
+    fn announcement(&self, popstate: &str) -> String {
+        if let Some(announce) = &self.announce {
+            return announce.clone();
+        }
+        let mut parts = Vec::new();
+        match self.vehicles {
+            Some(false) => parts.push("infantry only".to_string()),
+            Some(true) => parts.push("vehicles".to_string()),
+            None => (),
+        }
+        if let Some(tickets) = self.tickets {
+            parts.push(format!("{}% tickets", tickets));
+        }
+        if let Some(round_time) = self.round_time {
+            parts.push(format!("{}% round time", round_time));
+        }
+        if let Some(preset) = self.preset {
+            parts.push(format!("{:?}", preset).to_lowercase());
+        }
+        let name = self.name.as_deref().unwrap_or(popstate);
+        if parts.is_empty() {
+            format!("{} active", name)
+        } else {
+            format!("{} active: {}", name, parts.join(", "))
+        }
+    }
+
+    async fn apply(&self, bf4: &Bf4Client) -> Result<(), RconError> {
+        if let Some(tickets) = self.tickets {
+            bf4.set_tickets(tickets).await?;
+        }
+        if let Some(preset) = self.preset {
+            bf4.set_preset(preset).await?;
+        }
+        if let Some(vehicles) = self.vehicles {
+            bf4.set_vehicles_spawn_allowed(vehicles).await?;
+        }
+        if let Some(delay) = self.vehicle_spawn_delay {
+            bf4.set_vehicle_spawn_delay(delay).await?;
+        }
+        if let Some(round_time) = self.round_time {
+            bf4.set_round_time_limit(round_time).await?;
+        }
+        if let Some(idle) = self.idle_timeout {
+            bf4.set_idle_timeout(idle.as_secs() as usize).await?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Profiles {
+    config: RwLock<Arc<Config>>,
+    mapman: Arc<MapManager>,
+    /// The popstate whose profile is applied at the end of the round.
+    pending: Mutex<Option<String>>,
+    /// Announced when the next round starts.
+    announcement: Mutex<Option<String>>,
+    between_rounds: AtomicBool,
+}
+
+impl Profiles {
+    pub fn new(mapman: Arc<MapManager>, config: Config) -> Self {
+        Self {
+            config: RwLock::new(Arc::new(config)),
+            mapman,
+            pending: Mutex::new(None),
+            announcement: Mutex::new(None),
+            between_rounds: AtomicBool::new(false),
+        }
+    }
+
+    fn config(&self) -> Arc<Config> {
+        self.config.read().clone()
+    }
+
+    /// Queues the profile of `popstate`, or applies it right away if we're between rounds.
+    async fn popstate_changed(&self, bf4: &Bf4Client, popstate: String) {
+        let config = self.config();
+        let overrides = config.profiles.get(&popstate).map(Profile::overrides).unwrap_or_default();
+        // right away, since the next map switch is already between rounds.
+        self.mapman.set_overrides(overrides);
+
+        *self.pending.lock() = Some(popstate);
+        if self.between_rounds.load(Ordering::Relaxed) {
+            self.apply_pending(bf4).await;
+        }
+    }
+
+    async fn apply_pending(&self, bf4: &Bf4Client) {
+        let popstate = match self.pending.lock().take() {
+            Some(popstate) => popstate,
+            None => return,
+        };
+        let config = self.config();
+        let profile = match config.profiles.get(&popstate) {
+            Some(profile) => profile,
+            None => return,
+        };
+        match profile.apply(bf4).await {
+            Ok(()) => {
+                info!("[{}] Applied the profile for {}.", Self::NAME, popstate);
+                *self.announcement.lock() = Some(profile.announcement(&popstate));
+            },
+            Err(err) => error!("[{}] Failed to apply the profile for {}: {:?}", Self::NAME, popstate, err),
+        }
+    }
+}
+
+#[async_trait]
+impl Plugin for Profiles {
+    const NAME: &'static str = "profiles";
+    type Config = Config;
+
+    fn enabled(&self) -> bool {
+        self.config().enabled
+    }
+
+    async fn reconfigure(self: &Arc<Self>, bf4: &Arc<Bf4Client>, config: Config) -> Result<(), ConfigError> {
+        *self.config.write() = Arc::new(config);
+        self.popstate_changed(bf4, self.mapman.popstate().await.name).await;
+        Ok(())
+    }
+
+    async fn start(self: &Arc<Self>, bf4: &Arc<Bf4Client>) {
+        let weak = Arc::downgrade(self);
+        self.mapman.register_pool_change_callback(move |bf4, popstate| {
+            let weak = weak.clone();
+            Box::pin(async move {
+                match weak.upgrade() {
+                    Some(myself) => {
+                        tokio::spawn(async move { myself.popstate_changed(&bf4, popstate.name).await });
+                        CallbackResult::KeepGoing
+                    },
+                    None => CallbackResult::RemoveMe,
+                }
+            })
+        });
+        // we don't know what the server is set to, so get the current profile going.
+        self.popstate_changed(bf4, self.mapman.popstate().await.name).await;
+    }
+
+    async fn event(self: Arc<Self>, bf4: Arc<Bf4Client>, event: Event) -> RconResult<()> {
+        match event {
+            Event::RoundOver { .. } => {
+                self.between_rounds.store(true, Ordering::Relaxed);
+                self.apply_pending(&bf4).await;
+            },
+            Event::LevelLoaded { .. } => {
+                self.between_rounds.store(false, Ordering::Relaxed);
+                let announcement = self.announcement.lock().take();
+                if let Some(announcement) = announcement {
+                    // give everyone a moment to load in.
+                    tokio::time::sleep(Duration::from_secs(20)).await;
+                    let _ = bf4.say(announcement.clone(), Visibility::All).await;
+                    let _ = bf4.yell(announcement, Visibility::All).await;
+                }
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for Profiles {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Profiles")
+            .field("config", &self.config())
+            .field("pending", &*self.pending.lock())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn announcements() {
+        let config: Config = serde_yaml::from_str(include_str!("../configs/profiles.yaml")).unwrap();
+        let seeding = &config.profiles["SeedingPopulation"];
+        assert_eq!(seeding.announcement("SeedingPopulation"), "Seeding mode active: infantry only, 200% tickets");
+        assert_eq!(seeding.overrides().vehicles, Some(false));
+        assert_eq!(config.profiles["HighPopulation"].overrides().vehicle_spawn_delay, Some(100));
+
+        let profile: Profile = serde_yaml::from_str("round_time: 150").unwrap();
+        assert_eq!(profile.announcement("HighPopulation"), "HighPopulation active: 150% round time");
+    }
+}
@@ -0,0 +1,82 @@
+//! Punishments shared by plugins which escalate, like `chatfilter`, `floodguard` and `teamkilling`.
+//!
+//! In configs, a ladder of punishments looks like `[ warn, kill, mute, kick, tempban: 1day ]`.
+
+use std::{fmt::Display, time::Duration};
+
+use battlefield_rcon::bf4::{ban_list::{Ban, BanTimeout}, Bf4Client, Player, PlayerKickError};
+use serde::{Deserialize, Serialize};
+
+use crate::playermute::PlayerMute;
+use crate::Plugin;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Punishment {
+    Warn,
+    Kill,
+    /// Round mute via `PlayerMute`.
+    Mute,
+    Kick,
+    Tempban(#[serde(with = "humantime_serde")] Duration),
+}
+
+impl Display for Punishment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Punishment::Warn => write!(f, "warn"),
+            Punishment::Kill => write!(f, "kill"),
+            Punishment::Mute => write!(f, "mute"),
+            Punishment::Kick => write!(f, "kick"),
+            Punishment::Tempban(duration) => write!(f, "tempban {}", humantime::format_duration(*duration)),
+        }
+    }
+}
+
+/// The punishment for the `offence`th offence (starting at 1). After the end of the ladder, it
+/// stays at the last one. `None` if the ladder is empty.
+pub fn step(ladder: &[Punishment], offence: usize) -> Option<&Punishment> {
+    ladder.get(offence.saturating_sub(1)).or_else(|| ladder.last())
+}
+
+/// Punishes `player`, and tells them why. `reason` is short, like "Watch your language".
+///
+/// `by` is the name of the plugin doing it, for the logs.
+pub async fn punish(bf4: &Bf4Client, playermute: &PlayerMute, by: &str, player: &Player, punishment: &Punishment, reason: &str) {
+    info!("[{}] {:?} for {}: {}", by, punishment, player, reason);
+    match punishment {
+        Punishment::Warn => {
+            let _ = bf4.say(format!("{}: {}! Next time you'll be punished.", player.name, reason), player).await;
+        },
+        Punishment::Kill => {
+            let _ = bf4.kill(player.name.clone()).await;
+            let _ = bf4.say(format!("{}: Killed. {}!", player.name, reason), player).await;
+        },
+        Punishment::Mute => {
+            if playermute.enabled() {
+                playermute.mute_for_round(player.eaid, reason.to_string()).await;
+                let _ = bf4.say(format!("{}: You are muted for the rest of the round. {}!", player.name, reason), player).await;
+            } else {
+                warn!("[{}] Wanted to mute {}, but {} is disabled. Killing instead.", by, player, PlayerMute::NAME);
+                let _ = bf4.kill(player.name.clone()).await;
+                let _ = bf4.say(format!("{}: Killed. {}!", player.name, reason), player).await;
+            }
+        },
+        Punishment::Kick => {
+            match bf4.kick(player.name.clone(), format!("{}!", reason)).await {
+                Ok(()) | Err(PlayerKickError::PlayerNotFound) => (),
+                Err(err) => warn!("[{}] Failed to kick {}: {:?}", by, player, err),
+            }
+        },
+        Punishment::Tempban(duration) => {
+            let reason = format!("{}! (banned for {})", reason, humantime::format_duration(*duration));
+            if let Err(err) = bf4.ban_add(Ban::Guid(player.eaid), BanTimeout::Time(*duration), Some(reason.clone())).await {
+                warn!("[{}] Failed to ban {}: {:?}", by, player, err);
+            }
+            match bf4.kick(player.name.clone(), reason).await {
+                Ok(()) | Err(PlayerKickError::PlayerNotFound) => (),
+                Err(err) => warn!("[{}] Failed to kick {}: {:?}", by, player, err),
+            }
+        },
+    }
+}
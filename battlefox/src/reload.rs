@@ -0,0 +1,110 @@
+//! Reloads plugin configs from `configs/<NAME>.yaml` while BattleFox is running.
+//!
+//! Config files are polled for changes, and admins can also trigger a reload manually via
+//! `/bfox reload [plugin]`.
+
+use std::{collections::BTreeMap, sync::Arc, time::{Duration, SystemTime}};
+
+use battlefield_rcon::bf4::Bf4Client;
+use parking_lot::Mutex;
+
+use crate::{config_path, ConfigError, Plugin2};
+
+/// How often we check the config files' modification times.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+struct Entry {
+    plugin: Arc<dyn Plugin2>,
+    /// Modification time of the config file when we last looked at it.
+    /// `None` if the plugin has no config file.
+    mtime: Option<SystemTime>,
+}
+
+pub struct ConfigReloader {
+    plugins: Mutex<BTreeMap<&'static str, Entry>>,
+}
+
+impl ConfigReloader {
+    pub(crate) fn new() -> Self {
+        Self {
+            plugins: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub(crate) fn register(&self, name: &'static str, plugin: Arc<dyn Plugin2>) {
+        let mtime = mtime(name);
+        self.plugins.lock().insert(name, Entry { plugin, mtime });
+    }
+
+    /// Names of all plugins which have a config file.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.plugins.lock().iter()
+            .filter(|(_, entry)| entry.mtime.is_some())
+            .map(|(&name, _)| name)
+            .collect()
+    }
+
+    /// Re-reads the config of a single plugin and hands it to the plugin.
+    ///
+    /// On any error, the plugin keeps running with its old config.
+    pub async fn reload(&self, bf4: &Arc<Bf4Client>, name: &str) -> Result<(), ConfigError> {
+        let plugin = {
+            let mut lock = self.plugins.lock();
+            let entry = lock.get_mut(name)
+                .ok_or_else(|| ConfigError::Invalid(format!("No plugin named {}", name)))?;
+            // remember the mtime even if the reload fails, so we don't retry a broken file every
+            // few seconds. Fixing it will change the mtime again anyway.
+            entry.mtime = mtime(name);
+            entry.plugin.clone()
+        };
+
+        match plugin.reload_config(bf4.clone()).await {
+            Ok(()) => {
+                info!("Reloaded config of plugin {}.", name);
+                Ok(())
+            },
+            Err(err) => {
+                warn!("Failed to reload config of plugin {}, keeping the old one: {:?}", name, err);
+                Err(err)
+            }
+        }
+    }
+
+    /// Reloads the configs of all plugins whose config file has changed since we last looked.
+    pub async fn reload_changed(&self, bf4: &Arc<Bf4Client>) {
+        let changed = {
+            let lock = self.plugins.lock();
+            lock.iter()
+                .filter(|(&name, entry)| entry.mtime.is_some() && mtime(name) != entry.mtime)
+                .map(|(&name, _)| name)
+                .collect::<Vec<_>>()
+        };
+
+        for name in changed {
+            debug!("Config file of plugin {} has changed.", name);
+            let _ = self.reload(bf4, name).await;
+        }
+    }
+
+    /// Polls the config files for changes forever.
+    pub async fn watch(&self, bf4: Arc<Bf4Client>) {
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            self.reload_changed(&bf4).await;
+        }
+    }
+}
+
+fn mtime(plugin_name: &str) -> Option<SystemTime> {
+    std::fs::metadata(config_path(plugin_name))
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+impl std::fmt::Debug for ConfigReloader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConfigReloader")
+            .field("plugins", &self.plugins.lock().keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
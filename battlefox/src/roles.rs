@@ -0,0 +1,215 @@
+//! Who may do what: Every player has a `Role`, and commands require some minimum role.
+//!
+//! Roles are assigned in `configs/roles.yaml` by EA GUID, since soldier names can be changed.
+//! Players on the VIP list are at least `Role::Vip`, everyone else is `Role::Player`.
+//!
+//! Admins from the old `configs/admins.yaml` (by soldier name) are still admins, until they're
+//! moved over to `roles.yaml`.
+
+use std::{collections::{BTreeSet, HashMap}, path::Path, sync::Arc};
+
+use async_trait::async_trait;
+use battlefield_rcon::bf4::{Bf4Client, Eaid, Player};
+use either::{Left, Right};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+
+use crate::guard::{recent::Age, Guard, Judgement};
+use crate::vips::Vips;
+use crate::{config_path, load_config, ConfigError, Plugin};
+
+/// From least to most powerful. Each role can do everything the roles before it can.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Player,
+    Seeder,
+    Vip,
+    Moderator,
+    Admin,
+    Owner,
+}
+
+impl Judgement<Player> for Role {}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Role::Player => "player",
+            Role::Seeder => "seeder",
+            Role::Vip => "vip",
+            Role::Moderator => "moderator",
+            Role::Admin => "admin",
+            Role::Owner => "owner",
+        };
+        f.write_str(s)
+    }
+}
+
+impl Guard<Player, Role> {
+    pub fn role(&self) -> Role {
+        // reading is fine, the judgement can't be changed through this.
+        unsafe { *self.get_judgement() }
+    }
+
+    /// Whether this player may do things which require `role`.
+    pub fn has(&self, role: Role) -> bool {
+        self.role() >= role
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// EA GUID -> role.
+    #[serde(default)]
+    players: HashMap<Eaid, Role>,
+    /// Command name (e.g. `mute` or `bfox reload`) -> role needed to use it.
+    /// Overrides what the plugin which offers the command declared.
+    #[serde(default)]
+    commands: HashMap<String, Role>,
+}
+
+/// The example GUID from the sample `roles.yaml`, which nobody has.
+const PLACEHOLDER: &str = "EA_0123456789ABCDEF0123456789ABCDEF";
+
+impl Config {
+    /// Whether anyone (other than the placeholder) is admin or owner.
+    fn has_admin(&self) -> bool {
+        self.players.iter().any(|(eaid, &role)| role >= Role::Admin && eaid.to_string() != PLACEHOLDER)
+    }
+}
+
+/// What `configs/admins.yaml` used to look like.
+#[derive(Debug, Deserialize)]
+struct LegacyAdmins {
+    admins: BTreeSet<String>,
+}
+
+/// Soldier names from `configs/admins.yaml`, if it's still around.
+pub fn load_legacy_admins() -> BTreeSet<String> {
+    let path = config_path("admins");
+    if !Path::new(&path).exists() {
+        return BTreeSet::new();
+    }
+    match load_config::<LegacyAdmins>(&path) {
+        Ok(legacy) => {
+            warn!("{} is deprecated. {} are admins by soldier name until you add their EA GUIDs to {}.",
+                path, legacy.admins.iter().cloned().collect::<Vec<_>>().join(", "), config_path(Roles::NAME));
+            legacy.admins
+        },
+        Err(err) => {
+            error!("{}: {}", path, err);
+            BTreeSet::new()
+        },
+    }
+}
+
+#[derive(Debug)]
+pub struct Roles {
+    config: RwLock<Config>,
+    /// Soldier names from the old `configs/admins.yaml`, who are `Role::Admin`.
+    legacy_admins: BTreeSet<String>,
+    vips: Arc<Vips>,
+}
+
+impl Roles {
+    pub fn new(vips: Arc<Vips>, legacy_admins: BTreeSet<String>, config: Config) -> Self {
+        let roles = Self {
+            config: RwLock::new(config),
+            legacy_admins,
+            vips,
+        };
+        roles.warn_without_admin();
+        roles
+    }
+
+    /// Nobody could use admin commands otherwise, and it's easy to miss.
+    fn warn_without_admin(&self) {
+        if self.legacy_admins.is_empty() && !self.config.read().has_admin() {
+            error!("Nobody is admin or owner in {}! Add your own EA GUID there, `!bfox role` shows it.", config_path(Self::NAME));
+        }
+    }
+
+    /// Looks up the role of a player, and hands out the proof for it.
+    ///
+    /// This is the only place where `Guard<Player, Role>` is constructed.
+    pub async fn judge(&self, player: &Player, bf4: &Bf4Client) -> Guard<Player, Role> {
+        let configured = self.config.read().players.get(&player.eaid).copied();
+        let configured = if self.legacy_admins.contains(player.name.as_str()) {
+            configured.max(Some(Role::Admin))
+        } else {
+            configured
+        };
+        let role = match configured {
+            Some(role) if role >= Role::Vip => role,
+            configured => {
+                if self.is_vip(player, bf4).await {
+                    Role::Vip
+                } else {
+                    configured.unwrap_or(Role::Player)
+                }
+            }
+        };
+        unsafe { Guard::new_raw(player.clone(), role) }
+    }
+
+    async fn is_vip(&self, player: &Player, bf4: &Bf4Client) -> bool {
+        match self.vips.get_player(player, bf4).await {
+            Ok(vip) => match vip.cases() {
+                Age::Recent(vip) => match vip.cases() {
+                    Left(_) => true,
+                    Right(_) => false,
+                },
+                Age::Old => false,
+            },
+            Err(err) => {
+                warn!("[{}] Couldn't check whether {} is VIP: {:?}", Self::NAME, player, err);
+                false
+            }
+        }
+    }
+
+    /// The role needed for a command, taking the overrides from the config into account.
+    pub fn required(&self, command: &str, default: Role) -> Role {
+        self.config.read().commands.get(command).copied().unwrap_or(default)
+    }
+}
+
+#[async_trait]
+impl Plugin for Roles {
+    const NAME: &'static str = "roles";
+    type Config = Config;
+
+    async fn reconfigure(self: &Arc<Self>, _bf4: &Arc<Bf4Client>, config: Config) -> Result<(), ConfigError> {
+        *self.config.write() = config;
+        self.warn_without_admin();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn config() {
+        let config: Config = serde_yaml::from_str(r#"
+players:
+  EA_0123456789ABCDEF0123456789ABCDEF: moderator
+commands:
+  bfox reload: moderator
+"#).unwrap();
+        assert_eq!(config.players.values().next(), Some(&Role::Moderator));
+        assert_eq!(config.commands.get("bfox reload"), Some(&Role::Moderator));
+
+        assert!(!config.has_admin());
+
+        let config: Config = serde_yaml::from_str(include_str!("../configs/roles.yaml")).unwrap();
+        assert!(!config.has_admin(), "the placeholder doesn't count");
+        let config: Config = serde_yaml::from_str("players:\n  EA_FEDCBA9876543210FEDCBA9876543210: owner\n").unwrap();
+        assert!(config.has_admin());
+
+        let legacy: LegacyAdmins = serde_yaml::from_str(include_str!("../configs/admins.yaml")).unwrap();
+        assert!(legacy.admins.contains("Kiiyya"));
+    }
+}
@@ -0,0 +1,368 @@
+//! Announces the best players of each team at the end of the round, the MVP, and a few things
+//! which happened during it: most headshots, most teamkills, and the best weapon.
+//!
+//! The final kills, deaths and scores come from `server.onRoundOverPlayers`. Headshots, teamkills
+//! and weapons are counted from kills during the round. Optionally, the summary is also posted to
+//! a webhook, and appended to a file (one JSON object per line).
+
+use std::{cmp::Reverse, collections::HashMap, path::PathBuf, sync::Arc};
+
+use async_trait::async_trait;
+use battlefield_rcon::{bf4::{player_info_block::PlayerInfo, Bf4Client, Event, Player, Team, Visibility, Weapon}, rcon::RconResult};
+use chrono::{DateTime, Utc};
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+
+use crate::feed::Feed;
+use crate::players::Players;
+use crate::{ConfigError, Plugin};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    enabled: bool,
+    /// How many players per team to list for score, K/D and kills.
+    top: usize,
+    /// Players with fewer kills don't make it into the K/D list, so that 1/0 doesn't win.
+    kd_min_kills: i32,
+    /// Also announce the summary in chat. Otherwise, it's only published.
+    announce: bool,
+    /// POSTed there as JSON. `content` holds the text, so that Discord webhooks work as they are.
+    webhook: Option<String>,
+    /// Appended to as one JSON object per line.
+    file: Option<PathBuf>,
+}
+
+/// Counted from kill events during the round.
+#[derive(Debug, Default)]
+struct RoundStats {
+    headshots: usize,
+    teamkills: usize,
+    weapons: HashMap<Weapon, usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct Entry {
+    name: String,
+    kills: i32,
+    deaths: i32,
+    score: i32,
+}
+
+impl Entry {
+    fn new(pi: &PlayerInfo) -> Self {
+        Self {
+            name: pi.player_name.to_string(),
+            kills: pi.kills,
+            deaths: pi.deaths,
+            score: pi.score,
+        }
+    }
+
+    fn kd(&self) -> f32 {
+        self.kills as f32 / self.deaths.max(1) as f32
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct TeamSummary {
+    team: &'static str,
+    by_score: Vec<Entry>,
+    by_kd: Vec<Entry>,
+    by_kills: Vec<Entry>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct Notable {
+    name: String,
+    count: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    weapon: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Summary {
+    time: DateTime<Utc>,
+    map: Option<String>,
+    mode: Option<String>,
+    winner: Option<&'static str>,
+    teams: Vec<TeamSummary>,
+    mvp: Option<Entry>,
+    most_headshots: Option<Notable>,
+    most_teamkills: Option<Notable>,
+    best_weapon: Option<Notable>,
+}
+
+fn team_name(team: Team) -> &'static str {
+    match team {
+        Team::One => "US",
+        Team::Two => "RU",
+        Team::Neutral => "Neutral",
+    }
+}
+
+/// The player with the highest nonzero count, if any. Ties go to whoever comes first by name, so
+/// that it doesn't depend on `HashMap` order.
+fn most<'a>(stats: impl Iterator<Item = (&'a Player, usize, Option<String>)>) -> Option<Notable> {
+    stats
+        .filter(|(_, count, _)| *count > 0)
+        .min_by(|(a, a_count, _), (b, b_count, _)| b_count.cmp(a_count).then_with(|| a.name.cmp(&b.name)))
+        .map(|(player, count, weapon)| Notable { name: player.name.to_string(), count, weapon })
+}
+
+fn summarise(config: &Config, players: &[PlayerInfo], stats: &HashMap<Player, RoundStats>) -> Summary {
+    let teams = [Team::One, Team::Two].iter()
+        .map(|&team| {
+            let members = players.iter().filter(|pi| pi.team == team).map(Entry::new).collect::<Vec<_>>();
+            let top = |mut entries: Vec<Entry>| {
+                entries.truncate(config.top);
+                entries
+            };
+            let mut by_score = members.clone();
+            by_score.sort_by_key(|entry| Reverse(entry.score));
+            let mut by_kd = members.iter().filter(|entry| entry.kills >= config.kd_min_kills).cloned().collect::<Vec<_>>();
+            by_kd.sort_by(|a, b| b.kd().total_cmp(&a.kd()));
+            let mut by_kills = members;
+            by_kills.sort_by_key(|entry| Reverse(entry.kills));
+            TeamSummary {
+                team: team_name(team),
+                by_score: top(by_score),
+                by_kd: top(by_kd),
+                by_kills: top(by_kills),
+            }
+        })
+        .filter(|team| !team.by_score.is_empty())
+        .collect();
+
+    Summary {
+        time: Utc::now(),
+        map: None,
+        mode: None,
+        winner: None,
+        teams,
+        mvp: players.iter().max_by_key(|pi| (pi.score, pi.kills)).map(Entry::new),
+        most_headshots: most(stats.iter().map(|(player, stats)| (player, stats.headshots, None))),
+        most_teamkills: most(stats.iter().map(|(player, stats)| (player, stats.teamkills, None))),
+        best_weapon: most(stats.iter().filter_map(|(player, stats)| {
+            let (weapon, kills) = stats.weapons.iter().max_by_key(|(weapon, &kills)| (kills, Reverse(weapon.to_string())))?;
+            Some((player, *kills, Some(weapon.to_string())))
+        })),
+    }
+}
+
+impl Summary {
+    /// For chat, one line each.
+    fn lines(&self) -> Vec<String> {
+        let list = |entries: &[Entry], value: &dyn Fn(&Entry) -> String| {
+            entries.iter().map(|entry| format!("{} ({})", entry.name, value(entry))).collect::<Vec<_>>().join(", ")
+        };
+        let mut lines = Vec::new();
+        for team in &self.teams {
+            lines.push(format!("{} top score: {}", team.team, list(&team.by_score, &|entry| entry.score.to_string())));
+            if !team.by_kd.is_empty() {
+                lines.push(format!("{} best K/D: {}", team.team, list(&team.by_kd, &|entry| format!("{:.2}", entry.kd()))));
+            }
+            lines.push(format!("{} most kills: {}", team.team, list(&team.by_kills, &|entry| entry.kills.to_string())));
+        }
+        if let Some(mvp) = &self.mvp {
+            lines.push(format!("MVP: {} with {} points, {}/{}", mvp.name, mvp.score, mvp.kills, mvp.deaths));
+        }
+        if let Some(notable) = &self.most_headshots {
+            lines.push(format!("Most headshots: {} ({})", notable.name, notable.count));
+        }
+        if let Some(notable) = &self.most_teamkills {
+            lines.push(format!("Most teamkills: {} ({})", notable.name, notable.count));
+        }
+        if let Some(Notable { name, count, weapon: Some(weapon) }) = &self.best_weapon {
+            lines.push(format!("Best weapon: {} with {} ({} kills)", name, weapon, count));
+        }
+        lines
+    }
+}
+
+/// Sent to the webhook, and written to the file.
+#[derive(Debug, Serialize)]
+struct Published<'a> {
+    content: String,
+    #[serde(flatten)]
+    summary: &'a Summary,
+}
+
+pub struct RoundSummary {
+    config: RwLock<Arc<Config>>,
+    players: Arc<Players>,
+    feed: Arc<Feed>,
+    stats: Mutex<HashMap<Player, RoundStats>>,
+    winner: Mutex<Option<Team>>,
+}
+
+impl RoundSummary {
+    pub fn new(players: Arc<Players>, feed: Arc<Feed>, config: Config) -> Self {
+        Self {
+            config: RwLock::new(Arc::new(config)),
+            players,
+            feed,
+            stats: Mutex::new(HashMap::new()),
+            winner: Mutex::new(None),
+        }
+    }
+
+    fn config(&self) -> Arc<Config> {
+        self.config.read().clone()
+    }
+
+    async fn kill(&self, killer: Player, victim: Player, weapon: Weapon, headshot: bool) {
+        if killer == victim {
+            return;
+        }
+        let teamkill = match (self.players.player(&killer).await, self.players.player(&victim).await) {
+            (Some(killer), Some(victim)) => killer.team == victim.team,
+            _ => false,
+        };
+        let mut stats = self.stats.lock();
+        let stats = stats.entry(killer).or_default();
+        if teamkill {
+            stats.teamkills += 1;
+            return;
+        }
+        if headshot {
+            stats.headshots += 1;
+        }
+        *stats.weapons.entry(weapon).or_default() += 1;
+    }
+
+    async fn round_over(&self, bf4: &Bf4Client, players: Vec<PlayerInfo>) {
+        let config = self.config();
+        let mut summary = {
+            let stats = std::mem::take(&mut *self.stats.lock());
+            summarise(&config, &players, &stats)
+        };
+        summary.winner = self.winner.lock().take().map(team_name);
+        if let Ok(info) = bf4.server_info().await {
+            summary.map = Some(info.map.Pretty().to_string());
+            summary.mode = Some(info.game_mode.to_string());
+        }
+        let lines = summary.lines();
+
+        self.feed.publish("roundsummary", &summary);
+        if config.announce {
+            for line in &lines {
+                let _ = bf4.say(line.as_str(), Visibility::All).await;
+            }
+        }
+
+        let published = Published { content: lines.join("\n"), summary: &summary };
+        if let Some(url) = &config.webhook {
+            let result = reqwest::Client::new().post(url).json(&published).send().await.and_then(|res| res.error_for_status());
+            if let Err(err) = result {
+                warn!("[{}] Failed to post the summary to the webhook: {}", Self::NAME, err);
+            }
+        }
+        if let Some(path) = &config.file {
+            if let Err(err) = append_json(path, &published).await {
+                warn!("[{}] Failed to write the summary to {}: {}", Self::NAME, path.display(), err);
+            }
+        }
+    }
+}
+
+async fn append_json(path: &PathBuf, value: &impl Serialize) -> std::io::Result<()> {
+    let mut line = serde_json::to_vec(value)?;
+    line.push(b'\n');
+    let mut file = tokio::fs::OpenOptions::new().create(true).append(true).open(path).await?;
+    file.write_all(&line).await
+}
+
+#[async_trait]
+impl Plugin for RoundSummary {
+    const NAME: &'static str = "roundsummary";
+    type Config = Config;
+
+    fn enabled(&self) -> bool {
+        self.config().enabled
+    }
+
+    async fn reconfigure(self: &Arc<Self>, _bf4: &Arc<Bf4Client>, config: Config) -> Result<(), ConfigError> {
+        *self.config.write() = Arc::new(config);
+        Ok(())
+    }
+
+    async fn event(self: Arc<Self>, bf4: Arc<Bf4Client>, event: Event) -> RconResult<()> {
+        match event {
+            Event::Kill { killer: Some(killer), victim, weapon, headshot } => {
+                self.kill(killer, victim, weapon, headshot).await;
+            },
+            Event::RoundOver { winning_team } => {
+                *self.winner.lock() = Some(winning_team);
+            },
+            Event::RoundOverPlayers { players } => {
+                self.round_over(&bf4, players).await;
+            },
+            Event::LevelLoaded { .. } => {
+                // in case the round ended without `onRoundOverPlayers`, e.g. an admin switched maps.
+                self.stats.lock().clear();
+                *self.winner.lock() = None;
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for RoundSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RoundSummary")
+            .field("config", &self.config())
+            .field("stats", &*self.stats.lock())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ascii::IntoAsciiString;
+
+    use super::*;
+    use crate::testutil::player_info;
+
+    fn pi(name: &str, eaid: u8, team: Team, kills: i32, deaths: i32, score: i32) -> PlayerInfo {
+        PlayerInfo { kills, deaths, score, ..player_info(name, eaid, team) }
+    }
+
+    fn player(pi: &PlayerInfo) -> Player {
+        Player { name: pi.player_name.clone(), eaid: pi.eaid }
+    }
+
+    #[test]
+    fn summary() {
+        let mut config: Config = serde_yaml::from_str(include_str!("../configs/roundsummary.yaml")).unwrap();
+        config.top = 2;
+        let players = vec![
+            pi("Alice", 1, Team::One, 20, 4, 4000),
+            pi("Bobby", 2, Team::One, 3, 0, 900),
+            pi("Carol", 3, Team::One, 10, 10, 2000),
+            pi("Dave", 4, Team::Two, 8, 2, 1500),
+        ];
+        let mut stats = HashMap::new();
+        stats.insert(player(&players[2]), RoundStats {
+            headshots: 4,
+            teamkills: 0,
+            weapons: maplit::hashmap! { Weapon::Other("M416".into_ascii_string().unwrap()) => 9 },
+        });
+        stats.insert(player(&players[3]), RoundStats { headshots: 1, teamkills: 2, weapons: HashMap::new() });
+
+        let summary = summarise(&config, &players, &stats);
+        assert_eq!(summary.lines(), vec![
+            "US top score: Alice (4000), Carol (2000)",
+            "US best K/D: Alice (5.00), Carol (1.00)",
+            "US most kills: Alice (20), Carol (10)",
+            "RU top score: Dave (1500)",
+            "RU best K/D: Dave (4.00)",
+            "RU most kills: Dave (8)",
+            "MVP: Alice with 4000 points, 20/4",
+            "Most headshots: Carol (4)",
+            "Most teamkills: Dave (2)",
+            "Best weapon: Carol with M416 (9 kills)",
+        ]);
+    }
+}
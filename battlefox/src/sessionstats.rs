@@ -0,0 +1,432 @@
+//! Records every player session (from joining until leaving) with its stats into the database,
+//! and shows them with `!stats [player]`.
+//!
+//! Kills, deaths and score come from the scoreboard at the end of each round, and when leaving
+//! mid-round. Headshots and weapons are counted from kills.
+//!
+//! Sessions still going on are saved in `state/sessionstats.yaml`, and carry on after a restart.
+//! Those whose players left while BattleFox was down are recorded as having ended at the last
+//! save. Players we know nothing about count as having joined when BattleFox started.
+
+use std::{collections::HashMap, sync::{atomic::{AtomicBool, Ordering}, Arc}, time::{Duration, Instant}};
+
+use async_trait::async_trait;
+use battlefield_rcon::{bf4::{player_info_block::PlayerInfo, Bf4Client, Event, Player, Team, Visibility}, rcon::RconResult};
+use battlefox_database::{bfox::sessions::{LifetimeStats, NewSession}, BfoxContext};
+use chrono::{DateTime, Utc};
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+
+use crate::commands::{ArgKind, Command, Commands};
+use crate::state::{self, Persistent};
+use crate::{ConfigError, Plugin};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    enabled: bool,
+    /// Shorter sessions aren't recorded, they're mostly people who join and leave right away.
+    #[serde(with = "humantime_serde")]
+    min_duration: Duration,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct Numbers {
+    kills: u32,
+    deaths: u32,
+    score: i32,
+}
+
+impl Numbers {
+    fn of(pi: &PlayerInfo) -> Self {
+        Self {
+            kills: pi.kills.max(0) as u32,
+            deaths: pi.deaths.max(0) as u32,
+            score: pi.score,
+        }
+    }
+
+    fn add(&mut self, other: Numbers) {
+        self.kills += other.kills;
+        self.deaths += other.deaths;
+        self.score += other.score;
+    }
+}
+
+#[derive(Debug)]
+struct Session {
+    joined: Instant,
+    team: Team,
+    /// Of the rounds which are over.
+    totals: Numbers,
+    headshots: u32,
+    round_headshots: u32,
+    weapons: HashMap<String, u32>,
+}
+
+impl Session {
+    fn new(now: Instant) -> Self {
+        Self {
+            joined: now,
+            team: Team::Neutral,
+            totals: Numbers::default(),
+            headshots: 0,
+            round_headshots: 0,
+            weapons: HashMap::new(),
+        }
+    }
+
+    /// Adds a round's scoreboard.
+    fn round(&mut self, pi: &PlayerInfo) {
+        self.totals.add(Numbers::of(pi));
+        self.team = pi.team;
+        self.round_headshots = 0;
+    }
+}
+
+/// A `Session` on disk, where `Instant`s make no sense.
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedSession {
+    player: Player,
+    joined: DateTime<Utc>,
+    team: Team,
+    totals: Numbers,
+    headshots: u32,
+    round_headshots: u32,
+    weapons: HashMap<String, u32>,
+}
+
+impl SavedSession {
+    fn save(player: &Player, session: &Session) -> Self {
+        let ago = chrono::Duration::from_std(session.joined.elapsed()).unwrap_or_else(|_| chrono::Duration::zero());
+        Self {
+            player: player.clone(),
+            joined: Utc::now() - ago,
+            team: session.team,
+            totals: session.totals,
+            headshots: session.headshots,
+            round_headshots: session.round_headshots,
+            weapons: session.weapons.clone(),
+        }
+    }
+
+    /// If it's older than the host's uptime (e.g. after a reboot), an `Instant` can't go back that
+    /// far, so the session counts as joined now. Losing the playtime beats losing the whole session.
+    fn restore(self) -> (Player, Session) {
+        let ago = (Utc::now() - self.joined).to_std().unwrap_or_default();
+        (self.player, Session {
+            joined: Instant::now().checked_sub(ago).unwrap_or_else(Instant::now),
+            team: self.team,
+            totals: self.totals,
+            headshots: self.headshots,
+            round_headshots: self.round_headshots,
+            weapons: self.weapons,
+        })
+    }
+}
+
+/// What we remember across restarts, in `state/sessionstats.yaml`.
+#[derive(Debug, Serialize, Deserialize)]
+struct SessionStatsState {
+    saved: DateTime<Utc>,
+    between_rounds: bool,
+    sessions: Vec<SavedSession>,
+}
+
+impl Persistent for SessionStatsState {
+    const VERSION: u32 = 1;
+}
+
+fn kd(kills: u64, deaths: u64) -> f64 {
+    kills as f64 / deaths.max(1) as f64
+}
+
+/// The reply to `!stats`. `round` is the live scoreboard, `lifetime` everything including it.
+fn stats_lines(name: &str, round: Option<(Numbers, u32)>, lifetime: &LifetimeStats) -> Vec<String> {
+    let mut lines = Vec::new();
+    if let Some((round, headshots)) = round {
+        lines.push(format!("{} this round: {} kills, {} deaths, {} points, {} headshots",
+            name, round.kills, round.deaths, round.score, headshots));
+    }
+    // nobody cares about the seconds.
+    let playtime = humantime::format_duration(Duration::from_secs(lifetime.playtime.as_secs() / 60 * 60));
+    lines.push(format!("{} overall: {} kills, {} deaths (K/D {:.2}), {} points, {} headshots, {} sessions, {} played",
+        name, lifetime.kills, lifetime.deaths, kd(lifetime.kills, lifetime.deaths), lifetime.score,
+        lifetime.headshots, lifetime.sessions, playtime));
+    if let Some((weapon, kills)) = &lifetime.favourite_weapon {
+        lines.push(format!("{}'s favourite weapon: {} ({} kills)", name, weapon, kills));
+    }
+    lines
+}
+
+pub struct SessionStats {
+    config: RwLock<Arc<Config>>,
+    db: BfoxContext,
+    commands: Arc<Commands>,
+    sessions: Mutex<HashMap<Player, Session>>,
+    /// After the round's scoreboard was counted, and until the next round starts.
+    between_rounds: AtomicBool,
+    /// When the restored sessions were saved, until `start` sorted out who's still here.
+    restored_from: Mutex<Option<DateTime<Utc>>>,
+}
+
+impl SessionStats {
+    pub fn new(db: BfoxContext, commands: Arc<Commands>, config: Config) -> Self {
+        let (sessions, between_rounds, restored_from) = match state::load::<SessionStatsState>(Self::NAME) {
+            Some(state) => (
+                state.sessions.into_iter().map(SavedSession::restore).collect(),
+                state.between_rounds,
+                Some(state.saved),
+            ),
+            None => (HashMap::new(), false, None),
+        };
+        Self {
+            config: RwLock::new(Arc::new(config)),
+            db,
+            commands,
+            sessions: Mutex::new(sessions),
+            between_rounds: AtomicBool::new(between_rounds),
+            restored_from: Mutex::new(restored_from),
+        }
+    }
+
+    fn config(&self) -> Arc<Config> {
+        self.config.read().clone()
+    }
+
+    async fn left(&self, player: Player, final_scores: PlayerInfo) {
+        let session = {
+            let mut sessions = self.sessions.lock();
+            let mut session = match sessions.remove(&player) {
+                Some(session) => session,
+                None => return,
+            };
+            // between rounds, the scoreboard still shows the round we already counted.
+            if !self.between_rounds.load(Ordering::Relaxed) {
+                session.round(&final_scores);
+            }
+            session
+        };
+        self.record(player, session, Duration::ZERO).await;
+    }
+
+    /// Writes a session which ended `ended_ago`.
+    async fn record(&self, player: Player, session: Session, ended_ago: Duration) {
+        let duration = session.joined.elapsed().saturating_sub(ended_ago);
+        if duration < self.config().min_duration {
+            return;
+        }
+        let new = NewSession {
+            eaid: player.eaid.to_string(),
+            name: player.name.to_string(),
+            duration,
+            ended_ago,
+            team: match session.team {
+                Team::One => 1,
+                Team::Two => 2,
+                Team::Neutral => 0,
+            },
+            kills: session.totals.kills,
+            deaths: session.totals.deaths,
+            score: session.totals.score,
+            headshots: session.headshots,
+            weapons: session.weapons.into_iter().collect(),
+        };
+        if let Err(err) = self.db.insert_session(&new).await {
+            error!("[{}] Failed to record the session of {}: {:?}", Self::NAME, player, err);
+        }
+    }
+
+    async fn handle_stats(&self, bf4: Arc<Bf4Client>, asker: Player, target: Player) {
+        let mut lifetime = match self.db.lifetime_stats(target.eaid.to_string()).await {
+            Ok(lifetime) => lifetime,
+            Err(err) => {
+                error!("[{}] Failed to look up the stats of {}: {:?}", Self::NAME, target, err);
+                let _ = bf4.say(format!("Sorry, couldn't look up the stats of {}.", target.name), asker).await;
+                return;
+            },
+        };
+
+        let live = match bf4.list_players(Visibility::All).await {
+            Ok(list) => list.iter().find(|pi| pi.eaid == target.eaid).map(Numbers::of),
+            Err(_) => None,
+        };
+        let round = {
+            let sessions = self.sessions.lock();
+            sessions.get(&target).map(|session| {
+                let mut current = session.totals;
+                // between rounds, the scoreboard is the round we already counted.
+                let round = live.filter(|_| !self.between_rounds.load(Ordering::Relaxed));
+                if let Some(round) = round {
+                    current.add(round);
+                }
+                lifetime.sessions += 1;
+                lifetime.playtime += session.joined.elapsed();
+                lifetime.kills += current.kills as u64;
+                lifetime.deaths += current.deaths as u64;
+                lifetime.score += current.score as i64;
+                lifetime.headshots += session.headshots as u64;
+                round.map(|round| (round, session.round_headshots))
+            })
+        }.flatten();
+
+        let _ = bf4.say_lines(stats_lines(target.name.as_str(), round, &lifetime), asker).await;
+    }
+}
+
+#[async_trait]
+impl Plugin for SessionStats {
+    const NAME: &'static str = "sessionstats";
+    type Config = Config;
+
+    fn enabled(&self) -> bool {
+        self.config().enabled
+    }
+
+    async fn reconfigure(self: &Arc<Self>, _bf4: &Arc<Bf4Client>, config: Config) -> Result<(), ConfigError> {
+        *self.config.write() = Arc::new(config);
+        Ok(())
+    }
+
+    async fn start(self: &Arc<Self>, bf4: &Arc<Bf4Client>) {
+        if let Ok(list) = bf4.list_players(Visibility::All).await {
+            let now = Instant::now();
+            let gone = {
+                let mut sessions = self.sessions.lock();
+                let here: Vec<Player> = list.into_iter().map(|pi| Player { name: pi.player_name, eaid: pi.eaid }).collect();
+                let gone: Vec<Player> = sessions.keys().filter(|player| !here.contains(player)).cloned().collect();
+                let gone: Vec<(Player, Session)> = gone.into_iter()
+                    .filter_map(|player| sessions.remove(&player).map(|session| (player, session)))
+                    .collect();
+                for player in here {
+                    sessions.entry(player).or_insert_with(|| Session::new(now));
+                }
+                gone
+            };
+            // they left while we were down, so their session ended at the latest when we saved it.
+            let restored_from = self.restored_from.lock().take();
+            if let Some(saved) = restored_from {
+                let ended_ago = (Utc::now() - saved).to_std().unwrap_or_default();
+                for (player, session) in gone {
+                    self.record(player, session, ended_ago).await;
+                }
+            }
+        }
+
+        let myself = self.clone();
+        self.commands.register(
+            Command::new(Self::NAME, "stats", "Shows your stats on this server, or someone else's.")
+                .opt_arg("player", ArgKind::Player),
+            move |bf4, inv| {
+                let myself = myself.clone();
+                async move {
+                    let asker = (*inv.player).clone();
+                    let target = inv.args.player("player").cloned().unwrap_or_else(|| asker.clone());
+                    myself.handle_stats(bf4, asker, target).await;
+                }
+            });
+    }
+
+    async fn persist(self: &Arc<Self>, _bf4: &Arc<Bf4Client>) {
+        let sessions = self.sessions.lock().iter()
+            .map(|(player, session)| SavedSession::save(player, session))
+            .collect();
+        state::save(Self::NAME, &SessionStatsState {
+            saved: Utc::now(),
+            between_rounds: self.between_rounds.load(Ordering::Relaxed),
+            sessions,
+        });
+    }
+
+    async fn event(self: Arc<Self>, _bf4: Arc<Bf4Client>, event: Event) -> RconResult<()> {
+        match event {
+            Event::Authenticated { player } => {
+                self.sessions.lock().insert(player, Session::new(Instant::now()));
+            },
+            Event::Kill { killer: Some(killer), victim, weapon, headshot } if killer != victim => {
+                let mut sessions = self.sessions.lock();
+                let session = sessions.entry(killer).or_insert_with(|| Session::new(Instant::now()));
+                if headshot {
+                    session.headshots += 1;
+                    session.round_headshots += 1;
+                }
+                *session.weapons.entry(weapon.to_string()).or_default() += 1;
+            },
+            Event::RoundOverPlayers { players } => {
+                let now = Instant::now();
+                let mut sessions = self.sessions.lock();
+                for pi in &players {
+                    let player = Player { name: pi.player_name.clone(), eaid: pi.eaid };
+                    sessions.entry(player).or_insert_with(|| Session::new(now)).round(pi);
+                }
+                self.between_rounds.store(true, Ordering::Relaxed);
+            },
+            Event::LevelLoaded { .. } => {
+                self.between_rounds.store(false, Ordering::Relaxed);
+                for session in self.sessions.lock().values_mut() {
+                    session.round_headshots = 0;
+                }
+            },
+            Event::Leave { player, final_scores } => {
+                self.left(player, final_scores).await;
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for SessionStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionStats")
+            .field("config", &self.config())
+            .field("sessions", &*self.sessions.lock())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lines() {
+        let _: Config = serde_yaml::from_str(include_str!("../configs/sessionstats.yaml")).unwrap();
+        let lifetime = LifetimeStats {
+            sessions: 3,
+            playtime: Duration::from_secs(2 * 3600 + 5 * 60 + 7),
+            kills: 90,
+            deaths: 40,
+            score: 25000,
+            headshots: 12,
+            favourite_weapon: Some(("M416".to_string(), 50)),
+        };
+        let round = Numbers { kills: 10, deaths: 2, score: 3000 };
+        assert_eq!(stats_lines("Alice", Some((round, 4)), &lifetime), vec![
+            "Alice this round: 10 kills, 2 deaths, 3000 points, 4 headshots",
+            "Alice overall: 90 kills, 40 deaths (K/D 2.25), 25000 points, 12 headshots, 3 sessions, 2h 5m played",
+            "Alice's favourite weapon: M416 (50 kills)",
+        ]);
+        assert_eq!(stats_lines("Bob", None, &LifetimeStats::default()).len(), 1);
+    }
+
+    #[test]
+    fn saved_sessions() {
+        let player = crate::testutil::player("Alice", 1);
+        let mut session = Session::new(Instant::now() - Duration::from_secs(600));
+        session.totals = Numbers { kills: 10, deaths: 2, score: 3000 };
+        session.weapons.insert("M416".to_string(), 7);
+
+        let yaml = serde_yaml::to_string(&SavedSession::save(&player, &session)).unwrap();
+        let (restored_player, restored) = serde_yaml::from_str::<SavedSession>(&yaml).unwrap().restore();
+        assert_eq!(restored_player, player);
+        assert_eq!(restored.totals, session.totals);
+        assert_eq!(restored.weapons, session.weapons);
+        assert!(restored.joined.elapsed() >= Duration::from_secs(599));
+
+        // from before the host was rebooted.
+        let mut ancient = SavedSession::save(&player, &session);
+        ancient.joined = Utc::now() - chrono::Duration::days(365 * 100);
+        let (_, restored) = ancient.restore();
+        assert_eq!(restored.totals, session.totals);
+    }
+}
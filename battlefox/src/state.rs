@@ -0,0 +1,145 @@
+//! What plugins remember across restarts of BattleFox.
+//!
+//! A plugin puts what it wants to keep into a struct implementing `Persistent`, saves it with
+//! `state::save` in `Plugin::persist` (called every few minutes and on shutdown), and loads it
+//! back with `state::load` when it's created.
+//!
+//! Every file in `state/` carries the `VERSION` it was written with. When a plugin changes the
+//! layout of its state, it bumps `VERSION`, and older files are handed to `Persistent::migrate`
+//! instead of failing to deserialize. By default, old state is simply dropped.
+
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_yaml::Value;
+
+use crate::{load_config, save_state, state_path};
+
+pub trait Persistent: Serialize + DeserializeOwned {
+    /// Bump this whenever the layout changes in a way old files can't be read with anymore.
+    const VERSION: u32;
+
+    /// Turns state saved with an older `version` into the current layout.
+    ///
+    /// Files from before versioning existed are version `0`, and `old` is the whole file then.
+    /// Returning `None` starts from scratch.
+    fn migrate(_version: u32, _old: Value) -> Option<Self> {
+        None
+    }
+}
+
+#[derive(Serialize)]
+struct Stored<'a, T> {
+    version: u32,
+    saved: DateTime<Utc>,
+    state: &'a T,
+}
+
+#[derive(Deserialize)]
+struct Loaded {
+    version: u32,
+    state: Value,
+}
+
+/// Writes `state/<name>.yaml`. Failures are logged, there is nothing else to do about them.
+pub fn save<T: Persistent>(name: &str, state: &T) {
+    let stored = Stored {
+        version: T::VERSION,
+        saved: Utc::now(),
+        state,
+    };
+    if let Err(err) = save_state(name, &stored) {
+        error!("Failed to save {}: {:?}", state_path(name), err);
+    }
+}
+
+/// Reads `state/<name>.yaml`, migrating it if needed.
+///
+/// `None` if there is no such file, or if it can't be used. A file that can't be used is moved
+/// aside to `<name>.yaml.bak` first, so the next `save` doesn't overwrite what may still be rescued.
+pub fn load<T: Persistent>(name: &str) -> Option<T> {
+    let path = state_path(name);
+    if !std::path::Path::new(&path).exists() {
+        return None;
+    }
+    let file: Value = match load_config(&path) {
+        Ok(file) => file,
+        Err(err) => {
+            warn!("Can't read {}, starting from scratch: {:?}", path, err);
+            set_aside(&path);
+            return None;
+        }
+    };
+    match decode(file) {
+        Ok(state) => state,
+        Err(err) => {
+            warn!("Can't use {}, starting from scratch: {}", path, err);
+            set_aside(&path);
+            None
+        }
+    }
+}
+
+fn set_aside(path: &str) {
+    let backup = format!("{}.bak", path);
+    match std::fs::rename(path, &backup) {
+        Ok(()) => warn!("Moved {} to {}, have a look at it before it gets lost.", path, backup),
+        Err(err) => error!("Failed to move {} to {}: {:?}", path, backup, err),
+    }
+}
+
+fn decode<T: Persistent>(file: Value) -> Result<Option<T>, String> {
+    let loaded = match file.get("version") {
+        Some(_) => serde_yaml::from_value::<Loaded>(file).map_err(|err| err.to_string())?,
+        None => Loaded { version: 0, state: file },
+    };
+
+    if loaded.version == T::VERSION {
+        serde_yaml::from_value(loaded.state).map(Some).map_err(|err| err.to_string())
+    } else if loaded.version < T::VERSION {
+        info!("Migrating state from version {} to {}.", loaded.version, T::VERSION);
+        Ok(T::migrate(loaded.version, loaded.state))
+    } else {
+        Err(format!("it was written by a newer BattleFox (version {}, we know up to {})", loaded.version, T::VERSION))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Counter {
+        count: u32,
+    }
+
+    impl Persistent for Counter {
+        const VERSION: u32 = 2;
+
+        fn migrate(version: u32, old: Value) -> Option<Self> {
+            match version {
+                // version 1 called it `n`.
+                1 => Some(Self { count: old.get("n")?.as_u64()? as u32 }),
+                _ => None,
+            }
+        }
+    }
+
+    fn file(yaml: &str) -> Value {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn versions() {
+        let current: Option<Counter> = decode(file("version: 2\nstate:\n  count: 5\n")).unwrap();
+        assert_eq!(current, Some(Counter { count: 5 }));
+
+        let migrated: Option<Counter> = decode(file("version: 1\nstate:\n  n: 3\n")).unwrap();
+        assert_eq!(migrated, Some(Counter { count: 3 }));
+
+        let dropped: Option<Counter> = decode(file("count: 7\n")).unwrap();
+        assert_eq!(dropped, None);
+
+        assert!(decode::<Counter>(file("version: 3\nstate:\n  count: 5\n")).is_err());
+        assert!(decode::<Counter>(file("version: 2\nstate:\n  n: 5\n")).is_err());
+    }
+}
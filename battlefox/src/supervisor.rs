@@ -0,0 +1,379 @@
+//! Runs the plugins, and decides what happens when one of them fails.
+//!
+//! A plugin fails when its `run` returns `Err` or panics. What happens then is up to the plugin's
+//! `RestartPolicy`. The current state of every plugin can be seen with `/bfox plugins`.
+//!
+//! Catching panics needs `panic = 'unwind'`, which is why the release profile in the workspace's
+//! `Cargo.toml` must not be set to `abort`. With `abort`, any panic takes all of BattleFox down.
+//!
+//! Admins can also pause and resume plugins at runtime with `/bfox plugin disable|enable <name>`.
+//! Which plugins are paused is remembered in `state/paused_plugins.yaml`.
+
+use std::{any::Any, collections::{BTreeMap, BTreeSet}, fmt::Display, sync::Arc, time::{Duration, Instant}};
+
+use battlefield_rcon::{bf4::Bf4Client, rcon::RconError};
+use futures::{StreamExt, stream::FuturesUnordered};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::watch;
+
+use crate::{load_config, save_state, state_path, Plugin2};
+
+const PAUSED_STATE: &str = "paused_plugins";
+
+/// What to do when a plugin's `run` returns `Err` or panics.
+///
+/// A plugin which returns `Ok(())` is considered done and is never restarted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartPolicy {
+    /// Restart the plugin after waiting `initial_backoff`, doubling the wait on every further
+    /// failure up to `max_backoff`. If the plugin ran fine for longer than `max_backoff`, the
+    /// wait is reset to `initial_backoff`.
+    Restart {
+        initial_backoff: Duration,
+        max_backoff: Duration,
+    },
+    /// Stop all of BattleFox. For plugins which everything else depends on.
+    StopAll,
+    /// Log it and leave the plugin dead.
+    Ignore,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self::Restart {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PluginState {
+    /// Not yet started.
+    Starting,
+    Running,
+    /// Failed, waiting until `until` before running it again.
+    Restarting { until: Instant },
+    /// `run` returned `Ok(())` while the plugin is enabled.
+    Stopped,
+    /// `run` returned `Ok(())` and the plugin is disabled in its config.
+    Disabled,
+    /// Paused by an admin, waiting to be resumed.
+    Paused,
+    /// Failed and won't be restarted.
+    Dead,
+}
+
+impl Display for PluginState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PluginState::Starting => write!(f, "starting"),
+            PluginState::Running => write!(f, "running"),
+            PluginState::Restarting { until } => {
+                let left = until.saturating_duration_since(Instant::now());
+                write!(f, "restarting in {}s", left.as_secs())
+            },
+            PluginState::Stopped => write!(f, "stopped"),
+            PluginState::Disabled => write!(f, "disabled"),
+            PluginState::Paused => write!(f, "paused"),
+            PluginState::Dead => write!(f, "dead"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PluginStatus {
+    pub state: PluginState,
+    /// How often the plugin has been restarted so far.
+    pub restarts: usize,
+    /// Why the plugin failed the last time, if it ever did.
+    pub last_error: Option<String>,
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum PauseError {
+    #[error("No plugin named {0}")]
+    UnknownPlugin(String),
+    #[error("Plugin {0} can't be paused")]
+    NotPausable(String),
+}
+
+/// Contents of `state/paused_plugins.yaml`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PausedFile {
+    paused: BTreeSet<String>,
+}
+
+/// Whether the other plugins can keep going after one supervised plugin is done.
+enum Outcome {
+    Continue,
+    StopAll,
+}
+
+#[derive(Debug)]
+struct Entry {
+    status: PluginStatus,
+    pausable: bool,
+    paused: watch::Sender<bool>,
+}
+
+#[derive(Debug)]
+pub struct Supervisor {
+    plugins: Mutex<BTreeMap<&'static str, Entry>>,
+    /// Paused plugins as remembered from last time, applied once the plugins get registered.
+    paused_on_startup: BTreeSet<String>,
+}
+
+impl Supervisor {
+    pub(crate) fn new() -> Self {
+        let paused_on_startup = match load_config::<PausedFile>(state_path(PAUSED_STATE)) {
+            Ok(file) => file.paused,
+            Err(_) => BTreeSet::new(), // most likely there's no file yet, which is fine.
+        };
+        if !paused_on_startup.is_empty() {
+            info!("These plugins were paused last time and stay paused: {:?}", paused_on_startup);
+        }
+
+        Self {
+            plugins: Mutex::new(BTreeMap::new()),
+            paused_on_startup,
+        }
+    }
+
+    /// Current status of every plugin, sorted by name.
+    pub fn statuses(&self) -> Vec<(&'static str, PluginStatus)> {
+        self.plugins.lock().iter()
+            .map(|(&name, entry)| (name, entry.status.clone()))
+            .collect()
+    }
+
+    /// Pauses or resumes a plugin.
+    ///
+    /// A paused plugin doesn't receive any events, but keeps its state, and is told about it via
+    /// `Plugin::pause` and `Plugin::resume`. This is remembered across restarts of BattleFox.
+    pub fn set_paused(&self, name: &str, paused: bool) -> Result<(), PauseError> {
+        let mut plugins = self.plugins.lock();
+        let entry = plugins.get_mut(name).ok_or_else(|| PauseError::UnknownPlugin(name.to_string()))?;
+        if !entry.pausable {
+            return Err(PauseError::NotPausable(name.to_string()));
+        }
+        entry.paused.send_replace(paused);
+        info!("Plugin {} is now {}.", name, if paused { "paused" } else { "resumed" });
+
+        let file = PausedFile {
+            paused: plugins.iter()
+                .filter(|(_, entry)| *entry.paused.borrow())
+                .map(|(&name, _)| name.to_string())
+                .collect(),
+        };
+        drop(plugins);
+        if let Err(err) = save_state(PAUSED_STATE, &file) {
+            error!("Failed to save {}: {:?}", state_path(PAUSED_STATE), err);
+        }
+        Ok(())
+    }
+
+    pub fn is_paused(&self, name: &str) -> bool {
+        self.plugins.lock().get(name).map(|entry| *entry.paused.borrow()).unwrap_or(false)
+    }
+
+    /// The status table, one line per plugin, suitable for chat.
+    pub fn status_lines(&self) -> Vec<String> {
+        self.statuses().into_iter()
+            .map(|(name, status)| {
+                let mut line = format!("{}: {}", name, status.state);
+                if status.restarts > 0 {
+                    line += &format!(", {} restarts", status.restarts);
+                }
+                if let Some(err) = status.last_error {
+                    line += &format!(", last error: {}", err);
+                }
+                line
+            })
+            .collect()
+    }
+
+    fn set_state(&self, name: &'static str, state: PluginState) {
+        if let Some(entry) = self.plugins.lock().get_mut(name) {
+            entry.status.state = state;
+        }
+    }
+
+    /// Subscribes to the paused flag of a plugin.
+    fn paused_rx(&self, name: &'static str) -> watch::Receiver<bool> {
+        self.plugins.lock()[name].paused.subscribe()
+    }
+
+    /// Runs all plugins and waits until they are all stopped or dead, or until one of them with
+    /// `RestartPolicy::StopAll` failed.
+    pub(crate) async fn run(self: Arc<Self>, plugins: Vec<Arc<dyn Plugin2>>, bf4: Arc<Bf4Client>) {
+        {
+            let mut lock = self.plugins.lock();
+            for plugin in plugins.iter() {
+                let pausable = plugin.pausable();
+                let paused = pausable && self.paused_on_startup.contains(plugin.name());
+                lock.insert(plugin.name(), Entry {
+                    status: PluginStatus {
+                        state: PluginState::Starting,
+                        restarts: 0,
+                        last_error: None,
+                    },
+                    pausable,
+                    paused: watch::channel(paused).0,
+                });
+            }
+        }
+
+        let mut remaining = plugins.iter().map(|plugin| plugin.name()).collect::<BTreeSet<_>>();
+        let mut jhs = plugins.into_iter()
+            .map(|plugin| {
+                let name = plugin.name();
+                let self_clone = self.clone();
+                let jh = tokio::spawn(self_clone.supervise(plugin, bf4.clone()));
+                async move { (name, jh.await) }
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        while let Some((name, result)) = jhs.next().await {
+            remaining.remove(name);
+            match result {
+                Ok(Outcome::Continue) => (),
+                Ok(Outcome::StopAll) => {
+                    error!("Plugin {} has failed, stopping everything.", name);
+                    break;
+                },
+                Err(err) => {
+                    // `run` has its own task, so this was `start`, `pause` or `resume`.
+                    let reason = format!("panicked outside of run: {}", join_error_message(err));
+                    error!("Plugin {} {}", name, reason);
+                    self.fail(name, reason, PluginState::Dead);
+                },
+            }
+
+            // paused plugins would wait forever, e.g. after the RCON connection got closed.
+            let plugins = self.plugins.lock();
+            if remaining.iter().all(|name| plugins[name].status.state == PluginState::Paused) {
+                if !remaining.is_empty() {
+                    info!("Only paused plugins are left ({:?}), stopping.", remaining);
+                }
+                break;
+            }
+        }
+    }
+
+    async fn supervise(self: Arc<Self>, plugin: Arc<dyn Plugin2>, bf4: Arc<Bf4Client>) -> Outcome {
+        let name = plugin.name();
+        let policy = plugin.restart_policy();
+        let mut backoff: Option<Duration> = None;
+        let mut paused_rx = self.paused_rx(name);
+
+        // `start` only once, restarts just call `run` again.
+        plugin.clone().start(bf4.clone()).await;
+
+        loop {
+            if *paused_rx.borrow_and_update() {
+                self.set_state(name, PluginState::Paused);
+                plugin.clone().pause(bf4.clone()).await;
+                while *paused_rx.borrow_and_update() {
+                    if paused_rx.changed().await.is_err() {
+                        return Outcome::Continue;
+                    }
+                }
+                plugin.clone().resume(bf4.clone()).await;
+            }
+
+            self.set_state(name, PluginState::Running);
+            let started = Instant::now();
+            let mut jh = tokio::spawn(plugin.clone().run(bf4.clone()));
+            let result = tokio::select! {
+                result = &mut jh => result,
+                _ = wait_for(&mut paused_rx, true) => {
+                    jh.abort();
+                    continue;
+                },
+            };
+            let reason = match result {
+                Ok(Ok(())) => {
+                    self.set_state(name, if plugin.enabled() { PluginState::Stopped } else { PluginState::Disabled });
+                    return Outcome::Continue;
+                },
+                Ok(Err(RconError::ConnectionClosed)) => {
+                    // no point in restarting without a connection.
+                    error!("Plugin {} has quit, the RCON connection was closed.", name);
+                    self.fail(name, "connection closed".to_string(), PluginState::Dead);
+                    return Outcome::Continue;
+                },
+                Ok(Err(err)) => format!("{:?}", err),
+                Err(err) => format!("panicked: {}", join_error_message(err)),
+            };
+            error!("Plugin {} has quit with error: {}", name, reason);
+
+            match policy {
+                RestartPolicy::Restart { initial_backoff, max_backoff } => {
+                    let delay = match backoff {
+                        Some(prev) if started.elapsed() < max_backoff => (prev * 2).min(max_backoff),
+                        _ => initial_backoff,
+                    };
+                    backoff = Some(delay);
+                    info!("Restarting plugin {} in {}s.", name, delay.as_secs());
+                    self.fail(name, reason, PluginState::Restarting { until: Instant::now() + delay });
+                    if let Some(entry) = self.plugins.lock().get_mut(name) {
+                        entry.status.restarts += 1;
+                    }
+                    // pausing cuts the wait short, and then we wait for the resume instead.
+                    tokio::select! {
+                        _ = tokio::time::sleep(delay) => (),
+                        _ = wait_for(&mut paused_rx, true) => (),
+                    }
+                },
+                RestartPolicy::StopAll => {
+                    self.fail(name, reason, PluginState::Dead);
+                    return Outcome::StopAll;
+                },
+                RestartPolicy::Ignore => {
+                    self.fail(name, reason, PluginState::Dead);
+                    return Outcome::Continue;
+                },
+            }
+        }
+    }
+
+    fn fail(&self, name: &'static str, reason: String, state: PluginState) {
+        #[cfg(feature = "metrics")]
+        crate::metrics::PLUGIN_ERRORS.with_label_values(&[name]).inc();
+        if let Some(entry) = self.plugins.lock().get_mut(name) {
+            entry.status.state = state;
+            entry.status.last_error = Some(reason);
+        }
+    }
+}
+
+/// Waits until the watched value is `value`. Never returns if the sender is gone.
+async fn wait_for(rx: &mut watch::Receiver<bool>, value: bool) {
+    while *rx.borrow_and_update() != value {
+        if rx.changed().await.is_err() {
+            futures::future::pending::<()>().await;
+        }
+    }
+}
+
+fn join_error_message(err: tokio::task::JoinError) -> String {
+    if err.is_panic() {
+        panic_message(&*err.into_panic())
+    } else {
+        "cancelled".to_string()
+    }
+}
+
+/// Gets the message out of a panic payload, as far as possible.
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "<no message>".to_string()
+    }
+}
@@ -4,13 +4,23 @@ use std::sync::{Arc, Mutex};
 use std::time::{Instant, Duration};
 
 use async_trait::async_trait;
-use battlefield_rcon::bf4::{Bf4Client, Event, Player, Weapon, Visibility};
+use chrono::{DateTime, Utc};
+use battlefield_rcon::bf4::{Bf4Client, Envelope, Event, Player, Weapon, Visibility};
 use battlefield_rcon::rcon::RconResult;
+use battlefox_database::BfoxContext;
 use lerp::Lerp;
+use parking_lot::RwLock;
 use serde::{Deserialize, Serialize};
 
-use crate::Plugin;
+use crate::commands::{ArgKind, Command, Commands};
+use crate::feed::Feed;
+use crate::guard::Guard;
+use crate::roles::Role;
+use crate::state::{self, Persistent};
+use crate::{ConfigError, Plugin};
 use crate::players::Players;
+use crate::playermute::PlayerMute;
+use crate::punishment::{self, Punishment};
 
 // Serde doesn't allow literals as default values, apparently? Yikes.
 fn const_true() -> bool { true }
@@ -19,6 +29,27 @@ fn weapon_badness_default() -> BTreeMap<String, f32> {
     BTreeMap::new()
 }
 
+fn auto_forgive_default() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn factor_default() -> f32 { 1.0 }
+
+fn infraction_decay_default() -> Duration {
+    Duration::from_secs(60 * 60 * 24 * 30)
+}
+
+/// What happens when a victim types `!p`.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum Punish {
+    /// Kill the teamkiller.
+    #[default]
+    Kill,
+    /// Make the teamkill count this many times towards the badness.
+    Badness(f32),
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
 pub struct Config {
     #[serde(default = "const_true")]
@@ -37,9 +68,37 @@ pub struct Config {
 
     /// Amount of minutes after which history entries are to be trimmed.
     trim_history_minutes: u64,
+
+    /// Ask victims to `!p`unish or `!f`orgive. Forgiven teamkills are removed from the history.
+    #[serde(default)]
+    victim_choice: bool,
+
+    /// Teamkills the victim didn't decide about within this long are forgiven.
+    #[serde(default = "auto_forgive_default", with = "humantime_serde")]
+    auto_forgive_after: Duration,
+
+    #[serde(default)]
+    punish: Punish,
+
+    /// What happens when the badness reaches `badness_threshold_kick`. Every time, the player
+    /// moves one step up, and stays at the last one. Just kicks if empty.
+    #[serde(default)]
+    punishments: Vec<Punishment>,
+
+    /// Punishments are remembered in the database for this long, across sessions.
+    #[serde(default = "infraction_decay_default", with = "humantime_serde")]
+    infraction_decay: Duration,
 }
 
 impl Config {
+    /// Makes sure the config won't make us panic later on.
+    fn validate(&self) -> Result<(), ConfigError> {
+        if !self.badness_time_scale.contains_key(&0) {
+            return Err(ConfigError::Invalid("badness_time_scale needs an entry for zero seconds".to_string()));
+        }
+        Ok(())
+    }
+
     fn interpolate_time_scale(&self, duration: Duration) -> f32 {
         // Get iterator where we're only interested in (-inf, duration].
         // For example with `duration = 2`, we may find a key of `1` seconds.
@@ -71,6 +130,10 @@ struct HistEntry {
     timestamp: Instant,
     weapon: Weapon,
     victim: Player,
+    /// Whether the victim chose to punish.
+    punished: bool,
+    /// Multiplies the badness, raised by punishing.
+    factor: f32,
 }
 
 impl Display for HistEntry {
@@ -79,7 +142,11 @@ impl Display for HistEntry {
             self.timestamp.elapsed().as_secs(),
             self.victim.name,
             self.weapon,
-        )
+        )?;
+        if self.punished {
+            write!(f, " (punished)")?;
+        }
+        Ok(())
     }
 }
 
@@ -98,10 +165,20 @@ impl Display for PlayerHistory {
 }
 
 impl HistEntry {
+    /// The victim chose to punish.
+    fn punish(&mut self, punish: &Punish) {
+        self.punished = true;
+        if let Punish::Badness(factor) = *punish {
+            self.factor = factor;
+        }
+    }
+
     fn badness(&self, config: &Config) -> f32 {
         config.interpolate_time_scale(self.timestamp.elapsed())
         *
         config.weapon_badness.get(&self.weapon.to_string()).unwrap_or(&1.0)
+        *
+        self.factor
     }
 }
 
@@ -119,41 +196,360 @@ impl PlayerHistory {
     }
 }
 
+/// A `HistEntry` on disk, where `Instant`s make no sense.
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedHistEntry {
+    time: DateTime<Utc>,
+    weapon: Weapon,
+    victim: Player,
+    #[serde(default)]
+    punished: bool,
+    #[serde(default = "factor_default")]
+    factor: f32,
+}
+
+impl SavedHistEntry {
+    fn save(entry: &HistEntry) -> Self {
+        let ago = chrono::Duration::from_std(entry.timestamp.elapsed()).unwrap_or_else(|_| chrono::Duration::zero());
+        Self {
+            time: Utc::now() - ago,
+            weapon: entry.weapon.clone(),
+            victim: entry.victim.clone(),
+            punished: entry.punished,
+            factor: entry.factor,
+        }
+    }
+
+    /// `None` if it's so old that it can't be an `Instant` anymore, which means it's irrelevant anyway.
+    fn restore(self) -> Option<HistEntry> {
+        let ago = (Utc::now() - self.time).to_std().unwrap_or_default();
+        Some(HistEntry {
+            timestamp: Instant::now().checked_sub(ago)?,
+            weapon: self.weapon,
+            victim: self.victim,
+            punished: self.punished,
+            factor: self.factor,
+        })
+    }
+}
+
+/// What we remember across restarts, in `state/teamkilling.yaml`.
+#[derive(Debug, Serialize, Deserialize)]
+struct TeamKillingState {
+    histories: Vec<(Player, Vec<SavedHistEntry>)>,
+}
+
+impl Persistent for TeamKillingState {
+    const VERSION: u32 = 1;
+}
+
 #[derive(Debug, Clone, Copy)]
 enum DebugSatk {
     SuicidesAsTk,
     KillsAsTk,
 }
 
+/// A teamkill the victim hasn't decided about yet.
+#[derive(Debug, Clone)]
+struct Pending {
+    killer: Player,
+    /// Of the `HistEntry`, to find it again.
+    timestamp: Instant,
+}
+
+#[derive(Default)]
 struct Inner {
     histories: BTreeMap<Player, PlayerHistory>,
     debug_count_suicides_as_tk: BTreeMap<Player, DebugSatk>,
+    /// Victim -> their teamkills they haven't decided about yet, oldest first.
+    pending: BTreeMap<Player, Vec<Pending>>,
+}
+
+impl Inner {
+    /// Takes the killer's history out if it's bad enough to be punished.
+    fn take_if_bad(&mut self, killer: &Player, config: &Config) -> Option<PlayerHistory> {
+        if self.histories.get(killer)?.badness(config) < config.badness_threshold_kick {
+            return None;
+        }
+        self.histories.remove(killer)
+    }
+
+    /// Takes one of the victim's pending teamkills: the one from `timestamp`, or the most recent
+    /// one if `None`.
+    fn take_pending(&mut self, victim: &Player, timestamp: Option<Instant>) -> Option<Pending> {
+        let queue = self.pending.get_mut(victim)?;
+        let i = match timestamp {
+            Some(timestamp) => queue.iter().position(|pending| pending.timestamp == timestamp)?,
+            None => queue.len().checked_sub(1)?,
+        };
+        let pending = queue.remove(i);
+        if queue.is_empty() {
+            self.pending.remove(victim);
+        }
+        Some(pending)
+    }
+
+    /// Removes the teamkill from the killer's history.
+    fn forgive(&mut self, victim: &Player, pending: &Pending) {
+        if let Some(hist) = self.histories.get_mut(&pending.killer) {
+            hist.teamkills.retain(|entry| !(entry.timestamp == pending.timestamp && &entry.victim == victim));
+            if hist.teamkills.is_empty() {
+                self.histories.remove(&pending.killer);
+            }
+        }
+    }
+
+    /// Marks the teamkill as punished, and returns the killer's history.
+    fn punish(&mut self, victim: &Player, pending: &Pending, punish: &Punish) -> Option<PlayerHistory> {
+        let hist = self.histories.get_mut(&pending.killer)?;
+        if let Some(entry) = hist.teamkills.iter_mut().find(|entry| entry.timestamp == pending.timestamp && &entry.victim == victim) {
+            entry.punish(punish);
+        }
+        Some(hist.clone())
+    }
 }
 
 pub struct TeamKilling {
-    config: Config,
+    config: RwLock<Arc<Config>>,
     players: Arc<Players>,
+    feed: Arc<Feed>,
+    commands: Arc<Commands>,
+    playermute: Arc<PlayerMute>,
+    db: BfoxContext,
     inner: Mutex<Inner>,
 }
 
+/// Published to the feed as `teamkilling.kick`.
+#[derive(Debug, Serialize)]
+struct KickedForTeamkilling<'a> {
+    player: &'a Player,
+    badness: f32,
+    teamkills: usize,
+}
+
+/// Published to the feed as `teamkilling.punish`, when using `punishments`.
+#[derive(Debug, Serialize)]
+struct PunishedForTeamkilling<'a> {
+    player: &'a Player,
+    badness: f32,
+    teamkills: usize,
+    /// How many times they were punished within `infraction_decay`, including this one.
+    infraction: u32,
+    punishment: &'a Punishment,
+}
+
+/// `kind` of our records in `bfox_infractions`.
+const INFRACTION_KIND: &str = "teamkilling";
+
+/// Which infraction this is and the punishment for it, given `infraction` (counting this one),
+/// or `None` to just kick: without `punishments`, or when the infractions couldn't be counted.
+fn escalate(config: &Config, infraction: Option<u32>) -> Option<(u32, &Punishment)> {
+    let infraction = infraction?;
+    Some((infraction, punishment::step(&config.punishments, infraction as usize)?))
+}
+
 impl TeamKilling {
-    pub fn new(config: Config, players: Arc<Players>) -> Self {
+    pub fn new(config: Config, players: Arc<Players>, feed: Arc<Feed>, commands: Arc<Commands>, playermute: Arc<PlayerMute>, db: BfoxContext) -> Self {
+        let histories = state::load::<TeamKillingState>(Self::NAME)
+            .map(|state| state.histories.into_iter()
+                .map(|(player, entries)| (player, PlayerHistory {
+                    teamkills: entries.into_iter().filter_map(SavedHistEntry::restore).collect(),
+                }))
+                .collect())
+            .unwrap_or_default();
         Self {
-            config,
+            config: RwLock::new(Arc::new(config)),
             players,
+            feed,
+            commands,
+            playermute,
+            db,
             inner: Mutex::new(Inner {
-                histories: BTreeMap::new(),
-                debug_count_suicides_as_tk: BTreeMap::new(),
+                histories,
+                ..Default::default()
             })
         }
     }
 
-    async fn event(self: Arc<Self>, bf4: Arc<Bf4Client>, event: Event) -> RconResult<()> {
-        match event {
+    fn config(&self) -> Arc<Config> {
+        self.config.read().clone()
+    }
+
+    /// Punishes the teamkiller if their badness is over the threshold.
+    async fn punish_if_bad(&self, bf4: &Bf4Client, killer: &Player, config: &Config) {
+        // taken out before awaiting anything, so that only one of several teamkills in quick
+        // succession gets to punish.
+        let hist = match self.inner.lock().unwrap().take_if_bad(killer, config) {
+            Some(hist) => hist,
+            None => return,
+        };
+        let badness = hist.badness(config);
+        info!("Player {} achieved teamkilling badness {} with history:\n{}",
+            killer.name,
+            badness,
+            hist,
+        );
+        #[cfg(feature = "metrics")]
+        crate::metrics::TK_PUNISHMENTS.inc();
+
+        let infraction = if config.punishments.is_empty() {
+            None
+        } else {
+            match self.db.count_infractions(killer.eaid.to_string(), INFRACTION_KIND, config.infraction_decay).await {
+                Ok(previous) => Some(previous + 1),
+                Err(err) => {
+                    // better to kick than to start them over at a warning.
+                    error!("Failed to look up the teamkilling infractions of {}, kicking instead: {:?}", killer, err);
+                    None
+                },
+            }
+        };
+        let (infraction, punishment) = match escalate(config, infraction) {
+            Some(step) => step,
+            None => {
+                self.feed.publish("teamkilling.kick", KickedForTeamkilling {
+                    player: killer,
+                    badness,
+                    teamkills: hist.teamkills.len(),
+                });
+                // kicked players keep their history, like before there were punishments.
+                {
+                    let mut lock = self.inner.lock().unwrap();
+                    let teamkills = &mut lock.histories.entry(killer.clone()).or_default().teamkills;
+                    teamkills.extend(hist.teamkills.iter().cloned());
+                    teamkills.sort_by_key(|entry| entry.timestamp);
+                }
+                let _ = bf4.say(format!("Kicking {} for excessive teamkilling.", killer.name), Visibility::All).await;
+                let _ = bf4.kick(killer.name.clone(), "Teamkilling").await;
+                return;
+            },
+        };
+
+        self.feed.publish("teamkilling.punish", PunishedForTeamkilling {
+            player: killer,
+            badness,
+            teamkills: hist.teamkills.len(),
+            infraction,
+            punishment,
+        });
+        if let Err(err) = self.db.add_infraction(killer.eaid.to_string(), INFRACTION_KIND, &punishment.to_string()).await {
+            error!("Failed to record the teamkilling infraction of {}: {:?}", killer, err);
+        }
+        // the history stays gone: the next step needs as many teamkills again.
+        punishment::punish(bf4, &self.playermute, Self::NAME, killer, punishment, "Stop teamkilling").await;
+    }
+
+    fn take_pending(&self, victim: &Player, timestamp: Option<Instant>) -> Option<Pending> {
+        self.inner.lock().unwrap().take_pending(victim, timestamp)
+    }
+
+    fn forgive(&self, victim: &Player, pending: &Pending) {
+        self.inner.lock().unwrap().forgive(victim, pending);
+    }
+
+    async fn auto_forgive(&self, bf4: Arc<Bf4Client>, victim: Player, timestamp: Instant) {
+        if let Some(pending) = self.take_pending(&victim, Some(timestamp)) {
+            self.forgive(&victim, &pending);
+            let _ = bf4.say(format!("{} didn't punish you, the teamkill is forgiven.", victim.name), &pending.killer).await;
+        }
+    }
+
+    async fn handle_forgive(&self, bf4: Arc<Bf4Client>, victim: Guard<Player, Role>) {
+        let pending = match self.take_pending(&victim, None) {
+            Some(pending) => pending,
+            None => {
+                let _ = bf4.say("Nobody teamkilled you recently.", &*victim).await;
+                return;
+            },
+        };
+        self.forgive(&victim, &pending);
+        let _ = bf4.say(format!("You forgave {}.", pending.killer.name), &*victim).await;
+        let _ = bf4.say(format!("{} forgave your teamkill.", victim.name), &pending.killer).await;
+    }
+
+    async fn handle_punish(&self, bf4: Arc<Bf4Client>, victim: Guard<Player, Role>) {
+        let pending = match self.take_pending(&victim, None) {
+            Some(pending) => pending,
+            None => {
+                let _ = bf4.say("Nobody teamkilled you recently.", &*victim).await;
+                return;
+            },
+        };
+        let config = self.config();
+        let hist = self.inner.lock().unwrap().punish(&victim, &pending, &config.punish);
+        info!("{} punished {} for a teamkill ({:?}).", victim.name, pending.killer.name, config.punish);
+        let _ = bf4.say(format!("You punished {}.", pending.killer.name), &*victim).await;
+        match config.punish {
+            Punish::Kill => {
+                let _ = bf4.kill(pending.killer.name.clone()).await;
+                let _ = bf4.say(format!("{} punished you for teamkilling.", victim.name), &pending.killer).await;
+            },
+            Punish::Badness(_) => {
+                let _ = bf4.say(format!("{} punished you for teamkilling. Keep it up, and you'll be kicked.", victim.name), &pending.killer).await;
+            },
+        }
+        if hist.is_some() {
+            self.punish_if_bad(&bf4, &pending.killer, &config).await;
+        }
+    }
+
+    async fn handle_tkinfo(&self, bf4: Arc<Bf4Client>, admin: Guard<Player, Role>, target: Player) {
+        let config = self.config();
+        let hist = self.inner.lock().unwrap().histories.get(&target).cloned();
+        let lines = match hist {
+            Some(hist) => {
+                let mut lines = vec![format!("{}: badness {:.2} (kick at {:.2}), {} teamkills in the last {} minutes:",
+                    target.name, hist.badness(&config), config.badness_threshold_kick, hist.teamkills.len(), config.trim_history_minutes)];
+                lines.extend(hist.teamkills.iter().map(|entry| entry.to_string()));
+                lines
+            },
+            None => vec![format!("{} has no recent teamkills.", target.name)],
+        };
+        let _ = bf4.say_lines(lines, &*admin).await;
+    }
+
+    fn register_commands(self: &Arc<Self>) {
+        let myself = self.clone();
+        self.commands.register(
+            Command::new(Self::NAME, "p", "Punishes whoever teamkilled you last.")
+                .alias("punish"),
+            move |bf4, inv| {
+                let myself = myself.clone();
+                async move { myself.handle_punish(bf4, inv.player).await }
+            });
+
+        let myself = self.clone();
+        self.commands.register(
+            Command::new(Self::NAME, "f", "Forgives whoever teamkilled you last.")
+                .alias("forgive"),
+            move |bf4, inv| {
+                let myself = myself.clone();
+                async move { myself.handle_forgive(bf4, inv.player).await }
+            });
+
+        let myself = self.clone();
+        self.commands.register(
+            Command::new(Self::NAME, "tkinfo", "Shows a player's recent teamkills.")
+                .arg("player", ArgKind::Player)
+                .role(Role::Moderator),
+            move |bf4, inv| {
+                let myself = myself.clone();
+                async move {
+                    let target = inv.args.player("player").unwrap().clone(); // required arg.
+                    myself.handle_tkinfo(bf4, inv.player, target).await;
+                }
+            });
+    }
+
+    async fn event(self: Arc<Self>, bf4: Arc<Bf4Client>, envelope: Envelope) -> RconResult<()> {
+        let config = self.config();
+        match envelope.event {
             Event::Authenticated { player } => {
                 let _ = bf4.admin_add(&player.name, 1).await;
             },
             Event::Leave { player , .. } => {
+                // leaving forgives, nobody can answer anymore.
+                self.inner.lock().unwrap().pending.remove(&player);
                 let _ = bf4.admin_remove(&player.name).await;
             }
             Event::Kill {killer: Some(killer), victim, weapon, .. } => {
@@ -179,29 +575,38 @@ impl TeamKilling {
                             // If we do consider the kill to be a tk, then append it to the history,
                             // and return that history as Some(hist).
                             is_tk.then(|| {
-                                let hist = lock.histories.entry(killer).or_default();
+                                let hist = lock.histories.entry(killer.clone()).or_default();
                                 hist.teamkills.push(HistEntry {
-                                    timestamp: Instant::now(),
+                                    // when rcon told us, not when we got around to processing it.
+                                    timestamp: envelope.received,
                                     weapon: weapon.clone(),
-                                    victim,
+                                    victim: victim.clone(),
+                                    punished: false,
+                                    factor: 1.0,
                                 });
-                                hist.trim(self.config.trim_history_minutes);
-                                hist.clone()
+                                hist.trim(config.trim_history_minutes);
+                                let hist = hist.clone();
+                                if config.victim_choice && killer != victim {
+                                    lock.pending.entry(victim.clone()).or_default()
+                                        .push(Pending { killer: killer.clone(), timestamp: envelope.received });
+                                }
+                                hist
                             })
                         };
 
                         if let Some(hist) = teamkill_history {
-                            let badness = hist.badness(&self.config);
-                            trace!("Player {} teamkilled {} with {weapon}. Badness is now at {badness}", killer2.player.name, victim2.player.name);
-                            if badness >= self.config.badness_threshold_kick {
-                                info!("Player {} achieved teamkilling badness {} with history:\n{}",
-                                    killer2.player.name,
-                                    badness,
-                                    hist,
-                                );
-                                let _ = bf4.say(format!("Kicking {} for excessive teamkilling.", killer2.player.name), Visibility::All).await;
-                                let _ = bf4.kick(killer2.player.name, "Teamkilling").await;
+                            trace!("Player {} teamkilled {} with {weapon}. Badness is now at {}", killer2.player.name, victim2.player.name, hist.badness(&config));
+                            if config.victim_choice && killer != victim {
+                                let _ = bf4.say(format!("{} teamkilled you. Type !p to punish or !f to forgive.", killer.name), &victim).await;
+                                let myself = self.clone();
+                                let bf4 = bf4.clone();
+                                let wait = config.auto_forgive_after;
+                                tokio::spawn(async move {
+                                    tokio::time::sleep(wait).await;
+                                    myself.auto_forgive(bf4, victim, envelope.received).await;
+                                });
                             }
+                            self.punish_if_bad(&bf4, &killer, &config).await;
                         }
                     }
                 }
@@ -211,7 +616,7 @@ impl TeamKilling {
                     "/tk badness" => {
                         let x = {
                             let lock = self.inner.lock().unwrap();
-                            lock.histories.get(&player).map(|hist| hist.badness(&self.config))
+                            lock.histories.get(&player).map(|hist| hist.badness(&config))
                         };
                         if let Some(badness) = x {
                             let _ = bf4.say(format!("Your teamkilling badness: {}", badness), player).await;
@@ -269,17 +674,37 @@ impl TeamKilling {
 #[async_trait]
 impl Plugin for TeamKilling {
     const NAME: &'static str = "teamkilling";
-    fn enabled(&self) -> bool { self.config.enabled }
+    type Config = Config;
+    fn enabled(&self) -> bool { self.config.read().enabled }
+
+    fn validate(config: &Config) -> Result<(), ConfigError> {
+        config.validate()
+    }
+
+    async fn reconfigure(self: &Arc<Self>, _bf4: &Arc<Bf4Client>, config: Config) -> Result<(), ConfigError> {
+        *self.config.write() = Arc::new(config);
+        Ok(())
+    }
+
+    async fn persist(self: &Arc<Self>, _bf4: &Arc<Bf4Client>) {
+        let histories = self.inner.lock().unwrap().histories.iter()
+            .map(|(player, hist)| (player.clone(), hist.teamkills.iter().map(SavedHistEntry::save).collect()))
+            .collect();
+        state::save(Self::NAME, &TeamKillingState { histories });
+    }
 
     async fn start(self: &Arc<Self>, bf4: &Arc<Bf4Client>) {
+        self.register_commands();
+
         let self_clone = self.clone();
         tokio::spawn(async move {
             // every 10 minutes, trim teamkilling entries and yeet empty ones.
             loop {
                 tokio::time::sleep(Duration::from_secs(60 * 10)).await;
+                let trim_history_minutes = self_clone.config().trim_history_minutes;
                 let mut lock = self_clone.inner.lock().unwrap();
                 lock.histories.iter_mut()
-                    .for_each(|(_, hist)| hist.trim(self_clone.config.trim_history_minutes));
+                    .for_each(|(_, hist)| hist.trim(trim_history_minutes));
                 lock.histories.retain(|_, hist| !hist.teamkills.is_empty());
             }
         });
@@ -315,14 +740,127 @@ impl Plugin for TeamKilling {
         });
     }
 
-    async fn event(self: Arc<Self>, bf4: Arc<Bf4Client>, event: Event) -> RconResult<()> {
-        self.event(bf4, event).await
+    async fn envelope(self: Arc<Self>, bf4: Arc<Bf4Client>, envelope: Envelope) -> RconResult<()> {
+        self.event(bf4, envelope).await
     }
 }
 
 #[cfg(test)]
 mod test {
+    use ascii::IntoAsciiString;
+    use battlefield_rcon::bf4::Eaid;
+
     use super::*;
+    use crate::testutil::player;
+
+    fn entry(victim: &Player, timestamp: Instant) -> HistEntry {
+        HistEntry {
+            timestamp,
+            weapon: Weapon::Other("M416".into_ascii_string().unwrap()),
+            victim: victim.clone(),
+            punished: false,
+            factor: 1.0,
+        }
+    }
+
+    #[test]
+    fn punishing() {
+        let victim = player("Victim", 1);
+        let mut killed = entry(&victim, Instant::now());
+        killed.punish(&Punish::Kill);
+        assert!(killed.punished);
+        assert_eq!(killed.factor, 1.0);
+
+        let mut badness = entry(&victim, Instant::now());
+        badness.punish(&Punish::Badness(3.0));
+        assert!(badness.punished);
+        assert_eq!(badness.factor, 3.0);
+    }
+
+    #[test]
+    fn pending_per_victim() {
+        let (victim, other) = (player("Victim", 1), player("Other", 2));
+        let (alice, bob) = (player("Alice", 3), player("Bobby", 4));
+        let (t1, t2) = (Instant::now(), Instant::now() + Duration::from_secs(1));
+
+        let mut inner = Inner::default();
+        inner.histories.insert(alice.clone(), PlayerHistory { teamkills: vec![entry(&victim, t1), entry(&other, t1)] });
+        inner.histories.insert(bob.clone(), PlayerHistory { teamkills: vec![entry(&victim, t2)] });
+        inner.pending.insert(victim.clone(), vec![
+            Pending { killer: alice.clone(), timestamp: t1 },
+            Pending { killer: bob.clone(), timestamp: t2 },
+        ]);
+
+        // a stale timestamp finds nothing, and leaves the rest alone.
+        assert!(inner.take_pending(&victim, Some(t1 + Duration::from_secs(5))).is_none());
+        assert!(inner.take_pending(&other, None).is_none());
+
+        // the most recent first, and the older one is still there.
+        let bobs = inner.take_pending(&victim, None).unwrap();
+        assert_eq!(bobs.killer, bob);
+        let hist = inner.punish(&victim, &bobs, &Punish::Badness(2.0)).unwrap();
+        assert!(hist.teamkills[0].punished);
+
+        // auto-forgiving Alice's teamkill only forgives the one on this victim.
+        let alices = inner.take_pending(&victim, Some(t1)).unwrap();
+        assert_eq!(alices.killer, alice);
+        inner.forgive(&victim, &alices);
+        assert_eq!(inner.histories[&alice].teamkills.len(), 1);
+        assert_eq!(inner.histories[&alice].teamkills[0].victim, other);
+        assert!(inner.pending.is_empty());
+
+        inner.forgive(&other, &Pending { killer: alice.clone(), timestamp: t1 });
+        assert!(!inner.histories.contains_key(&alice));
+    }
+
+    #[test]
+    fn punished_counts_more() {
+        let mut cfg: Config = serde_yaml::from_str(include_str!("../configs/teamkilling.yaml")).unwrap();
+        cfg.validate().unwrap();
+        assert!(cfg.victim_choice);
+        cfg.punish = serde_yaml::from_str("badness: 2.0").unwrap();
+
+        let victim = Player { name: "Victim".into_ascii_string().unwrap(), eaid: Eaid::new_invalid() };
+        let mut entry = entry(&victim, Instant::now());
+        let before = entry.badness(&cfg);
+        entry.punish(&cfg.punish);
+        assert!((entry.badness(&cfg) - 2.0 * before).abs() < 0.01);
+        assert!(entry.to_string().ends_with("(punished)"));
+    }
+
+    #[test]
+    fn ladder() {
+        let cfg: Config = serde_yaml::from_str(include_str!("../configs/teamkilling.yaml")).unwrap();
+        assert_eq!(cfg.infraction_decay, Duration::from_secs(30 * 24 * 60 * 60));
+        let steps = (1..=6)
+            .map(|n| punishment::step(&cfg.punishments, n).unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(steps, vec!["warn", "kill", "kick", "tempban 1h", "tempban 1day", "tempban 1day"]);
+    }
+
+    #[test]
+    fn escalation() {
+        let mut cfg: Config = serde_yaml::from_str(include_str!("../configs/teamkilling.yaml")).unwrap();
+        assert_eq!(escalate(&cfg, Some(1)), Some((1, &Punishment::Warn)));
+        assert_eq!(escalate(&cfg, Some(3)), Some((3, &Punishment::Kick)));
+        assert_eq!(escalate(&cfg, Some(9)).map(|(n, p)| (n, p.to_string())), Some((9, "tempban 1day".to_string())));
+        // the database was down.
+        assert_eq!(escalate(&cfg, None), None);
+        cfg.punishments.clear();
+        assert_eq!(escalate(&cfg, Some(1)), None);
+
+        // several teamkills in quick succession only punish once.
+        let (killer, victim) = (player("Killer", 1), player("Victim", 2));
+        let now = Instant::now();
+        let mut inner = Inner::default();
+        inner.histories.insert(killer.clone(), PlayerHistory { teamkills: vec![entry(&victim, now)] });
+        cfg.badness_threshold_kick = 1000.0;
+        assert!(inner.take_if_bad(&killer, &cfg).is_none());
+        assert!(inner.histories.contains_key(&killer));
+        cfg.badness_threshold_kick = 0.0;
+        assert!(inner.take_if_bad(&killer, &cfg).is_some());
+        assert!(inner.take_if_bad(&killer, &cfg).is_none());
+    }
 
     #[test]
     #[ignore]
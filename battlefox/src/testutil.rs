@@ -0,0 +1,31 @@
+//! Made-up players for tests.
+
+use ascii::IntoAsciiString;
+use battlefield_rcon::bf4::{player_info_block::PlayerInfo, Eaid, Player, Squad, Team};
+
+/// A valid EA GUID, different for each `n`.
+pub fn eaid(n: u8) -> Eaid {
+    Eaid::new(&format!("EA_{:032X}", n).into_ascii_string().unwrap()).unwrap()
+}
+
+pub fn player(name: &str, n: u8) -> Player {
+    Player {
+        name: name.into_ascii_string().unwrap(),
+        eaid: eaid(n),
+    }
+}
+
+/// A fresh scoreboard entry without a squad, override the rest with `..player_info(..)`.
+pub fn player_info(name: &str, n: u8, team: Team) -> PlayerInfo {
+    PlayerInfo {
+        player_name: name.into_ascii_string().unwrap(),
+        eaid: eaid(n),
+        squad: Squad::NoSquad,
+        team,
+        kills: 0,
+        deaths: 0,
+        score: 0,
+        rank: 0,
+        ping: 0,
+    }
+}
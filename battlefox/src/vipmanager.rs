@@ -0,0 +1,364 @@
+//! Manages who is VIP, and until when.
+//!
+//! The list lives in `state/vips.yaml` and is synced into the server's reserved slots list, which
+//! is where `Vips` gets VIP status from. When a VIP's time runs out, they are removed from the
+//! reserved slots again. Before that, they get a heads-up in chat whenever they spawn.
+
+use std::{collections::{BTreeMap, HashSet}, sync::Arc, time::Duration};
+
+use ascii::AsciiString;
+use async_trait::async_trait;
+use battlefield_rcon::{bf4::{Bf4Client, Eaid, Event, Player, ReservedSlotsError}, rcon::RconResult};
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+
+use crate::commands::{ArgKind, Command, Commands};
+use crate::roles::Role;
+use crate::vips::Vips;
+use crate::state::{self, Persistent};
+use crate::{ConfigError, Plugin};
+
+const STATE: &str = "vips";
+
+/// How often we look for VIPs whose time ran out.
+const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Config {
+    enabled: bool,
+    /// VIPs whose time runs out within this many days get a warning in chat when they spawn.
+    warn_days: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VipEntry {
+    /// Reserved slots go by soldier name, so this is what ends up in the reserved slots list.
+    pub name: AsciiString,
+    pub eaid: Eaid,
+    /// `None` means forever.
+    pub expires: Option<DateTime<Utc>>,
+}
+
+impl VipEntry {
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        matches!(self.expires, Some(expires) if expires <= now)
+    }
+
+    fn describe(&self) -> String {
+        match self.expires {
+            Some(expires) => format!("{} until {}", self.name, expires.format("%Y-%m-%d %H:%M UTC")),
+            None => format!("{} forever", self.name),
+        }
+    }
+}
+
+/// Contents of `state/vips.yaml`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct VipFile {
+    vips: Vec<VipEntry>,
+}
+
+impl Persistent for VipFile {
+    const VERSION: u32 = 1;
+
+    fn migrate(version: u32, old: serde_yaml::Value) -> Option<Self> {
+        match version {
+            // same layout, just from before state files had versions.
+            0 => serde_yaml::from_value(old).ok(),
+            _ => None,
+        }
+    }
+}
+
+pub struct VipManager {
+    config: RwLock<Config>,
+    vips: Arc<Vips>,
+    commands: Arc<Commands>,
+    list: Mutex<BTreeMap<Eaid, VipEntry>>,
+    /// Who already got the expiry warning since they joined.
+    warned: Mutex<HashSet<Eaid>>,
+}
+
+impl VipManager {
+    pub fn new(vips: Arc<Vips>, commands: Arc<Commands>, config: Config) -> Self {
+        let list = state::load::<VipFile>(STATE)
+            .map(|file| file.vips.into_iter().map(|entry| (entry.eaid, entry)).collect())
+            .unwrap_or_default();
+
+        Self {
+            config: RwLock::new(config),
+            vips,
+            commands,
+            list: Mutex::new(list),
+            warned: Mutex::new(HashSet::new()),
+        }
+    }
+
+    fn save(&self) {
+        let file = VipFile {
+            vips: self.list.lock().values().cloned().collect(),
+        };
+        state::save(STATE, &file);
+    }
+
+    fn find_by_name(&self, name: &str) -> Option<VipEntry> {
+        self.list.lock().values()
+            .find(|entry| entry.name.as_str().eq_ignore_ascii_case(name))
+            .cloned()
+    }
+
+    /// Makes the player VIP for `duration` from now on, or forever.
+    pub async fn grant(&self, bf4: &Bf4Client, player: &Player, duration: Option<Duration>) -> Result<VipEntry, ReservedSlotsError> {
+        let entry = VipEntry {
+            name: player.name.clone(),
+            eaid: player.eaid,
+            expires: duration.map(|duration| Utc::now() + to_chrono(duration)),
+        };
+        // may be a rename, in which case the old name has to go.
+        let old = self.list.lock().insert(player.eaid, entry.clone());
+        if let Some(old) = old {
+            if old.name != entry.name {
+                let _ = bf4.reserved_remove(&old.name).await;
+            }
+        }
+        self.save();
+
+        match bf4.reserved_add(player).await {
+            Ok(()) | Err(ReservedSlotsError::PlayerAlreadyInList) => (),
+            Err(err) => return Err(err),
+        }
+        bf4.reserved_save().await?;
+        self.vips.clear_cache().await;
+        Ok(entry)
+    }
+
+    /// Adds `duration` to the remaining VIP time. Permanent VIPs stay permanent.
+    pub fn extend(&self, name: &str, duration: Duration) -> Option<VipEntry> {
+        let entry = self.find_by_name(name)?;
+        let mut list = self.list.lock();
+        let entry = list.get_mut(&entry.eaid)?;
+        if let Some(expires) = entry.expires {
+            entry.expires = Some(expires.max(Utc::now()) + to_chrono(duration));
+        }
+        let entry = entry.clone();
+        drop(list);
+        self.save();
+        Some(entry)
+    }
+
+    /// Removes the VIP from our list and from the reserved slots.
+    pub async fn revoke(&self, bf4: &Bf4Client, eaid: &Eaid) -> Result<Option<VipEntry>, ReservedSlotsError> {
+        let entry = self.list.lock().remove(eaid);
+        if let Some(entry) = &entry {
+            self.save();
+            match bf4.reserved_remove(&entry.name).await {
+                Ok(()) | Err(ReservedSlotsError::PlayerNotInList) => (),
+                Err(err) => return Err(err),
+            }
+            bf4.reserved_save().await?;
+            self.vips.clear_cache().await;
+        }
+        Ok(entry)
+    }
+
+    /// Makes sure everyone on our list is in the server's reserved slots list.
+    async fn sync(&self, bf4: &Bf4Client) {
+        let now = Utc::now();
+        let entries = self.list.lock().values()
+            .filter(|entry| !entry.is_expired(now))
+            .cloned()
+            .collect_vec();
+        for entry in entries.iter() {
+            let player = Player { name: entry.name.clone(), eaid: entry.eaid };
+            match bf4.reserved_add(&player).await {
+                Ok(()) | Err(ReservedSlotsError::PlayerAlreadyInList) => (),
+                Err(err) => error!("[{}] Failed to add {} to the reserved slots: {:?}", Self::NAME, entry.name, err),
+            }
+        }
+        if let Err(err) = bf4.reserved_save().await {
+            error!("[{}] Failed to save the reserved slots: {:?}", Self::NAME, err);
+        }
+        info!("[{}] Synced {} VIPs into the reserved slots.", Self::NAME, entries.len());
+    }
+
+    async fn expire_loop(&self, bf4: Arc<Bf4Client>) {
+        loop {
+            let now = Utc::now();
+            let expired = self.list.lock().values()
+                .filter(|entry| entry.is_expired(now))
+                .map(|entry| entry.eaid)
+                .collect_vec();
+            for eaid in expired {
+                match self.revoke(&bf4, &eaid).await {
+                    Ok(Some(entry)) => info!("[{}] VIP of {} has expired.", Self::NAME, entry.name),
+                    Ok(None) => (),
+                    Err(err) => error!("[{}] Failed to remove expired VIP {}: {:?}", Self::NAME, eaid, err),
+                }
+            }
+            tokio::time::sleep(EXPIRY_CHECK_INTERVAL).await;
+        }
+    }
+
+    async fn maybe_warn(&self, bf4: &Bf4Client, player: &Player) {
+        let expires = match self.list.lock().get(&player.eaid).and_then(|entry| entry.expires) {
+            Some(expires) => expires,
+            None => return,
+        };
+        let left = expires - Utc::now();
+        if left > chrono::Duration::days(self.config.read().warn_days) {
+            return;
+        }
+        if !self.warned.lock().insert(player.eaid) {
+            return; // already warned.
+        }
+
+        let left = left.to_std().unwrap_or_default();
+        // no need to be more precise than hours.
+        let left = Duration::from_secs(left.as_secs() / 3600 * 3600);
+        let _ = bf4.say_lines(vec![
+            format!("{}: Your VIP status runs out in {}.", player, humantime::format_duration(left)),
+            "Talk to an admin to extend it. Thank you for supporting the server!".to_string(),
+        ], player).await;
+    }
+
+    fn register_commands(self: &Arc<Self>) {
+        let myself = self.clone();
+        self.commands.register(
+            Command::new(Self::NAME, "vip", "Shows until when you are VIP."),
+            move |bf4, inv| {
+                let myself = myself.clone();
+                async move {
+                    let msg = match myself.list.lock().get(&inv.player.eaid) {
+                        Some(entry) => format!("You are VIP: {}", entry.describe()),
+                        None => "You are not on the VIP list.".to_string(),
+                    };
+                    let _ = bf4.say(msg, &*inv.player).await;
+                }
+            });
+
+        let myself = self.clone();
+        self.commands.register(
+            Command::new(Self::NAME, "vip grant", "Makes a player VIP, for the given time or forever.")
+                .arg("player", ArgKind::Player)
+                .opt_arg("duration", ArgKind::Duration)
+                .role(Role::Admin),
+            move |bf4, inv| {
+                let myself = myself.clone();
+                async move {
+                    let target = inv.args.player("player").unwrap(); // required arg.
+                    let msg = match myself.grant(&bf4, target, inv.args.duration("duration")).await {
+                        Ok(entry) => format!("{} is now VIP: {}", target, entry.describe()),
+                        Err(err) => format!("Added {} to the list, but the reserved slots failed: {:?}", target, err),
+                    };
+                    let _ = bf4.say(msg, &*inv.player).await;
+                }
+            });
+
+        let myself = self.clone();
+        self.commands.register(
+            Command::new(Self::NAME, "vip extend", "Adds time to someone's VIP, they don't need to be online.")
+                .arg("name", ArgKind::Word)
+                .arg("duration", ArgKind::Duration)
+                .role(Role::Admin),
+            move |bf4, inv| {
+                let myself = myself.clone();
+                async move {
+                    // both args are required.
+                    let name = inv.args.word("name").unwrap();
+                    let msg = match myself.extend(name, inv.args.duration("duration").unwrap()) {
+                        Some(entry) => format!("Extended VIP: {}", entry.describe()),
+                        None => format!("{} is not on the VIP list.", name),
+                    };
+                    let _ = bf4.say(msg, &*inv.player).await;
+                }
+            });
+
+        let myself = self.clone();
+        self.commands.register(
+            Command::new(Self::NAME, "vip revoke", "Takes away someone's VIP, they don't need to be online.")
+                .arg("name", ArgKind::Word)
+                .role(Role::Admin),
+            move |bf4, inv| {
+                let myself = myself.clone();
+                async move {
+                    let name = inv.args.word("name").unwrap(); // required arg.
+                    let msg = match myself.find_by_name(name) {
+                        Some(entry) => match myself.revoke(&bf4, &entry.eaid).await {
+                            Ok(_) => format!("{} is not VIP anymore.", entry.name),
+                            Err(err) => format!("Removed {} from the list, but the reserved slots failed: {:?}", entry.name, err),
+                        },
+                        None => format!("{} is not on the VIP list.", name),
+                    };
+                    let _ = bf4.say(msg, &*inv.player).await;
+                }
+            });
+
+        let myself = self.clone();
+        self.commands.register(
+            Command::new(Self::NAME, "vip list", "Lists all VIPs managed by BattleFox.")
+                .role(Role::Admin),
+            move |bf4, inv| {
+                let myself = myself.clone();
+                async move {
+                    let lines = myself.list.lock().values()
+                        .sorted_by_key(|entry| entry.expires)
+                        .map(|entry| entry.describe())
+                        .collect_vec();
+                    if lines.is_empty() {
+                        let _ = bf4.say("There are no VIPs on the list.", &*inv.player).await;
+                    } else {
+                        let _ = bf4.say_lines(lines, &*inv.player).await;
+                    }
+                }
+            });
+    }
+}
+
+fn to_chrono(duration: Duration) -> chrono::Duration {
+    chrono::Duration::from_std(duration).unwrap_or_else(|_| chrono::Duration::days(100 * 365))
+}
+
+#[async_trait]
+impl Plugin for VipManager {
+    const NAME: &'static str = "vipmanager";
+    type Config = Config;
+
+    fn enabled(&self) -> bool {
+        self.config.read().enabled
+    }
+
+    async fn reconfigure(self: &Arc<Self>, _bf4: &Arc<Bf4Client>, config: Config) -> Result<(), ConfigError> {
+        *self.config.write() = config;
+        Ok(())
+    }
+
+    async fn start(self: &Arc<Self>, bf4: &Arc<Bf4Client>) {
+        self.register_commands();
+        self.sync(bf4).await;
+
+        let myself = self.clone();
+        let bf4 = bf4.clone();
+        tokio::spawn(async move { myself.expire_loop(bf4).await });
+    }
+
+    async fn event(self: Arc<Self>, bf4: Arc<Bf4Client>, event: Event) -> RconResult<()> {
+        match event {
+            Event::Spawn { player, .. } => self.maybe_warn(&bf4, &player).await,
+            Event::Leave { player, .. } => {
+                self.warned.lock().remove(&player.eaid);
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for VipManager {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VipManager")
+            .field("list", &self.list.lock().len())
+            .finish()
+    }
+}
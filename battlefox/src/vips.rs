@@ -170,10 +170,6 @@ impl Vips {
                 }));
         }
 
-        // :3
-        inner.vips.insert(AsciiString::from_ascii("Kiiyya").unwrap(), Recent::now(MaybeVip::left(YesVip)));
-        inner.vips.insert(AsciiString::from_ascii("PocketWolfy").unwrap(), Recent::now(MaybeVip::left(YesVip)));
-
         let mut vips = inner.vips.iter().filter_map(|(k, v)|
             v.and_then(|g| match g.cases() {
                 Left(_) => format!("{} (yes)", k),
@@ -188,6 +184,7 @@ impl Vips {
 #[async_trait]
 impl Plugin for Vips {
     const NAME: &'static str = "vips";
+    type Config = ();
 
     async fn run(self: Arc<Self>, _bf4: Arc<Bf4Client>) -> RconResult<()> {
         // Disable the default event loop for Vips
@@ -4,9 +4,11 @@ use std::sync::Arc;
 use async_trait::async_trait;
 use battlefield_rcon::{bf4::{Bf4Client, Event, Player, Weapon}, rcon::RconResult};
 use futures::StreamExt;
+use parking_lot::{Mutex, RwLock};
 use serde::{Serialize, Deserialize};
 
-use crate::Plugin;
+use crate::{ConfigError, Plugin};
+use crate::state::{self, Persistent};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
@@ -14,13 +16,29 @@ pub struct Config {
 }
 
 pub struct WeaponEnforcer {
-    config: Config,
+    config: RwLock<Arc<Config>>,
+    /// How often each player used a forbidden weapon this round.
+    offenses: Mutex<HashMap<Player, usize>>,
+}
+
+/// What we remember across restarts, in `state/weaponforcer.yaml`.
+#[derive(Debug, Serialize, Deserialize)]
+struct WeaponEnforcerState {
+    offenses: Vec<(Player, usize)>,
+}
+
+impl Persistent for WeaponEnforcerState {
+    const VERSION: u32 = 1;
 }
 
 impl WeaponEnforcer {
     pub fn new(config: Config) -> Self {
+        let offenses = state::load::<WeaponEnforcerState>(Self::NAME)
+            .map(|state| state.offenses.into_iter().collect())
+            .unwrap_or_default();
         Self {
-            config
+            config: RwLock::new(Arc::new(config)),
+            offenses: Mutex::new(offenses),
         }
     }
 }
@@ -28,14 +46,25 @@ impl WeaponEnforcer {
 #[async_trait]
 impl Plugin for WeaponEnforcer {
     const NAME: &'static str = "weaponforcer";
+    type Config = Config;
 
     fn enabled(&self) -> bool {
-        self.config.enabled
+        self.config.read().enabled
     }
 
-    async fn run(self: Arc<Self>, bf4: Arc<Bf4Client>) -> RconResult<()> {
-        let mut offenses : HashMap<Player, usize> = HashMap::new();
+    async fn reconfigure(self: &Arc<Self>, _bf4: &Arc<Bf4Client>, config: Config) -> Result<(), ConfigError> {
+        *self.config.write() = Arc::new(config);
+        Ok(())
+    }
 
+    async fn persist(self: &Arc<Self>, _bf4: &Arc<Bf4Client>) {
+        let offenses = self.offenses.lock().iter()
+            .map(|(player, &n)| (player.clone(), n))
+            .collect();
+        state::save(Self::NAME, &WeaponEnforcerState { offenses });
+    }
+
+    async fn run(self: Arc<Self>, bf4: Arc<Bf4Client>) -> RconResult<()> {
         let mut stream = bf4.event_stream().await?;
         while let Some(event) = stream.next().await {
             match event {
@@ -45,10 +74,14 @@ impl Plugin for WeaponEnforcer {
                         format!("{}: You have been killed by {} by a forbidden weapon.\n\tThey have been punished for their sins.", victim, killer),
                     ], victim).await;
 
-                    let n = offenses.entry(killer.clone()).or_insert(0);
-                    *n += 1;
+                    let n = {
+                        let mut offenses = self.offenses.lock();
+                        let n = offenses.entry(killer.clone()).or_insert(0);
+                        *n += 1;
+                        *n
+                    };
 
-                    if *n >= 2 {
+                    if n >= 2 {
                         let _ = dbg!(bf4.kick(killer.name.clone(), format!("{} is forbidden on this server!", weapon)).await);
                     } else {
                         let _ = dbg!(bf4.kill(killer.name.clone()).await); // ignore potential fails with let _ = ...
@@ -56,7 +89,7 @@ impl Plugin for WeaponEnforcer {
                     }
                 },
                 Ok(Event::RoundOver { winning_team: _ }) => {
-                    offenses.clear();
+                    self.offenses.lock().clear();
                 }
                 _ => {}
             }
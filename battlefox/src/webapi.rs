@@ -0,0 +1,389 @@
+//! Small HTTP JSON API, so we can look at BattleFox from a web dashboard.
+//!
+//! Everything under `GET /api/...` is read-only, but open to anyone who can reach `bind`, and shows
+//! EA GUIDs and chat. So keep `bind` on localhost, and put a reverse proxy with its own auth in
+//! front if the dashboard lives elsewhere. The `POST` endpoints actually do things on the server,
+//! and need an `Authorization: Bearer <token>` header with the token from `configs/webapi.yaml`.
+//! Without a configured token, they are refused entirely.
+//!
+//! `/api/feed` is a WebSocket which pushes everything from the `Feed` as JSON. Clients pick what
+//! they want with `?types=kill,chat,mapvote` and get the last `?backfill=N` items on connect.
+//! They can change their filter later by sending `{ "types": [...] }`.
+//!
+//! With the `metrics` cargo feature, `/metrics` serves Prometheus metrics, see `crate::metrics`.
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use axum::{
+    extract::{ws::{Message, WebSocket, WebSocketUpgrade}, Query, State},
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use battlefield_rcon::bf4::{ban_list::{Ban, BanTimeout}, server_info::ServerInfo, Bf4Client, Map, Player, Squad, Team, Visibility};
+use itertools::Itertools;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::feed::{Feed, FeedItem, BACKFILL_MAX};
+use crate::mapmanager::{MapManager, PopState};
+use crate::mapvote::{Mapvote, MapvoteStatus};
+use crate::players::Players;
+use crate::supervisor::Supervisor;
+use crate::{ConfigError, Plugin};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Config {
+    enabled: bool,
+    /// Where to listen, e.g. `127.0.0.1:8080`.
+    bind: SocketAddr,
+    /// Needed for all `POST` endpoints. Leave empty to disable them.
+    #[serde(default)]
+    token: String,
+}
+
+#[derive(Debug)]
+pub struct WebApi {
+    config: RwLock<Config>,
+    players: Arc<Players>,
+    mapman: Arc<MapManager>,
+    mapvote: Arc<Mapvote>,
+    supervisor: Arc<Supervisor>,
+    feed: Arc<Feed>,
+}
+
+/// What the handlers get to work with.
+#[derive(Clone)]
+struct ApiState {
+    api: Arc<WebApi>,
+    bf4: Arc<Bf4Client>,
+}
+
+/// An error, as the client sees it: A status code, and `{ "error": "..." }` as body.
+#[derive(Debug)]
+struct ApiError(StatusCode, String);
+
+impl ApiError {
+    fn rcon(err: impl std::fmt::Debug) -> Self {
+        Self(StatusCode::BAD_GATEWAY, format!("RCON error: {:?}", err))
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        #[derive(Serialize)]
+        struct Body {
+            error: String,
+        }
+        (self.0, Json(Body { error: self.1 })).into_response()
+    }
+}
+
+type ApiResult<T> = Result<Json<T>, ApiError>;
+
+#[derive(Debug, Serialize)]
+struct PlayerJson {
+    #[serde(flatten)]
+    player: Player,
+    team: Team,
+    squad: Squad,
+}
+
+#[derive(Debug, Serialize)]
+struct MapmanJson {
+    popstate: PopState,
+    recent_maps: Vec<Map>,
+}
+
+#[derive(Debug, Serialize)]
+struct PluginJson {
+    name: &'static str,
+    state: String,
+    restarts: usize,
+    last_error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Done {
+    ok: bool,
+}
+
+const DONE: Done = Done { ok: true };
+
+/// How many feed items a client gets on connect, unless it asks for something else.
+const DEFAULT_BACKFILL: usize = 50;
+
+#[derive(Debug, Deserialize)]
+struct FeedQuery {
+    /// Comma-separated, e.g. `kill,chat,mapvote`. Everything if missing.
+    types: Option<String>,
+    backfill: Option<usize>,
+}
+
+/// Sent by feed clients to change what they get.
+#[derive(Debug, Deserialize)]
+struct FeedFilter {
+    types: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct KickRequest {
+    player: String,
+    #[serde(default)]
+    reason: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct KillRequest {
+    player: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SayRequest {
+    message: String,
+    /// Only to this player, instead of everyone.
+    player: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BanRequest {
+    player: String,
+    /// E.g. `2h` or `7days`. Permanent if missing.
+    duration: Option<String>,
+    #[serde(default)]
+    reason: String,
+}
+
+impl WebApi {
+    pub fn new(players: Arc<Players>, mapman: Arc<MapManager>, mapvote: Arc<Mapvote>, supervisor: Arc<Supervisor>, feed: Arc<Feed>, config: Config) -> Self {
+        Self {
+            config: RwLock::new(config),
+            players,
+            mapman,
+            mapvote,
+            supervisor,
+            feed,
+        }
+    }
+
+    fn router(self: &Arc<Self>, bf4: Arc<Bf4Client>) -> Router {
+        let router = Router::new();
+        #[cfg(feature = "metrics")]
+        let router = router.route("/metrics", get(get_metrics));
+        router
+            .route("/api/players", get(get_players))
+            .route("/api/server", get(get_server))
+            .route("/api/mapman", get(get_mapman))
+            .route("/api/mapvote", get(get_mapvote))
+            .route("/api/plugins", get(get_plugins))
+            .route("/api/feed", get(get_feed))
+            .route("/api/kick", post(post_kick))
+            .route("/api/kill", post(post_kill))
+            .route("/api/say", post(post_say))
+            .route("/api/ban", post(post_ban))
+            .with_state(ApiState { api: self.clone(), bf4 })
+    }
+
+    fn authorize(&self, headers: &HeaderMap) -> Result<(), ApiError> {
+        let config = self.config.read();
+        if config.token.is_empty() {
+            return Err(ApiError(StatusCode::FORBIDDEN, "No API token configured, so this is disabled.".to_string()));
+        }
+        let given = headers.get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        // constant time, so the token can't be guessed byte by byte from how long we take.
+        let matches = given.is_some_and(|given| bool::from(given.as_bytes().ct_eq(config.token.as_bytes())));
+        if matches {
+            Ok(())
+        } else {
+            Err(ApiError(StatusCode::UNAUTHORIZED, "Missing or wrong API token.".to_string()))
+        }
+    }
+
+    /// Looks up a player currently on the server by their exact name (ignoring case).
+    async fn find_player(&self, bf4: &Bf4Client, name: &str) -> Result<Player, ApiError> {
+        self.players.players(bf4).await.into_keys()
+            .find(|player| player.name.as_str().eq_ignore_ascii_case(name))
+            .ok_or_else(|| ApiError(StatusCode::NOT_FOUND, format!("{} is not on the server.", name)))
+    }
+}
+
+async fn get_players(State(state): State<ApiState>) -> ApiResult<Vec<PlayerJson>> {
+    let players = state.api.players.players(&state.bf4).await.into_values()
+        .map(|p| PlayerJson { player: p.player, team: p.team, squad: p.squad })
+        .sorted_by(|a, b| a.player.name.cmp(&b.player.name))
+        .collect();
+    Ok(Json(players))
+}
+
+async fn get_server(State(state): State<ApiState>) -> ApiResult<ServerInfo> {
+    state.bf4.server_info().await.map(Json).map_err(ApiError::rcon)
+}
+
+async fn get_mapman(State(state): State<ApiState>) -> ApiResult<MapmanJson> {
+    Ok(Json(MapmanJson {
+        popstate: state.api.mapman.popstate().await,
+        recent_maps: state.api.mapman.recent_maps(),
+    }))
+}
+
+async fn get_mapvote(State(state): State<ApiState>) -> ApiResult<MapvoteStatus> {
+    state.api.mapvote.status().await
+        .map(Json)
+        .ok_or_else(|| ApiError(StatusCode::SERVICE_UNAVAILABLE, "The mapvote isn't running yet.".to_string()))
+}
+
+async fn get_plugins(State(state): State<ApiState>) -> ApiResult<Vec<PluginJson>> {
+    let plugins = state.api.supervisor.statuses().into_iter()
+        .map(|(name, status)| PluginJson {
+            name,
+            state: status.state.to_string(),
+            restarts: status.restarts,
+            last_error: status.last_error,
+        })
+        .collect();
+    Ok(Json(plugins))
+}
+
+#[cfg(feature = "metrics")]
+async fn get_metrics(State(state): State<ApiState>) -> String {
+    let api = &state.api;
+    crate::metrics::gather(&state.bf4, &api.players, &api.mapman, &api.mapvote).await
+}
+
+async fn get_feed(State(state): State<ApiState>, Query(query): Query<FeedQuery>, ws: WebSocketUpgrade) -> Response {
+    let feed = state.api.feed.clone();
+    ws.on_upgrade(move |socket| feed_client(feed, socket, query))
+}
+
+async fn feed_client(feed: Arc<Feed>, mut socket: WebSocket, query: FeedQuery) {
+    let mut kinds = query.types
+        .map(|types| types.split(',').map(|kind| kind.trim().to_string()).filter(|kind| !kind.is_empty()).collect_vec())
+        .unwrap_or_default();
+    let (backfill, mut receiver) = feed.subscribe(query.backfill.unwrap_or(DEFAULT_BACKFILL).min(BACKFILL_MAX));
+
+    for item in backfill {
+        if item.matches(&kinds) && send_item(&mut socket, &item).await.is_err() {
+            return;
+        }
+    }
+
+    loop {
+        tokio::select! {
+            item = receiver.recv() => match item {
+                Ok(item) => {
+                    if item.matches(&kinds) && send_item(&mut socket, &item).await.is_err() {
+                        return;
+                    }
+                },
+                Err(RecvError::Lagged(n)) => debug!("[{}] Feed client is too slow, skipped {} items.", WebApi::NAME, n),
+                Err(RecvError::Closed) => return,
+            },
+            msg = socket.recv() => match msg {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str::<FeedFilter>(&text) {
+                    Ok(filter) => kinds = filter.types,
+                    Err(err) => debug!("[{}] Ignoring weird message from feed client: {}", WebApi::NAME, err),
+                },
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => (), // pings get answered by axum already.
+            },
+        }
+    }
+}
+
+async fn send_item(socket: &mut WebSocket, item: &FeedItem) -> Result<(), axum::Error> {
+    // serializing our own plain structs can't really fail.
+    let json = serde_json::to_string(item).unwrap_or_default();
+    socket.send(Message::Text(json)).await
+}
+
+async fn post_kick(State(state): State<ApiState>, headers: HeaderMap, Json(req): Json<KickRequest>) -> ApiResult<Done> {
+    state.api.authorize(&headers)?;
+    let player = state.api.find_player(&state.bf4, &req.player).await?;
+    info!("[{}] Kicking {}: {}", WebApi::NAME, player, req.reason);
+    state.bf4.kick(player.name, req.reason).await.map_err(ApiError::rcon)?;
+    Ok(Json(DONE))
+}
+
+async fn post_kill(State(state): State<ApiState>, headers: HeaderMap, Json(req): Json<KillRequest>) -> ApiResult<Done> {
+    state.api.authorize(&headers)?;
+    let player = state.api.find_player(&state.bf4, &req.player).await?;
+    info!("[{}] Killing {}", WebApi::NAME, player);
+    state.bf4.kill(player.name).await.map_err(ApiError::rcon)?;
+    Ok(Json(DONE))
+}
+
+async fn post_say(State(state): State<ApiState>, headers: HeaderMap, Json(req): Json<SayRequest>) -> ApiResult<Done> {
+    state.api.authorize(&headers)?;
+    let vis = match req.player {
+        Some(name) => Visibility::Player(state.api.find_player(&state.bf4, &name).await?.name),
+        None => Visibility::All,
+    };
+    state.bf4.say(req.message, vis).await.map_err(ApiError::rcon)?;
+    Ok(Json(DONE))
+}
+
+async fn post_ban(State(state): State<ApiState>, headers: HeaderMap, Json(req): Json<BanRequest>) -> ApiResult<Done> {
+    state.api.authorize(&headers)?;
+    let timeout = match req.duration {
+        Some(duration) => {
+            let duration: Duration = humantime::parse_duration(&duration)
+                .map_err(|err| ApiError(StatusCode::BAD_REQUEST, format!("Invalid duration: {}", err)))?;
+            BanTimeout::Time(duration)
+        },
+        None => BanTimeout::Permanent,
+    };
+    let player = state.api.find_player(&state.bf4, &req.player).await?;
+    info!("[{}] Banning {} ({:?}): {}", WebApi::NAME, player, timeout, req.reason);
+    state.bf4.ban_add(Ban::Guid(player.eaid), timeout, Some(req.reason)).await.map_err(ApiError::rcon)?;
+    // the ban list only applies when joining, so get rid of them now.
+    let _ = state.bf4.kick(player.name, "Banned").await;
+    Ok(Json(DONE))
+}
+
+#[async_trait]
+impl Plugin for WebApi {
+    const NAME: &'static str = "webapi";
+    type Config = Config;
+
+    fn enabled(&self) -> bool {
+        self.config.read().enabled
+    }
+
+    fn pausable(&self) -> bool {
+        // the server keeps running anyway, pausing would be misleading.
+        false
+    }
+
+    async fn reconfigure(self: &Arc<Self>, _bf4: &Arc<Bf4Client>, config: Config) -> Result<(), ConfigError> {
+        let mut current = self.config.write();
+        if config.bind != current.bind {
+            return Err(ConfigError::Invalid("Changing the bind address needs a restart of BattleFox".to_string()));
+        }
+        *current = config;
+        Ok(())
+    }
+
+    async fn start(self: &Arc<Self>, bf4: &Arc<Bf4Client>) {
+        let bind = self.config.read().bind;
+        let router = self.router(bf4.clone());
+        tokio::spawn(async move {
+            info!("[{}] Listening on http://{}/api", WebApi::NAME, bind);
+            let server = match axum::Server::try_bind(&bind) {
+                Ok(server) => server,
+                Err(err) => {
+                    error!("[{}] Failed to listen on {}: {}", WebApi::NAME, bind, err);
+                    return;
+                }
+            };
+            if let Err(err) = server.serve(router.into_make_service()).await {
+                error!("[{}] HTTP server died: {}", WebApi::NAME, err);
+            }
+        });
+    }
+}
@@ -0,0 +1,162 @@
+//! Greets players when they join.
+//!
+//! Who joins for the very first time (by EA GUID, remembered in the database) gets a short
+//! summary of the rules. Admins and VIPs get their own greeting, everyone else a welcome back.
+
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use battlefield_rcon::{bf4::{Bf4Client, Event, Player}, rcon::RconResult};
+use battlefox_database::{bfox::seen::SeenPlayer, BfoxContext};
+use parking_lot::{Mutex, RwLock};
+use serde::Deserialize;
+
+use crate::roles::{Role, Roles};
+use crate::{ConfigError, Plugin};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    enabled: bool,
+    /// How long after joining to greet, so that it isn't lost in the loading screen.
+    #[serde(with = "humantime_serde")]
+    delay: Duration,
+    /// Lines for each kind of player. `{name}` and `{joins}` (how often they've been here) are
+    /// filled in.
+    first_time: Vec<String>,
+    /// Shown to first-timers after `first_time`.
+    #[serde(default)]
+    rules: Vec<String>,
+    returning: Vec<String>,
+    vip: Vec<String>,
+    /// For moderators and up.
+    admin: Vec<String>,
+}
+
+pub struct Welcome {
+    config: RwLock<Arc<Config>>,
+    db: BfoxContext,
+    roles: Arc<Roles>,
+    /// Joined, but not greeted yet. Whoever leaves before the delay is over isn't greeted.
+    pending: Mutex<HashSet<Player>>,
+}
+
+impl Welcome {
+    pub fn new(db: BfoxContext, roles: Arc<Roles>, config: Config) -> Self {
+        Self {
+            config: RwLock::new(Arc::new(config)),
+            db,
+            roles,
+            pending: Mutex::new(HashSet::new()),
+        }
+    }
+
+    fn config(&self) -> Arc<Config> {
+        self.config.read().clone()
+    }
+
+    async fn greet(self: Arc<Self>, bf4: Arc<Bf4Client>, player: Player) {
+        // look them up right away, so that a quick rejoin still counts as their first time.
+        let seen = match self.db.player_joined(player.eaid.to_string()).await {
+            Ok(seen) => Some(seen),
+            Err(err) => {
+                warn!("[{}] Couldn't record {} joining, greeting them as returning: {}", Self::NAME, player, err);
+                None
+            }
+        };
+
+        let config = self.config();
+        tokio::time::sleep(config.delay).await;
+        if !self.pending.lock().remove(&player) {
+            return; // left already.
+        }
+
+        let role = self.roles.judge(&player, &bf4).await.role();
+        let lines = lines_for(&config, role, seen.as_ref())
+            .map(|line| line
+                .replace("{name}", player.name.as_str())
+                .replace("{joins}", &seen.as_ref().map_or(0, |seen| seen.joins).to_string()))
+            .collect::<Vec<_>>();
+        if lines.is_empty() {
+            return;
+        }
+        debug!("[{}] Greeting {} ({}, {:?})", Self::NAME, player, role, seen);
+        if let Err(err) = bf4.say_lines(lines, player.clone()).await {
+            warn!("[{}] Couldn't greet {}: {:?}", Self::NAME, player, err);
+        }
+    }
+}
+
+/// Which lines someone gets. `seen` is `None` if we don't know whether they've been here before.
+fn lines_for<'a>(config: &'a Config, role: Role, seen: Option<&SeenPlayer>) -> impl Iterator<Item = &'a String> {
+    let first_time = seen.is_some_and(SeenPlayer::is_first_time);
+    let (lines, rules): (&[String], &[String]) = if role >= Role::Moderator {
+        (&config.admin, &[])
+    } else if first_time {
+        (&config.first_time, &config.rules)
+    } else if role >= Role::Vip {
+        (&config.vip, &[])
+    } else {
+        (&config.returning, &[])
+    };
+    lines.iter().chain(rules.iter())
+}
+
+#[async_trait]
+impl Plugin for Welcome {
+    const NAME: &'static str = "welcome";
+    type Config = Config;
+
+    fn enabled(&self) -> bool {
+        self.config().enabled
+    }
+
+    async fn reconfigure(self: &Arc<Self>, _bf4: &Arc<Bf4Client>, config: Config) -> Result<(), ConfigError> {
+        *self.config.write() = Arc::new(config);
+        Ok(())
+    }
+
+    async fn event(self: Arc<Self>, bf4: Arc<Bf4Client>, event: Event) -> RconResult<()> {
+        match event {
+            Event::Authenticated { player } if self.pending.lock().insert(player.clone()) => {
+                tokio::spawn(self.greet(bf4, player));
+            },
+            Event::Leave { player, .. } => {
+                self.pending.lock().remove(&player);
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for Welcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Welcome")
+            .field("config", &self.config())
+            .field("pending", &*self.pending.lock())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use battlefox_database::DateTime;
+
+    use super::*;
+
+    #[test]
+    fn who_gets_what() {
+        let config: Config = serde_yaml::from_str(include_str!("../configs/welcome.yaml")).unwrap();
+        let first = |role, joins| lines_for(&config, role, Some(&SeenPlayer { first_seen: DateTime::now_utc(), joins }))
+            .next()
+            .cloned();
+
+        assert_eq!(first(Role::Player, 1), config.first_time.first().cloned());
+        assert_eq!(lines_for(&config, Role::Player, Some(&SeenPlayer { first_seen: DateTime::now_utc(), joins: 1 })).count(),
+            config.first_time.len() + config.rules.len());
+        assert_eq!(first(Role::Player, 5), config.returning.first().cloned());
+        assert_eq!(first(Role::Vip, 5), config.vip.first().cloned());
+        assert_eq!(first(Role::Admin, 1), config.admin.first().cloned());
+        assert_eq!(lines_for(&config, Role::Player, None).next(), config.returning.first());
+    }
+}
@@ -0,0 +1,54 @@
+-- Tables BattleFox uses in addition to the AdKats schema.
+
+CREATE TABLE IF NOT EXISTS bfox_players_seen (
+    eaid VARCHAR(35) NOT NULL PRIMARY KEY,
+    first_seen DATETIME NOT NULL,
+    last_seen DATETIME NOT NULL,
+    joins INT UNSIGNED NOT NULL DEFAULT 0
+);
+
+-- One row per visit, from joining until leaving.
+CREATE TABLE IF NOT EXISTS bfox_sessions (
+    id INT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    eaid VARCHAR(35) NOT NULL,
+    name VARCHAR(45) NOT NULL,
+    joined_at DATETIME NOT NULL,
+    left_at DATETIME NOT NULL,
+    team TINYINT UNSIGNED NOT NULL,
+    kills INT UNSIGNED NOT NULL,
+    deaths INT UNSIGNED NOT NULL,
+    score INT NOT NULL,
+    headshots INT UNSIGNED NOT NULL,
+    INDEX (eaid)
+);
+
+-- Kills per weapon within a session.
+CREATE TABLE IF NOT EXISTS bfox_session_weapons (
+    session_id INT UNSIGNED NOT NULL,
+    weapon VARCHAR(64) NOT NULL,
+    kills INT UNSIGNED NOT NULL,
+    PRIMARY KEY (session_id, weapon),
+    FOREIGN KEY (session_id) REFERENCES bfox_sessions (id) ON DELETE CASCADE
+);
+
+-- Punishments handed out, so that repeat offenders escalate across sessions.
+CREATE TABLE IF NOT EXISTS bfox_infractions (
+    id INT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    eaid VARCHAR(35) NOT NULL,
+    -- What for, e.g. `teamkilling`.
+    kind VARCHAR(32) NOT NULL,
+    punishment VARCHAR(64) NOT NULL,
+    created_at DATETIME NOT NULL,
+    INDEX (eaid, kind, created_at)
+);
+
+-- Mutes from `!mute`. Round mutes are deleted again at the end of the round.
+CREATE TABLE IF NOT EXISTS bfox_muted_players (
+    eaid VARCHAR(35) NOT NULL PRIMARY KEY,
+    -- 0 disabled, 1 round, 2 days (until end_date), 3 permanent.
+    `type` INT NOT NULL,
+    end_date DATETIME NULL,
+    -- How often they were kicked for talking anyway.
+    kicks INT NULL,
+    reason VARCHAR(255) NULL
+);
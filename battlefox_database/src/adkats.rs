@@ -1,3 +1,4 @@
 pub mod players;
 pub mod mutes;
-pub mod bans;
\ No newline at end of file
+pub mod bans;
+pub mod chatlog;
@@ -0,0 +1,75 @@
+//! The chat log in `tbl_chatlog`, which AdKats and the stats logger share.
+
+use std::time::Duration;
+
+use sqlx::types::time::{PrimitiveDateTime, UtcOffset};
+
+use crate::{BfoxContext, DateTime};
+
+/// `logSubset`, who could see a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatSubset {
+    Global,
+    Team,
+    Squad,
+}
+
+impl ChatSubset {
+    fn as_str(self) -> &'static str {
+        match self {
+            ChatSubset::Global => "Global",
+            ChatSubset::Team => "Team",
+            ChatSubset::Squad => "Squad",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ChatLogEntry {
+    pub time: DateTime,
+    pub subset: ChatSubset,
+    pub soldier_name: String,
+    /// Used to find `logPlayerID` in `tbl_playerdata`. `None` for the server itself.
+    pub eaid: Option<String>,
+    pub message: String,
+}
+
+/// Rows per `INSERT`, to stay well below MySQL's packet size.
+const ROWS_PER_INSERT: usize = 100;
+
+impl BfoxContext {
+    /// Inserts all entries, several per query. Either all of them make it, or none.
+    pub async fn insert_chat_log(&self, server_id: u16, entries: &[ChatLogEntry]) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        for chunk in entries.chunks(ROWS_PER_INSERT) {
+            let row = "(?, ?, ?, (SELECT PlayerID FROM tbl_playerdata WHERE EAGUID = ? LIMIT 1), ?, ?)";
+            let sql = format!(
+                "INSERT INTO tbl_chatlog (logDate, ServerID, logSubset, logPlayerID, logSoldierName, logMessage) VALUES {};",
+                vec![row; chunk.len()].join(", "),
+            );
+            let mut query = sqlx::query(&sql);
+            for entry in chunk {
+                let time = entry.time.to_offset(UtcOffset::UTC);
+                query = query
+                    .bind(PrimitiveDateTime::new(time.date(), time.time()))
+                    .bind(server_id)
+                    .bind(entry.subset.as_str())
+                    .bind(entry.eaid.as_deref())
+                    .bind(&entry.soldier_name)
+                    .bind(&entry.message);
+            }
+            query.execute(&mut tx).await?;
+        }
+        tx.commit().await
+    }
+
+    /// Deletes this server's messages older than `older_than`, and returns how many.
+    pub async fn prune_chat_log(&self, server_id: u16, older_than: Duration) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM tbl_chatlog WHERE ServerID = ? AND logDate < UTC_TIMESTAMP() - INTERVAL ? SECOND;")
+            .bind(server_id)
+            .bind(older_than.as_secs())
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
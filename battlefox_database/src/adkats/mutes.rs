@@ -1,3 +1,7 @@
+//! Mutes handed out with `!mute`, in `bfox_muted_players`.
+
+use sqlx::types::time::{PrimitiveDateTime, UtcOffset};
+
 use crate::{BfoxContext, DateTime};
 
 #[derive(Debug)]
@@ -9,47 +13,74 @@ pub struct BfoxMutedPlayer {
     pub reason: Option<String>,
 }
 
+type Row = (String, i32, Option<PrimitiveDateTime>, Option<i32>, Option<String>);
+
+impl BfoxMutedPlayer {
+    fn from_row((eaid, type_, end_date, kicks, reason): Row) -> Self {
+        Self {
+            eaid,
+            type_,
+            end_date: end_date.map(PrimitiveDateTime::assume_utc),
+            kicks,
+            reason,
+        }
+    }
+}
+
+fn primitive(time: DateTime) -> PrimitiveDateTime {
+    let time = time.to_offset(UtcOffset::UTC);
+    PrimitiveDateTime::new(time.date(), time.time())
+}
+
 impl BfoxContext {
+    /// All mutes in effect: not disabled (type 0), and day mutes (type 2) which haven't ended yet.
     pub async fn get_muted_players(&self) -> Result<Vec<BfoxMutedPlayer>, sqlx::Error> {
-        todo!()
-        // use schema::bfox_muted_players::dsl::{type_, end_date, bfox_muted_players};
-        // bfox_muted_players
-        //     .filter(type_.eq(MuteType::Days as i32).and(end_date.gt(Some(Utc::now().naive_utc().date()))))
-        //     .or_filter(type_.ne(MuteType::Days as i32))
-        //     .filter(type_.ne(MuteType::Disabled as i32))
-        //     .load(conn)
+        let rows: Vec<Row> = sqlx::query_as(
+            "SELECT eaid, `type`, end_date, kicks, reason FROM bfox_muted_players
+            WHERE `type` != 0 AND (`type` != 2 OR end_date > UTC_TIMESTAMP());"
+        ).fetch_all(&self.pool).await?;
+        Ok(rows.into_iter().map(BfoxMutedPlayer::from_row).collect())
     }
 
-    pub async fn get_muted_player(&self, _id: impl AsRef<str>) -> Result<BfoxMutedPlayer, sqlx::Error> {
-        todo!()
-    //     use schema::bfox_muted_players::dsl::{eaid, bfox_muted_players};
-    //     bfox_muted_players
-    //         .filter(eaid.eq(id))
-    //         .first(conn)
+    pub async fn get_muted_player(&self, id: impl AsRef<str>) -> Result<Option<BfoxMutedPlayer>, sqlx::Error> {
+        let row: Option<Row> = sqlx::query_as(
+            "SELECT eaid, `type`, end_date, kicks, reason FROM bfox_muted_players WHERE eaid = ?;"
+        ).bind(id.as_ref()).fetch_optional(&self.pool).await?;
+        Ok(row.map(BfoxMutedPlayer::from_row))
     }
 
-    pub async fn replace_into_muted_player(&self, _player: &BfoxMutedPlayer) -> Result<(), sqlx::Error> {
-        todo!()
-    //     use schema::bfox_muted_players::dsl::*;
-    //     // let primary_key = player.eaid.clone();
-    //     // let exists = bfox_muted_players
-    //     //     .filter(eaid.eq(primary_key))
-    //     //     .count()
-    //     //     .first::<i64>(conn)
-    //     //     .unwrap();
-    //     // println!("Count: {}", exists);
-    //     replace_into(bfox_muted_players)
-    //         .values(player)
-    //         .execute(conn)?;
-    //     Ok(())
+    /// Adds the mute, or replaces the one the player already had.
+    pub async fn replace_into_muted_player(&self, player: &BfoxMutedPlayer) -> Result<(), sqlx::Error> {
+        sqlx::query("REPLACE INTO bfox_muted_players (eaid, `type`, end_date, kicks, reason) VALUES (?, ?, ?, ?, ?);")
+            .bind(&player.eaid)
+            .bind(player.type_)
+            .bind(player.end_date.map(primitive))
+            .bind(player.kicks)
+            .bind(player.reason.as_deref())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
     }
 
-    pub async fn delete_muted_players(&self, _ids: &[impl AsRef<str>]) -> Result<(), sqlx::Error> {
-        todo!()
-    //     use schema::bfox_muted_players::dsl::*;
-    //     delete(bfox_muted_players)
-    //         .filter(eaid.eq(id))
-    //         .execute(conn)?;
-    //     Ok(())
+    /// Counts a kick for talking while muted. Does nothing if there's no such mute.
+    pub async fn add_muted_player_kick(&self, id: impl AsRef<str>) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE bfox_muted_players SET kicks = COALESCE(kicks, 0) + 1 WHERE eaid = ?;")
+            .bind(id.as_ref())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
     }
-}
\ No newline at end of file
+
+    pub async fn delete_muted_players(&self, ids: &[impl AsRef<str>]) -> Result<(), sqlx::Error> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let sql = format!("DELETE FROM bfox_muted_players WHERE eaid IN ({});", vec!["?"; ids.len()].join(", "));
+        let mut query = sqlx::query(&sql);
+        for id in ids {
+            query = query.bind(id.as_ref());
+        }
+        query.execute(&self.pool).await?;
+        Ok(())
+    }
+}
@@ -36,4 +36,14 @@ impl BfoxContext {
 
         Ok(res)
     }
-}
\ No newline at end of file
+
+    /// The clan tag AdKats last saw the player with this GUID wear. `None` if they have none,
+    /// or AdKats doesn't know them.
+    pub async fn get_clan_tag(&self, guid: impl AsRef<str>) -> Result<Option<String>, sqlx::Error> {
+        let tag: Option<(Option<String>,)> = sqlx::query_as("SELECT ClanTag FROM tbl_playerdata WHERE EAGUID = ?;")
+            .bind(guid.as_ref())
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(tag.and_then(|(tag,)| tag).filter(|tag| !tag.is_empty()))
+    }
+}
@@ -0,0 +1,5 @@
+//! BattleFox's own tables, next to the AdKats ones. See `bfox_schema.sql` for how to create them.
+
+pub mod infractions;
+pub mod seen;
+pub mod sessions;
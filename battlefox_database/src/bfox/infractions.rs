@@ -0,0 +1,32 @@
+//! Punishments a player received, in `bfox_infractions`. Plugins look at recent ones to escalate.
+
+use std::time::Duration;
+
+use crate::BfoxContext;
+
+impl BfoxContext {
+    /// Records that the player with this GUID got `punishment` (e.g. `kick`) just now, for `kind`
+    /// of offence (e.g. `teamkilling`).
+    pub async fn add_infraction(&self, guid: impl AsRef<str>, kind: &str, punishment: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("INSERT INTO bfox_infractions (eaid, kind, punishment, created_at) VALUES (?, ?, ?, UTC_TIMESTAMP());")
+            .bind(guid.as_ref())
+            .bind(kind)
+            .bind(punishment)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// How many infractions of this `kind` the player got within the last `within`.
+    pub async fn count_infractions(&self, guid: impl AsRef<str>, kind: &str, within: Duration) -> Result<u32, sqlx::Error> {
+        let (count,): (i64,) = sqlx::query_as(
+            "SELECT COUNT(*) FROM bfox_infractions WHERE eaid = ? AND kind = ? AND created_at > UTC_TIMESTAMP() - INTERVAL ? SECOND;"
+        )
+            .bind(guid.as_ref())
+            .bind(kind)
+            .bind(within.as_secs())
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(count as u32)
+    }
+}
@@ -0,0 +1,38 @@
+//! Which players have been on the server before, in `bfox_players_seen`.
+
+use sqlx::types::time::PrimitiveDateTime;
+
+use crate::{BfoxContext, DateTime};
+
+#[derive(Debug, Clone)]
+pub struct SeenPlayer {
+    pub first_seen: DateTime,
+    /// How often they joined, including this time.
+    pub joins: u32,
+}
+
+impl SeenPlayer {
+    pub fn is_first_time(&self) -> bool {
+        self.joins <= 1
+    }
+}
+
+impl BfoxContext {
+    /// Records that the player with this GUID joined just now, and returns what we know about them.
+    pub async fn player_joined(&self, guid: impl AsRef<str>) -> Result<SeenPlayer, sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO bfox_players_seen (eaid, first_seen, last_seen, joins)
+            VALUES (?, UTC_TIMESTAMP(), UTC_TIMESTAMP(), 1)
+            ON DUPLICATE KEY UPDATE last_seen = UTC_TIMESTAMP(), joins = joins + 1;"
+        ).bind(guid.as_ref()).execute(&self.pool).await?;
+
+        let (first_seen, joins): (PrimitiveDateTime, u32) = sqlx::query_as(
+            "SELECT first_seen, joins FROM bfox_players_seen WHERE eaid = ?;"
+        ).bind(guid.as_ref()).fetch_one(&self.pool).await?;
+
+        Ok(SeenPlayer {
+            first_seen: first_seen.assume_utc(),
+            joins,
+        })
+    }
+}
@@ -0,0 +1,99 @@
+//! Player sessions (from joining until leaving) with their stats, in `bfox_sessions` and
+//! `bfox_session_weapons`.
+
+use std::time::Duration;
+
+use crate::BfoxContext;
+
+/// A session which ended.
+#[derive(Debug, Clone)]
+pub struct NewSession {
+    pub eaid: String,
+    pub name: String,
+    /// How long they were on the server.
+    pub duration: Duration,
+    /// How long ago the session ended, usually zero.
+    pub ended_ago: Duration,
+    /// 1 or 2, of the last round they played.
+    pub team: u8,
+    pub kills: u32,
+    pub deaths: u32,
+    pub score: i32,
+    pub headshots: u32,
+    /// Weapon name -> kills.
+    pub weapons: Vec<(String, u32)>,
+}
+
+/// Everything a player did on our server, summed up over all their sessions.
+#[derive(Debug, Clone, Default)]
+pub struct LifetimeStats {
+    pub sessions: u64,
+    pub playtime: Duration,
+    pub kills: u64,
+    pub deaths: u64,
+    pub score: i64,
+    pub headshots: u64,
+    /// The weapon with the most kills, and how many.
+    pub favourite_weapon: Option<(String, u64)>,
+}
+
+impl BfoxContext {
+    pub async fn insert_session(&self, session: &NewSession) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let id = sqlx::query(
+            "INSERT INTO bfox_sessions (eaid, name, joined_at, left_at, team, kills, deaths, score, headshots)
+            VALUES (?, ?, UTC_TIMESTAMP() - INTERVAL ? SECOND, UTC_TIMESTAMP() - INTERVAL ? SECOND, ?, ?, ?, ?, ?);"
+        )
+            .bind(&session.eaid)
+            .bind(&session.name)
+            .bind((session.duration + session.ended_ago).as_secs())
+            .bind(session.ended_ago.as_secs())
+            .bind(session.team)
+            .bind(session.kills)
+            .bind(session.deaths)
+            .bind(session.score)
+            .bind(session.headshots)
+            .execute(&mut tx).await?
+            .last_insert_id();
+
+        for (weapon, kills) in &session.weapons {
+            sqlx::query("INSERT INTO bfox_session_weapons (session_id, weapon, kills) VALUES (?, ?, ?);")
+                .bind(id)
+                .bind(weapon)
+                .bind(kills)
+                .execute(&mut tx).await?;
+        }
+        tx.commit().await
+    }
+
+    /// All zeroes if we've never seen them finish a session.
+    pub async fn lifetime_stats(&self, guid: impl AsRef<str>) -> Result<LifetimeStats, sqlx::Error> {
+        // SUM() would be a DECIMAL otherwise.
+        let (sessions, seconds, kills, deaths, score, headshots): (i64, i64, i64, i64, i64, i64) = sqlx::query_as(
+            "SELECT COUNT(*),
+                CAST(COALESCE(SUM(TIMESTAMPDIFF(SECOND, joined_at, left_at)), 0) AS SIGNED),
+                CAST(COALESCE(SUM(kills), 0) AS SIGNED),
+                CAST(COALESCE(SUM(deaths), 0) AS SIGNED),
+                CAST(COALESCE(SUM(score), 0) AS SIGNED),
+                CAST(COALESCE(SUM(headshots), 0) AS SIGNED)
+            FROM bfox_sessions WHERE eaid = ?;"
+        ).bind(guid.as_ref()).fetch_one(&self.pool).await?;
+
+        let favourite_weapon: Option<(String, i64)> = sqlx::query_as(
+            "SELECT w.weapon, CAST(SUM(w.kills) AS SIGNED) AS total
+            FROM bfox_session_weapons w INNER JOIN bfox_sessions s ON s.id = w.session_id
+            WHERE s.eaid = ?
+            GROUP BY w.weapon ORDER BY total DESC LIMIT 1;"
+        ).bind(guid.as_ref()).fetch_optional(&self.pool).await?;
+
+        Ok(LifetimeStats {
+            sessions: sessions as u64,
+            playtime: Duration::from_secs(seconds.max(0) as u64),
+            kills: kills as u64,
+            deaths: deaths as u64,
+            score,
+            headshots: headshots as u64,
+            favourite_weapon: favourite_weapon.map(|(weapon, kills)| (weapon, kills as u64)),
+        })
+    }
+}
@@ -12,10 +12,12 @@
 
 use std::env;
 
+use sqlx::mysql::MySqlDatabaseError;
 use sqlx::MySqlPool;
 use sqlx::types::time::OffsetDateTime;
 
 pub mod adkats;
+pub mod bfox;
 
 pub type DateTime = OffsetDateTime;
 
@@ -49,6 +51,20 @@ impl BfoxContext {
     }
 }
 
+/// Whether trying the same query again later may work, because the database was unreachable or
+/// busy. Anything else (bad data, schema mismatch) will just fail again.
+pub fn is_transient(err: &sqlx::Error) -> bool {
+    match err {
+        sqlx::Error::Io(_) | sqlx::Error::Tls(_) | sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed | sqlx::Error::WorkerCrashed => true,
+        sqlx::Error::Database(err) => match err.try_downcast_ref::<MySqlDatabaseError>() {
+            // too many connections, lock wait timeout, deadlock.
+            Some(err) => matches!(err.number(), 1040 | 1205 | 1213),
+            None => false,
+        },
+        _ => false,
+    }
+}
+
 #[cfg(test)]
 mod test {
     use anyhow::Context;